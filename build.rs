@@ -0,0 +1,32 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::{env, fs};
+
+// Every file in `src/commands/` becomes a module of `commands` so adding a command only needs a new file
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let commands_dir = Path::new(&manifest_dir).join("src").join("commands");
+    println!("cargo:rerun-if-changed={}", commands_dir.display());
+
+    let mut files: Vec<_> = fs::read_dir(&commands_dir)
+        .expect("Cannot read the commands directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|x| x == "rs"))
+        .collect();
+    // Sorted so the generated file (and with it the build) doesn't depend on the file system
+    files.sort();
+
+    let mut modules = String::new();
+    for path in files {
+        let name = path.file_stem().unwrap().to_str().unwrap();
+        writeln!(
+            modules,
+            "#[path = {:?}]\npub mod {name};",
+            path.display().to_string()
+        )
+        .unwrap();
+    }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("command_modules.rs"), modules).unwrap();
+}
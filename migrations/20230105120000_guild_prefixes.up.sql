@@ -0,0 +1,6 @@
+CREATE TABLE guild_prefixes (
+    guild_id BIGINT NOT NULL,
+    prefix TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (guild_id, prefix)
+);
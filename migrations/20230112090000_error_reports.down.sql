@@ -0,0 +1 @@
+DROP TABLE error_reports;
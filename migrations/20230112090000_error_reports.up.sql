@@ -0,0 +1,10 @@
+CREATE TABLE error_reports (
+    id TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    command TEXT,
+    guild_id BIGINT,
+    channel_id BIGINT,
+    user_id BIGINT,
+    message TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,2 @@
+DROP TABLE cases;
+DROP TABLE guild_case_counters;
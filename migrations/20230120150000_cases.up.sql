@@ -0,0 +1,19 @@
+-- Hands out case numbers per guild
+CREATE TABLE guild_case_counters (
+    guild_id BIGINT PRIMARY KEY,
+    last_case_number INT NOT NULL
+);
+
+CREATE TABLE cases (
+    guild_id BIGINT NOT NULL,
+    case_number INT NOT NULL,
+    action TEXT NOT NULL,
+    moderator_id BIGINT NOT NULL,
+    target_id BIGINT,
+    reason TEXT,
+    duration_seconds BIGINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (guild_id, case_number)
+);
+
+CREATE INDEX cases_target_idx ON cases (guild_id, target_id);
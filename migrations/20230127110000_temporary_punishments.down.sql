@@ -0,0 +1,2 @@
+DROP TABLE scheduled_actions;
+DROP TABLE guild_settings;
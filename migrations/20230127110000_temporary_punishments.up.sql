@@ -0,0 +1,21 @@
+CREATE TABLE guild_settings (
+    guild_id BIGINT PRIMARY KEY,
+    mute_role_id BIGINT
+);
+
+-- Actions that undo temporary punishments once they run out
+CREATE TABLE scheduled_actions (
+    id BIGSERIAL PRIMARY KEY,
+    guild_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    action TEXT NOT NULL,
+    role_id BIGINT,
+    case_number INT,
+    execute_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    completed_at TIMESTAMPTZ,
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT
+);
+
+CREATE INDEX scheduled_actions_pending_idx ON scheduled_actions (execute_at) WHERE completed_at IS NULL;
@@ -0,0 +1,31 @@
+CREATE TABLE scheduled_actions (
+    id BIGSERIAL PRIMARY KEY,
+    guild_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    action TEXT NOT NULL,
+    role_id BIGINT,
+    case_number INT,
+    execute_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    completed_at TIMESTAMPTZ,
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT
+);
+
+CREATE INDEX scheduled_actions_pending_idx ON scheduled_actions (execute_at) WHERE completed_at IS NULL;
+
+INSERT INTO scheduled_actions (guild_id, user_id, action, role_id, case_number, execute_at, created_at, attempts, last_error)
+SELECT
+    (payload->>'guild_id')::bigint,
+    (payload->>'user_id')::bigint,
+    CASE WHEN payload->'action' = to_jsonb('unban'::text) THEN 'unban' ELSE 'remove_role' END,
+    (payload->'action'->>'remove_role')::bigint,
+    (payload->>'case_number')::int,
+    run_at,
+    created_at,
+    attempts,
+    last_error
+FROM jobs
+WHERE kind = 'expire_punishment' AND status = 'pending';
+
+DROP TABLE jobs;
@@ -0,0 +1,44 @@
+-- Generic queue for work that has to happen later, see `src/jobs.rs`
+CREATE TABLE jobs (
+    id BIGSERIAL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    -- Jobs with the same kind and key replace each other while pending
+    key TEXT,
+    payload JSONB NOT NULL,
+    -- pending, running, done or dead
+    status TEXT NOT NULL DEFAULT 'pending',
+    run_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    attempts INT NOT NULL DEFAULT 0,
+    max_attempts INT NOT NULL,
+    last_error TEXT,
+    locked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX jobs_pending_idx ON jobs (run_at) WHERE status = 'pending';
+CREATE INDEX jobs_key_idx ON jobs (kind, key) WHERE key IS NOT NULL;
+
+-- Temporary punishments that have not run out yet move over to the queue
+INSERT INTO jobs (kind, key, payload, run_at, attempts, max_attempts, last_error, created_at)
+SELECT
+    'expire_punishment',
+    guild_id || ':' || user_id || ':' || action,
+    jsonb_build_object(
+        'guild_id', guild_id::text,
+        'user_id', user_id::text,
+        'action', CASE action
+            WHEN 'unban' THEN to_jsonb('unban'::text)
+            ELSE jsonb_build_object('remove_role', role_id::text)
+        END,
+        'case_number', case_number
+    ),
+    execute_at,
+    attempts,
+    5,
+    last_error,
+    created_at
+FROM scheduled_actions
+WHERE completed_at IS NULL;
+
+DROP TABLE scheduled_actions;
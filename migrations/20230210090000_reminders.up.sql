@@ -0,0 +1,21 @@
+CREATE TABLE user_settings (
+    user_id BIGINT PRIMARY KEY,
+    -- IANA name like Europe/Berlin
+    timezone TEXT
+);
+
+CREATE TABLE reminders (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    guild_id BIGINT,
+    -- Delivered by DM when there is no channel
+    channel_id BIGINT,
+    message TEXT NOT NULL,
+    remind_at TIMESTAMPTZ NOT NULL,
+    -- Set for reminders that repeat
+    interval_seconds BIGINT,
+    timezone TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX reminders_user_idx ON reminders (user_id);
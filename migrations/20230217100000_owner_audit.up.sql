@@ -0,0 +1,11 @@
+-- Every use of an owner command, see `audit.rs`
+CREATE TABLE owner_audit (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    command TEXT NOT NULL,
+    -- The command as it was typed, with its arguments
+    invocation TEXT NOT NULL,
+    guild_id BIGINT,
+    channel_id BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
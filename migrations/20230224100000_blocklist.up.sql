@@ -0,0 +1,12 @@
+-- Users and guilds that can't use the bot, see `blocklist.rs`
+CREATE TABLE blocklist (
+    -- 'user' or 'guild'
+    kind TEXT NOT NULL,
+    id BIGINT NOT NULL,
+    reason TEXT,
+    -- Blocked for good when there is no expiry
+    expires_at TIMESTAMPTZ,
+    added_by BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (kind, id)
+);
@@ -0,0 +1,9 @@
+-- Whether the bot is in maintenance mode, see `maintenance.rs`. There is only ever one row
+CREATE TABLE maintenance (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    enabled BOOLEAN NOT NULL,
+    -- The notice from `MAINTENANCE_MESSAGE` is used when there is none
+    message TEXT,
+    updated_by BIGINT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,15 @@
+-- Commands and categories a guild turned off or restricted, see `overrides.rs`
+CREATE TABLE command_overrides (
+    guild_id BIGINT NOT NULL,
+    -- 'command' or 'category'
+    kind TEXT NOT NULL,
+    -- Qualified name of the command (like `prefix add`) or name of the category
+    name TEXT NOT NULL,
+    disabled BOOLEAN NOT NULL DEFAULT FALSE,
+    -- Only usable in these channels, anywhere when empty
+    channel_ids BIGINT[] NOT NULL DEFAULT '{}',
+    -- Only usable by members with one of these roles, by everyone when empty
+    role_ids BIGINT[] NOT NULL DEFAULT '{}',
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (guild_id, kind, name)
+);
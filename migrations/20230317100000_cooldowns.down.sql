@@ -0,0 +1,2 @@
+DROP TABLE guild_cooldowns;
+DROP TABLE cooldowns;
@@ -0,0 +1,20 @@
+-- Cooldowns that did not run out yet, see `cooldowns.rs`
+CREATE TABLE cooldowns (
+    command TEXT NOT NULL,
+    -- 'global', 'user', 'member', 'channel' or 'guild'
+    bucket TEXT NOT NULL,
+    -- Who or where it applies to, like the user id for the 'user' bucket
+    key TEXT NOT NULL,
+    ends_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (command, bucket, key)
+);
+
+-- Cooldowns a guild changed from the ones the commands come with
+CREATE TABLE guild_cooldowns (
+    guild_id BIGINT NOT NULL,
+    command TEXT NOT NULL,
+    bucket TEXT NOT NULL,
+    -- 0 means there is no cooldown in this bucket
+    seconds BIGINT NOT NULL,
+    PRIMARY KEY (guild_id, command, bucket)
+);
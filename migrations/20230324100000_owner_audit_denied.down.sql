@@ -0,0 +1 @@
+ALTER TABLE owner_audit DROP COLUMN allowed;
@@ -0,0 +1,2 @@
+-- Users who aren't owners trying to use owner commands are recorded too
+ALTER TABLE owner_audit ADD COLUMN allowed BOOLEAN NOT NULL DEFAULT true;
@@ -0,0 +1,5 @@
+-- Repeating reminders come back at this local time of day, even after one of them was moved by
+-- daylight saving time
+ALTER TABLE reminders ADD COLUMN time_of_day TIME;
+UPDATE reminders SET time_of_day = (remind_at AT TIME ZONE timezone)::time
+WHERE interval_seconds IS NOT NULL;
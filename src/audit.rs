@@ -0,0 +1,26 @@
+use crate::{Context, Error};
+
+/// Whether the command (or one of its parents) is only for owners, those are recorded in the
+/// `owner_audit` table
+pub fn is_audited(ctx: Context<'_>) -> bool {
+    ctx.command().owners_only || ctx.parent_commands().iter().any(|x| x.owners_only)
+}
+
+/// Records who used an owner command where, done in `pre_command` so it happens before the
+/// command gets to do anything. Users who aren't owners are recorded with `allowed` set to false
+/// when they try.
+pub async fn record(ctx: Context<'_>, allowed: bool) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO owner_audit (user_id, command, invocation, guild_id, channel_id, allowed)
+        VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(ctx.author().id.0 as i64)
+    .bind(&ctx.command().qualified_name)
+    .bind(ctx.invocation_string())
+    .bind(ctx.guild_id().map(|x| x.0 as i64))
+    .bind(ctx.channel_id().0 as i64)
+    .bind(allowed)
+    .execute(&ctx.data().db)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::Error;
+
+/// What is blocked, stored as text in the `blocklist` table
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, poise::ChoiceParameter)]
+pub enum Kind {
+    User,
+    Guild,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::User => "user",
+            Kind::Guild => "guild",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "user" => Some(Kind::User),
+            "guild" => Some(Kind::Guild),
+            _ => None,
+        }
+    }
+}
+
+/// A row of the `blocklist` table
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub kind: Kind,
+    pub id: u64,
+    pub reason: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub added_by: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Entry {
+    fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_none_or(|x| x > now)
+    }
+}
+
+type Row = (
+    String,
+    i64,
+    Option<String>,
+    Option<DateTime<Utc>>,
+    i64,
+    DateTime<Utc>,
+);
+
+// The whole blocklist is kept in memory since it is checked before every command, the database
+// is only there so it survives restarts
+pub struct Blocklist {
+    entries: RwLock<HashMap<(Kind, u64), Entry>>,
+}
+
+impl Blocklist {
+    /// Loads every entry that did not expire yet, called once on startup
+    pub async fn load(db: &PgPool) -> Result<Self, Error> {
+        // Expired entries would never be looked at again
+        sqlx::query("DELETE FROM blocklist WHERE expires_at <= now()")
+            .execute(db)
+            .await?;
+
+        let rows: Vec<Row> = sqlx::query_as(
+            "SELECT kind, id, reason, expires_at, added_by, created_at FROM blocklist",
+        )
+        .fetch_all(db)
+        .await?;
+
+        let entries = rows
+            .into_iter()
+            .filter_map(|(kind, id, reason, expires_at, added_by, created_at)| {
+                let entry = Entry {
+                    kind: Kind::parse(&kind)?,
+                    id: id as u64,
+                    reason,
+                    expires_at,
+                    added_by: added_by as u64,
+                    created_at,
+                };
+                Some(((entry.kind, entry.id), entry))
+            })
+            .collect();
+
+        Ok(Self {
+            entries: RwLock::new(entries),
+        })
+    }
+
+    pub fn is_blocked(&self, kind: Kind, id: u64) -> bool {
+        self.entries
+            .read()
+            .unwrap()
+            .get(&(kind, id))
+            .is_some_and(|x| x.is_active(Utc::now()))
+    }
+
+    /// Blocks a user or guild, replacing the reason and expiry if it already was
+    pub async fn add(&self, db: &PgPool, entry: Entry) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO blocklist (kind, id, reason, expires_at, added_by, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (kind, id) DO UPDATE SET reason = excluded.reason,
+                expires_at = excluded.expires_at, added_by = excluded.added_by,
+                created_at = excluded.created_at",
+        )
+        .bind(entry.kind.as_str())
+        .bind(entry.id as i64)
+        .bind(&entry.reason)
+        .bind(entry.expires_at)
+        .bind(entry.added_by as i64)
+        .bind(entry.created_at)
+        .execute(db)
+        .await?;
+
+        self.entries
+            .write()
+            .unwrap()
+            .insert((entry.kind, entry.id), entry);
+        Ok(())
+    }
+
+    /// Unblocks a user or guild, returns false if it was not blocked
+    pub async fn remove(&self, db: &PgPool, kind: Kind, id: u64) -> Result<bool, Error> {
+        sqlx::query("DELETE FROM blocklist WHERE kind = $1 AND id = $2")
+            .bind(kind.as_str())
+            .bind(id as i64)
+            .execute(db)
+            .await?;
+
+        let removed = self.entries.write().unwrap().remove(&(kind, id));
+        Ok(removed.is_some_and(|x| x.is_active(Utc::now())))
+    }
+
+    /// Every entry that did not expire, newest first
+    pub fn list(&self) -> Vec<Entry> {
+        let now = Utc::now();
+        let mut entries: Vec<_> = self
+            .entries
+            .read()
+            .unwrap()
+            .values()
+            .filter(|x| x.is_active(now))
+            .cloned()
+            .collect();
+        entries.sort_by_key(|x| std::cmp::Reverse(x.created_at));
+        entries
+    }
+}
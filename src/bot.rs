@@ -0,0 +1,711 @@
+use std::any::Any;
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use poise::serenity_prelude as serenity;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+
+use crate::blocklist::Blocklist;
+use crate::commands::{self, Command};
+use crate::config::Config;
+use crate::cooldowns::{self, Cooldowns};
+use crate::extensions::Extensions;
+use crate::health::Health;
+use crate::maintenance::Maintenance;
+use crate::metrics::Metrics;
+use crate::overrides::OverrideCache;
+use crate::prefixes::PrefixCache;
+use crate::recording::{self, Recorder};
+use crate::registration::{self, Scope};
+use crate::shutdown::{self, Shutdown};
+use crate::{
+    framework_options, http, jobs, logging, reminders, scheduler, Data, Error, Framework, INTENTS,
+    JOB_WORKERS, MIGRATOR,
+};
+
+/// Gets every event after the bot handled it, added with [`BotBuilder::event_handler`]
+pub type EventHandler = Arc<
+    dyn for<'a> Fn(
+            &'a serenity::Context,
+            &'a poise::Event<'a>,
+            poise::FrameworkContext<'a, Data, Error>,
+            &'a Data,
+        ) -> poise::BoxFuture<'a, Result<(), Error>>
+        + Send
+        + Sync,
+>;
+
+type Task = Box<dyn FnOnce(TaskContext) -> poise::BoxFuture<'static, ()> + Send>;
+
+/// What a background task added with [`BotBuilder::task`] gets
+#[derive(Clone)]
+pub struct TaskContext {
+    pub http: Arc<serenity::Http>,
+    pub cache: Arc<serenity::Cache>,
+    pub db: PgPool,
+    /// `shutdown.stopped()` returns once the bot shuts down, the task should return then too
+    pub shutdown: Arc<Shutdown>,
+    /// [`Framework::user_data`] has the [`Data`] of the bot once it is ready
+    pub framework: Arc<Framework>,
+}
+
+/// Sets up the bot, with commands, state, event handlers and background tasks of its user next
+/// to the ones of the bot itself
+pub struct BotBuilder {
+    config: Config,
+    db: Option<PgPool>,
+    commands: Vec<Command>,
+    extensions: Extensions,
+    event_handlers: Vec<EventHandler>,
+    tasks: Vec<Task>,
+    handle_signals: bool,
+}
+
+impl BotBuilder {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            db: None,
+            commands: Vec::new(),
+            extensions: Extensions::default(),
+            event_handlers: Vec::new(),
+            tasks: Vec::new(),
+            handle_signals: false,
+        }
+    }
+
+    /// Uses this pool instead of connecting to `DATABASE_URL`. It stays open when the bot shuts
+    /// down, closing it is up to whoever passed it.
+    pub fn database(mut self, db: PgPool) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// Adds a command next to the ones of the bot, it can't have the name of one of them
+    pub fn command(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        self
+    }
+
+    /// Adds a value to [`Data::extensions`], there is one per type
+    pub fn data<T: Any + Send + Sync>(mut self, value: T) -> Self {
+        self.extensions.insert(value);
+        self
+    }
+
+    /// Runs `handler` for every event, once the bot handled it itself
+    pub fn event_handler<F>(mut self, handler: F) -> Self
+    where
+        F: for<'a> Fn(
+                &'a serenity::Context,
+                &'a poise::Event<'a>,
+                poise::FrameworkContext<'a, Data, Error>,
+                &'a Data,
+            ) -> poise::BoxFuture<'a, Result<(), Error>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.event_handlers.push(Arc::new(handler));
+        self
+    }
+
+    /// Spawns `task` when the bot starts. It should return once `ctx.shutdown.stopped()` does,
+    /// shutting down waits for it like it waits for running commands.
+    pub fn task<F, Fut>(mut self, task: F) -> Self
+    where
+        F: FnOnce(TaskContext) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.push(Box::new(|ctx| Box::pin(task(ctx))));
+        self
+    }
+
+    /// Shuts the bot down on ctrl+c, SIGTERM and SIGHUP like the binary does. Without this,
+    /// signals are left to the host, which can call [`Bot::shutdown`].
+    pub fn handle_signals(mut self) -> Self {
+        self.handle_signals = true;
+        self
+    }
+
+    /// Connects to the database, brings it up to date and loads what the commands need. Nothing
+    /// talks to the gateway until [`Bot::start`].
+    pub async fn build(self) -> Result<Bot, Error> {
+        let config = self.config;
+        // Only a pool the bot opened itself is closed when it shuts down
+        let (db, owns_db) = match self.db {
+            Some(db) => (db, false),
+            None => (
+                PgPoolOptions::new().connect(&config.database_url).await?,
+                true,
+            ),
+        };
+
+        // Commands register themselves, this only makes sure they don't clash with each other
+        let mut commands = commands::all_with(self.commands)
+            .map_err(|problems| format!("Conflicting command names or aliases:\n{problems}"))?;
+        info!("Registered commands:{}", commands::tree(&commands));
+        cooldowns::validate(&commands)
+            .map_err(|problems| format!("Cooldowns of unknown commands:\n{problems}"))?;
+        logging::instrument(&mut commands);
+
+        let shutdown = Arc::new(Shutdown::default());
+        let metrics = Arc::new(Metrics::default());
+        let health = Arc::new(Health::default());
+        // Metrics and health checks, only when there is an address to serve them on. Started this
+        // early so `/readyz` can tell that the bot is still starting
+        if let Some(address) = config.http_address {
+            let state = http::HttpState {
+                metrics: metrics.clone(),
+                health: health.clone(),
+                shutdown: shutdown.clone(),
+                db: db.clone(),
+            };
+            tokio::spawn(http::serve(address, state));
+        }
+
+        // Makes sure the sql tables are updated to the latest definitions
+        MIGRATOR.run(&db).await?;
+        health.set_migrated();
+
+        let http = discord_http(&config);
+
+        let maintenance =
+            Maintenance::load(&db, config.maintenance_message.clone(), config.maintenance).await?;
+        if maintenance.is_enabled() {
+            warn!("Starting in maintenance mode, only owners can use commands");
+        }
+        let data = Data {
+            db: db.clone(),
+            prefixes: PrefixCache::new(config.prefixes.clone()),
+            shutdown: shutdown.clone(),
+            metrics,
+            blocklist: Blocklist::load(&db).await?,
+            maintenance,
+            overrides: OverrideCache::default(),
+            cooldowns: Cooldowns::load(&db).await?,
+            extensions: self.extensions,
+            event_handlers: self.event_handlers,
+        };
+
+        let parts = Parts {
+            config,
+            db,
+            owns_db,
+            commands,
+            data,
+            http,
+            health,
+            tasks: self.tasks,
+            handle_signals: self.handle_signals,
+        };
+        Ok(Bot {
+            shutdown,
+            parts: Mutex::new(Some(parts)),
+        })
+    }
+}
+
+/// Everything a bot runs with, taken by whichever of [`Bot::start`] and [`Bot::replay`] runs
+struct Parts {
+    config: Config,
+    db: PgPool,
+    /// Whether [`BotBuilder::build`] connected to the database, rather than getting a pool
+    owns_db: bool,
+    commands: Vec<Command>,
+    data: Data,
+    http: serenity::Http,
+    health: Arc<Health>,
+    tasks: Vec<Task>,
+    handle_signals: bool,
+}
+
+/// A bot that is ready to start, made with [`BotBuilder`]
+pub struct Bot {
+    shutdown: Arc<Shutdown>,
+    parts: Mutex<Option<Parts>>,
+}
+
+impl Bot {
+    /// Connects to discord and runs until the bot shuts down, returns the exit code for the
+    /// process. A bot only runs once, it can't be started again after it stopped.
+    pub async fn start(&self) -> i32 {
+        let Some(parts) = self.take() else {
+            return shutdown::EXIT_FAILURE;
+        };
+        let Parts {
+            config,
+            db,
+            owns_db,
+            commands,
+            data,
+            http,
+            health,
+            tasks,
+            handle_signals,
+        } = parts;
+        let shutdown = &self.shutdown;
+
+        let dev_guild_id = config.dev_guild_id;
+        let setup_health = health.clone();
+        let setup = setup_fn(move |ctx, _ready, framework| {
+            Box::pin(async move {
+                // Only touches the registered commands if they actually changed
+                let scope = match dev_guild_id {
+                    Some(guild_id) => Scope::Guild(guild_id),
+                    None => Scope::Global,
+                };
+                registration::sync(&ctx.http, &framework.options().commands, scope).await?;
+                setup_health.set_registered();
+
+                Ok(data)
+            })
+        });
+
+        let owners = owners(&config, &http).await;
+
+        // Build the framework
+        let mut client_builder = serenity::ClientBuilder::new_with_http(http, INTENTS);
+        let recorder = match &config.record_events {
+            Some(path) => match Recorder::create(path) {
+                Ok(recorder) => {
+                    info!("Recording gateway events to {}", path.display());
+                    client_builder = client_builder.raw_event_handler(recorder.clone());
+                    Some(recorder)
+                }
+                Err(err) => {
+                    error!("Cannot record gateway events to {}: {err}", path.display());
+                    return shutdown::EXIT_FAILURE;
+                }
+            },
+            None => None,
+        };
+        let options = framework_options(commands, owners);
+        let framework = match Framework::new(client_builder, setup, options).await {
+            Ok(framework) => framework,
+            Err(err) => {
+                error!("Cannot build the bot framework: {err}");
+                return shutdown::EXIT_FAILURE;
+            }
+        };
+
+        let cache_and_http = framework.client().cache_and_http.clone();
+
+        // Runs queued jobs like reminders and the end of temporary bans, including those that were due while offline
+        let registry = jobs::Registry::new()
+            .register::<scheduler::ExpirePunishment>()
+            .register::<reminders::DeliverReminder>();
+        let job_context = jobs::JobContext {
+            http: cache_and_http.http.clone(),
+            db: db.clone(),
+        };
+        let workers = jobs::start(registry, job_context, JOB_WORKERS);
+
+        let task_context = TaskContext {
+            http: cache_and_http.http.clone(),
+            cache: cache_and_http.cache.clone(),
+            db: db.clone(),
+            shutdown: shutdown.clone(),
+            framework: framework.clone(),
+        };
+        let tasks = tasks
+            .into_iter()
+            .map(|task| tokio::spawn(task(task_context.clone())))
+            .collect();
+
+        health.set_shard_manager(framework.shard_manager().clone());
+
+        // Stops the bot cleanly once asked to, and on ctrl+c, SIGTERM or SIGHUP if it handles them
+        let coordinator = tokio::spawn(shutdown::coordinate(
+            shutdown.clone(),
+            framework.shard_manager().clone(),
+            workers,
+            tasks,
+            owns_db.then_some(db),
+            handle_signals,
+        ));
+
+        tracing::info!("Starting the bot!");
+        let result = framework.start().await;
+        if let Some(Err(err)) = recorder.map(|x| x.finish()) {
+            error!("Cannot finish the recording of gateway events: {err}");
+        }
+        let failed = match result {
+            Err(err) => {
+                error!("The bot stopped because of an error: {err}");
+                true
+            }
+            // The shards only stop on their own when something went wrong
+            Ok(()) if !shutdown.is_stopping() => {
+                error!("Lost the connection to discord");
+                true
+            }
+            Ok(()) => false,
+        };
+        if failed {
+            // Jobs, tasks and the database still have to stop the way they do on a shutdown
+            shutdown.request(false);
+            let _ = coordinator.await;
+            return shutdown::EXIT_FAILURE;
+        }
+        coordinator.await.unwrap_or(shutdown::EXIT_FAILURE)
+    }
+
+    /// Sends the events of a recording through the bot instead of connecting to the gateway, see
+    /// `--replay`. Requests go to `DISCORD_API_URL`, a replay refuses to run without it. Background
+    /// tasks don't run, and only `OWNERS` are owners.
+    pub async fn replay(&self, path: &Path, speed: f64) -> i32 {
+        let Some(parts) = self.take() else {
+            return shutdown::EXIT_FAILURE;
+        };
+        // The commands of the recording would ban, kick and reply on discord all over again
+        if parts.config.discord_api_url.is_none() {
+            error!("Replaying needs DISCORD_API_URL, requests would go to discord otherwise");
+            return shutdown::EXIT_FAILURE;
+        }
+        let mut commands = parts.commands;
+        // Done by the framework when it is built
+        commands::set_qualified_names(&mut commands);
+
+        // Never started, only built for its cache and the shard manager commands look at
+        let client = match serenity::ClientBuilder::new_with_http(parts.http, INTENTS).await {
+            Ok(client) => client,
+            Err(err) => {
+                error!("Cannot build the discord client: {err}");
+                return shutdown::EXIT_FAILURE;
+            }
+        };
+        // Without a receiver collectors stop waiting for interactions right away
+        let (shard_tx, _) = serenity::futures::channel::mpsc::unbounded();
+        let replayer = recording::Replayer {
+            options: framework_options(commands, parts.config.owners),
+            data: parts.data,
+            shard_manager: client.shard_manager.clone(),
+            ctx: serenity::Context {
+                data: client.data.clone(),
+                shard: serenity::ShardMessenger::new(shard_tx),
+                shard_id: 0,
+                http: client.cache_and_http.http.clone(),
+                cache: client.cache_and_http.cache.clone(),
+            },
+        };
+
+        info!("Replaying {} at {speed}x", path.display());
+        match recording::replay(replayer, path, speed).await {
+            Ok(_) => shutdown::EXIT_OK,
+            Err(err) => {
+                error!("Cannot replay {}: {err}", path.display());
+                shutdown::EXIT_FAILURE
+            }
+        }
+    }
+
+    /// Shuts the bot down cleanly, the way `/owner shutdown` does. [`Bot::start`] returns once
+    /// it is done.
+    pub fn shutdown(&self) {
+        self.shutdown.request(false);
+    }
+
+    /// Whether an owner used `/owner restart`, see [`shutdown::restart`]
+    pub fn restart_requested(&self) -> bool {
+        self.shutdown.restart_requested()
+    }
+
+    fn take(&self) -> Option<Parts> {
+        let parts = self.parts.lock().unwrap().take();
+        if parts.is_none() {
+            error!("The bot already ran, build a new one to start it again");
+        }
+        parts
+    }
+}
+
+/// `OWNERS` and the owner of the application (or the admins of its team)
+async fn owners(config: &Config, http: &serenity::Http) -> HashSet<serenity::UserId> {
+    let mut owners = config.owners.clone();
+    match http.get_current_application_info().await {
+        Ok(application) => {
+            // Serenity only learns it once a shard is ready otherwise
+            http.set_application_id(application.id.0);
+            owners.extend(application_owners(&application));
+        }
+        Err(err) => warn!("Cannot look up the owners of the application: {err}"),
+    }
+    owners
+}
+
+/// Pins down the signature of the setup closure, which can't be inferred from the closure alone
+fn setup_fn<F>(f: F) -> F
+where
+    F: for<'a> FnOnce(
+        &'a serenity::Context,
+        &'a serenity::Ready,
+        &'a Framework,
+    ) -> poise::BoxFuture<'a, Result<Data, Error>>,
+{
+    f
+}
+
+/// The client for discord's HTTP API, which talks to `DISCORD_API_URL` instead when it is set
+pub fn discord_http(config: &Config) -> serenity::Http {
+    let mut builder = serenity::HttpBuilder::new(&config.discord_token);
+    if let Some(url) = &config.discord_api_url {
+        // Requests are sent to the url with the path appended to it
+        let url = format!("{}/", url.trim_end_matches('/'));
+        builder = builder
+            .proxy(url)
+            .expect("DISCORD_API_URL is checked when loading the config")
+            .ratelimiter_disabled(true);
+    }
+    builder.build()
+}
+
+/// The owner of the application, or the admins of the team it belongs to
+fn application_owners(application: &serenity::CurrentApplicationInfo) -> HashSet<serenity::UserId> {
+    match &application.team {
+        Some(team) => team
+            .members
+            .iter()
+            .filter(|member| member.permissions.iter().any(|x| x == "*"))
+            .map(|member| member.user.id)
+            .collect(),
+        None => HashSet::from([application.owner.id]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use axum::http::Method;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{FakeDiscord, Request, AUTHOR_ID, BOT_ID, CHANNEL_ID, GUILD_ID, OWNER_ID};
+    use crate::Context;
+
+    async fn build(config: Config, db: PgPool) -> Arc<Bot> {
+        let bot = BotBuilder::new(config).database(db).build().await.unwrap();
+        Arc::new(bot)
+    }
+
+    fn start(bot: &Arc<Bot>) -> tokio::task::JoinHandle<i32> {
+        let bot = bot.clone();
+        tokio::spawn(async move { bot.start().await })
+    }
+
+    #[sqlx::test]
+    async fn runs_against_a_fake_discord(db: PgPool) {
+        let discord = FakeDiscord::start().await;
+        let bot = start(&build(discord.config(), db).await);
+
+        // The commands are registered once the shard is ready
+        let commands = format!("/applications/{BOT_ID}/guilds/{GUILD_ID}/commands");
+        let registered = discord
+            .wait_for("the slash commands", |x| {
+                x.method == Method::PUT && x.path == commands
+            })
+            .await;
+        assert!(registered.body.as_array().unwrap().len() > 1);
+        let identify = &discord.gateway_payloads()[0];
+        assert_eq!(identify["op"], 2);
+        assert_eq!(identify["d"]["token"], "Bot token");
+
+        discord.message_create(AUTHOR_ID, None, "!pong");
+        let messages = format!("/channels/{CHANNEL_ID}/messages");
+        discord
+            .wait_for("pong!", |x| {
+                x.path == messages && x.body["content"] == "pong!"
+            })
+            .await;
+
+        discord.interaction_create(AUTHOR_ID, None, "help", &[("command", json!("pong"))]);
+        let response = discord
+            .wait_for("the help of /pong", |x| x.path.ends_with("/callback"))
+            .await;
+        assert_eq!(response.body["data"]["embeds"][0]["title"], "/pong");
+        assert_eq!(response.body["data"]["flags"], 64);
+
+        // The menu waits for clicks, but not once the bot shuts down
+        discord.interaction_create(AUTHOR_ID, None, "help", &[]);
+        discord
+            .wait_for("the menu", |x| {
+                x.path.ends_with("/callback") && !x.body["data"]["components"].is_null()
+            })
+            .await;
+
+        // Only the owner of the application can shut the bot down
+        discord.message_create(AUTHOR_ID, None, "!owner shutdown");
+        discord.message_create(OWNER_ID, None, "!owner shutdown");
+        let code = tokio::time::timeout(Duration::from_secs(10), bot)
+            .await
+            .expect("the bot did not shut down")
+            .unwrap();
+        assert_eq!(code, shutdown::EXIT_OK);
+
+        let replies: Vec<_> = discord
+            .requests()
+            .into_iter()
+            .filter(|x| x.method == Method::POST && x.path == messages)
+            .filter_map(|x| x.body["content"].as_str().map(String::from))
+            .collect();
+        // Both reply at the same time, the first one after recording the attempt
+        assert!(replies.iter().any(|x| x == "Shutting down..."));
+        assert!(replies.iter().any(|x| x.contains("owner")));
+        let closed = discord
+            .requests()
+            .into_iter()
+            .any(|x| x.method == Method::PATCH && x.body["components"] == json!([]));
+        assert!(closed, "the menu kept its components");
+    }
+
+    #[sqlx::test]
+    async fn replays_a_recording(db: PgPool) {
+        let path = std::env::temp_dir().join(format!("events-{}.gz", std::process::id()));
+        let messages = format!("/channels/{CHANNEL_ID}/messages");
+        let pong = |x: &Request| x.path == messages && x.body["content"] == "pong!";
+
+        let discord = FakeDiscord::start().await;
+        let mut recording = discord.config();
+        recording.record_events = Some(path.clone());
+        let bot = start(&build(recording, db.clone()).await);
+        discord
+            .wait_for("the slash commands", |x| x.method == Method::PUT)
+            .await;
+        discord.message_create(AUTHOR_ID, None, "!pong");
+        discord.wait_for("pong!", pong).await;
+        discord.message_create(OWNER_ID, None, "!owner shutdown");
+        bot.await.unwrap();
+
+        // Otherwise the replayed `!pong` is still on cooldown
+        sqlx::query("DELETE FROM cooldowns")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        // Without DISCORD_API_URL the requests would go to discord
+        let mut to_discord = discord.config();
+        to_discord.discord_api_url = None;
+        let bot = build(to_discord, db.clone()).await;
+        assert_eq!(bot.replay(&path, 0.0).await, shutdown::EXIT_FAILURE);
+
+        let replayed = FakeDiscord::start().await;
+        let bot = build(replayed.config(), db.clone()).await;
+        let code = bot.replay(&path, 0.0).await;
+        db.close().await;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(code, shutdown::EXIT_OK);
+        replayed.wait_for("pong!", pong).await;
+        // Nothing connects to the gateway, commands aren't registered again and the owners aren't
+        // looked up
+        assert!(replayed.gateway_payloads().is_empty());
+        let requests = replayed.requests();
+        assert!(!requests.iter().any(|x| x.method == Method::PUT));
+        assert!(!requests.iter().any(|x| x.path.starts_with("/oauth2")));
+    }
+
+    struct Greeting(&'static str);
+
+    /// Greets with the greeting from the extensions
+    #[poise::command(prefix_command)]
+    async fn greet(ctx: Context<'_>) -> Result<(), Error> {
+        let greeting = ctx.data().extensions.get::<Greeting>().unwrap();
+        ctx.say(greeting.0).await?;
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn runs_what_was_added_with_the_builder(db: PgPool) {
+        let discord = FakeDiscord::start().await;
+        let (stopped_tx, stopped_rx) = tokio::sync::oneshot::channel();
+        let saw_ready = Arc::new(AtomicBool::new(false));
+        let on_ready = saw_ready.clone();
+        let bot = BotBuilder::new(discord.config())
+            .database(db.clone())
+            .command(greet())
+            .data(Greeting("hello there"))
+            .event_handler(move |_ctx, event, _framework, _data| {
+                if let poise::Event::Ready { .. } = event {
+                    on_ready.store(true, Ordering::SeqCst);
+                }
+                Box::pin(async { Ok(()) })
+            })
+            .task(|ctx| async move {
+                ctx.shutdown.stopped().await;
+                stopped_tx.send(()).unwrap();
+            })
+            .build()
+            .await
+            .unwrap();
+        let bot = Arc::new(bot);
+        let running = start(&bot);
+
+        let commands = format!("/applications/{BOT_ID}/guilds/{GUILD_ID}/commands");
+        discord
+            .wait_for("the slash commands", |x| {
+                x.method == Method::PUT && x.path == commands
+            })
+            .await;
+        discord.message_create(AUTHOR_ID, None, "!greet");
+        let messages = format!("/channels/{CHANNEL_ID}/messages");
+        discord
+            .wait_for("the greeting", |x| {
+                x.path == messages && x.body["content"] == "hello there"
+            })
+            .await;
+        assert!(saw_ready.load(Ordering::SeqCst));
+
+        // Shutting down waits for the task
+        bot.shutdown();
+        let code = tokio::time::timeout(Duration::from_secs(10), running)
+            .await
+            .expect("the bot did not shut down")
+            .unwrap();
+        assert_eq!(code, shutdown::EXIT_OK);
+        stopped_rx.await.unwrap();
+        // The pool was passed in, so it is still open
+        sqlx::query("SELECT 1").execute(&db).await.unwrap();
+        // A bot only runs once
+        assert_eq!(bot.start().await, shutdown::EXIT_FAILURE);
+    }
+
+    #[sqlx::test]
+    async fn stops_everything_when_the_connection_is_lost(db: PgPool) {
+        let discord = FakeDiscord::start().await;
+        let (stopped_tx, stopped_rx) = tokio::sync::oneshot::channel();
+        let bot = BotBuilder::new(discord.config())
+            .database(db)
+            .task(|ctx| async move {
+                ctx.shutdown.stopped().await;
+                stopped_tx.send(()).unwrap();
+            })
+            .build()
+            .await
+            .unwrap();
+        let running = start(&Arc::new(bot));
+
+        let commands = format!("/applications/{BOT_ID}/guilds/{GUILD_ID}/commands");
+        discord
+            .wait_for("the slash commands", |x| {
+                x.method == Method::PUT && x.path == commands
+            })
+            .await;
+        discord.close_gateway(4004);
+
+        // Serenity gives the shard that is already gone a few seconds to stop
+        let code = tokio::time::timeout(Duration::from_secs(30), running)
+            .await
+            .expect("the bot did not stop")
+            .unwrap();
+        assert_eq!(code, shutdown::EXIT_FAILURE);
+        tokio::time::timeout(Duration::from_secs(10), stopped_rx)
+            .await
+            .expect("the task never saw the shutdown")
+            .unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude as serenity;
+use serenity::{GuildId, UserId};
+use sqlx::PgPool;
+
+use crate::Error;
+
+/// What a moderator did, stored as text in the `cases` table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaseAction {
+    Warn,
+    Timeout,
+    Kick,
+    Ban,
+    Unban,
+    Mute,
+    Unmute,
+    Purge,
+}
+
+impl CaseAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CaseAction::Warn => "warn",
+            CaseAction::Timeout => "timeout",
+            CaseAction::Kick => "kick",
+            CaseAction::Ban => "ban",
+            CaseAction::Unban => "unban",
+            CaseAction::Mute => "mute",
+            CaseAction::Unmute => "unmute",
+            CaseAction::Purge => "purge",
+        }
+    }
+
+    /// How the action reads in a sentence, like "Banned @user"
+    pub fn past_tense(self) -> &'static str {
+        match self {
+            CaseAction::Warn => "Warned",
+            CaseAction::Timeout => "Timed out",
+            CaseAction::Kick => "Kicked",
+            CaseAction::Ban => "Banned",
+            CaseAction::Unban => "Unbanned",
+            CaseAction::Mute => "Muted",
+            CaseAction::Unmute => "Unmuted",
+            CaseAction::Purge => "Purged",
+        }
+    }
+}
+
+impl fmt::Display for CaseAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CaseAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "warn" => CaseAction::Warn,
+            "timeout" => CaseAction::Timeout,
+            "kick" => CaseAction::Kick,
+            "ban" => CaseAction::Ban,
+            "unban" => CaseAction::Unban,
+            "mute" => CaseAction::Mute,
+            "unmute" => CaseAction::Unmute,
+            "purge" => CaseAction::Purge,
+            other => return Err(format!("unknown case action `{other}`").into()),
+        })
+    }
+}
+
+/// A row of the `cases` table
+#[derive(sqlx::FromRow, Debug)]
+pub struct Case {
+    pub case_number: i32,
+    pub action: String,
+    pub moderator_id: i64,
+    pub target_id: Option<i64>,
+    pub reason: Option<String>,
+    pub duration_seconds: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Case {
+    pub fn action(&self) -> Result<CaseAction, Error> {
+        self.action.parse()
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration_seconds
+            .map(|secs| Duration::from_secs(secs as u64))
+    }
+}
+
+/// A case that has not been saved yet
+pub struct NewCase<'a> {
+    pub guild_id: GuildId,
+    pub action: CaseAction,
+    pub moderator_id: UserId,
+    pub target_id: Option<UserId>,
+    pub reason: Option<&'a str>,
+    pub duration: Option<Duration>,
+}
+
+impl NewCase<'_> {
+    /// Saves the case under the next case number of the guild, which is returned
+    pub async fn create(&self, db: &PgPool) -> Result<i32, Error> {
+        let mut transaction = db.begin().await?;
+
+        // A counter row per guild hands out numbers without gaps or duplicates, even when two
+        // moderators act at the same time
+        let case_number: i32 = sqlx::query_scalar(
+            "INSERT INTO guild_case_counters (guild_id, last_case_number) VALUES ($1, 1)
+            ON CONFLICT (guild_id) DO UPDATE SET last_case_number = guild_case_counters.last_case_number + 1
+            RETURNING last_case_number",
+        )
+        .bind(self.guild_id.0 as i64)
+        .fetch_one(&mut transaction)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO cases (guild_id, case_number, action, moderator_id, target_id, reason, duration_seconds)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(self.guild_id.0 as i64)
+        .bind(case_number)
+        .bind(self.action.as_str())
+        .bind(self.moderator_id.0 as i64)
+        .bind(self.target_id.map(|x| x.0 as i64))
+        .bind(self.reason)
+        .bind(self.duration.map(|x| x.as_secs() as i64))
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+        Ok(case_number)
+    }
+}
+
+pub async fn get(db: &PgPool, guild_id: GuildId, case_number: i32) -> Result<Option<Case>, Error> {
+    let case = sqlx::query_as("SELECT * FROM cases WHERE guild_id = $1 AND case_number = $2")
+        .bind(guild_id.0 as i64)
+        .bind(case_number)
+        .fetch_optional(db)
+        .await?;
+    Ok(case)
+}
+
+/// Changes the reason of a case, returns false if there is no such case
+pub async fn set_reason(
+    db: &PgPool,
+    guild_id: GuildId,
+    case_number: i32,
+    reason: &str,
+) -> Result<bool, Error> {
+    let updated =
+        sqlx::query("UPDATE cases SET reason = $3 WHERE guild_id = $1 AND case_number = $2")
+            .bind(guild_id.0 as i64)
+            .bind(case_number)
+            .bind(reason)
+            .execute(db)
+            .await?
+            .rows_affected()
+            > 0;
+    Ok(updated)
+}
+
+/// Deletes a case, returns false if there is no such case
+pub async fn delete(db: &PgPool, guild_id: GuildId, case_number: i32) -> Result<bool, Error> {
+    let deleted = sqlx::query("DELETE FROM cases WHERE guild_id = $1 AND case_number = $2")
+        .bind(guild_id.0 as i64)
+        .bind(case_number)
+        .execute(db)
+        .await?
+        .rows_affected()
+        > 0;
+    Ok(deleted)
+}
+
+/// The most recent cases against a user, newest first
+pub async fn for_target(
+    db: &PgPool,
+    guild_id: GuildId,
+    target_id: UserId,
+    limit: i64,
+) -> Result<Vec<Case>, Error> {
+    let cases = sqlx::query_as(
+        "SELECT * FROM cases WHERE guild_id = $1 AND target_id = $2
+        ORDER BY case_number DESC LIMIT $3",
+    )
+    .bind(guild_id.0 as i64)
+    .bind(target_id.0 as i64)
+    .bind(limit)
+    .fetch_all(db)
+    .await?;
+    Ok(cases)
+}
+
+/// Total amount of cases against a user
+pub async fn count_for_target(
+    db: &PgPool,
+    guild_id: GuildId,
+    target_id: UserId,
+) -> Result<i64, Error> {
+    let count =
+        sqlx::query_scalar("SELECT count(*) FROM cases WHERE guild_id = $1 AND target_id = $2")
+            .bind(guild_id.0 as i64)
+            .bind(target_id.0 as i64)
+            .fetch_one(db)
+            .await?;
+    Ok(count)
+}
@@ -0,0 +1,71 @@
+use poise::ApplicationCommandOrAutocompleteInteraction;
+
+use crate::blocklist::Kind;
+use crate::error::BotError;
+use crate::overrides;
+use crate::{Context, Error};
+
+/// Used as the `command_check` of the framework, runs before every command
+pub async fn command_check(ctx: Context<'_>) -> Result<bool, Error> {
+    // Poise checks autocomplete requests too, every keystroke. Those aren't uses of the command
+    // and a denial would only end up as an error reply to something that can't be replied to.
+    if let Context::Application(ctx) = ctx {
+        if matches!(
+            ctx.interaction,
+            ApplicationCommandOrAutocompleteInteraction::Autocomplete(_)
+        ) {
+            return Ok(true);
+        }
+    }
+
+    // Commands that already started get to finish, but no new ones start
+    if ctx.data().shutdown.is_stopping() {
+        return Err(BotError::user(
+            "The bot is shutting down, try again in a moment",
+        ));
+    }
+
+    // Owners can't lock themselves out, and need to be able to use commands during maintenance
+    let author = ctx.author().id;
+    if ctx.framework().options().owners.contains(&author) {
+        return Ok(true);
+    }
+
+    let blocklist = &ctx.data().blocklist;
+    let guild_blocked = ctx
+        .guild_id()
+        .is_some_and(|x| blocklist.is_blocked(Kind::Guild, x.0));
+    if guild_blocked || blocklist.is_blocked(Kind::User, author.0) {
+        return Err(Box::new(BotError::Blocked));
+    }
+
+    if let Some(notice) = ctx.data().maintenance.notice() {
+        return Err(Box::new(BotError::Maintenance(notice)));
+    }
+
+    // Commands the guild turned off or restricted to some channels or roles
+    let mut path = ctx.parent_commands().to_vec();
+    path.push(ctx.command());
+    if let Some(reason) = overrides::denial(ctx, &path).await? {
+        return Err(BotError::permission(reason));
+    }
+
+    // Poise runs the checks for every group of a subcommand before the ones of the subcommand,
+    // only the command that was used has a cooldown
+    let checked = ctx.invocation_data::<Checked>().await.map_or(0, |x| x.0) + 1;
+    if checked <= ctx.parent_commands().len() {
+        ctx.set_invocation_data(Checked(checked)).await;
+        return Ok(true);
+    }
+
+    // They are started by `pre_command` once the command got past every check
+    if let Some(remaining) = ctx.data().cooldowns.remaining(ctx).await? {
+        return Err(Box::new(BotError::Cooldown(remaining)));
+    }
+
+    Ok(true)
+}
+
+/// How many commands of the invocation passed the checks so far, kept in the invocation data
+/// until `pre_command` replaces it
+struct Checked(usize);
@@ -0,0 +1,285 @@
+//! What the subcommands of the binary do, next to running the bot. They print for whoever runs
+//! them and return the exit code for the process.
+
+use std::time::Duration;
+
+use poise::serenity_prelude as serenity;
+use sqlx::migrate::{Migrate, Migration};
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+
+use crate::bot::discord_http;
+use crate::commands::Command;
+use crate::config::{Config, MigrateArgs, RegisterArgs};
+use crate::registration::{self, Scope};
+use crate::shutdown::{EXIT_FAILURE, EXIT_OK};
+use crate::{Error, MIGRATOR};
+
+/// How long `check-config` waits for the database
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `migrate`: applies the pending migrations, or lists or reverts them
+pub async fn migrate(config: &Config, args: &MigrateArgs) -> i32 {
+    let result = async {
+        let db = PgPoolOptions::new().connect(&config.database_url).await?;
+        let result = migrate_with(&db, args).await;
+        db.close().await;
+        result
+    };
+    exit_code(result.await, "Cannot migrate the database")
+}
+
+/// `register`: registers the slash commands (or removes them) the way the bot does when it starts
+pub async fn register(config: &Config, commands: &[Command], args: &RegisterArgs) -> i32 {
+    let scope = match (args.guild, args.global) {
+        (Some(guild_id), _) => Scope::Guild(serenity::GuildId(guild_id)),
+        (None, true) => Scope::Global,
+        (None, false) => config.dev_guild_id.map_or(Scope::Global, Scope::Guild),
+    };
+    let result = async {
+        let http = discord_http(config);
+        // Serenity only learns it once a shard is ready otherwise
+        let application = http.get_current_application_info().await?;
+        http.set_application_id(application.id.0);
+
+        if args.clear {
+            registration::clear(&http, scope).await?;
+            println!("Removed every slash command {scope}");
+        } else if registration::sync(&http, commands, scope).await? {
+            println!("Registered the slash commands {scope}");
+        } else {
+            println!("The slash commands are already up to date {scope}");
+        }
+        Ok(())
+    };
+    exit_code(result.await, "Cannot register the slash commands")
+}
+
+/// `check-config`: the configuration was loaded fine if this runs, so this checks whether the
+/// database and discord accept it
+pub async fn check_config(config: &Config) -> i32 {
+    println!("The configuration is valid");
+    let mut ok = true;
+
+    let db = PgPoolOptions::new()
+        .acquire_timeout(CONNECT_TIMEOUT)
+        .connect(&config.database_url)
+        .await;
+    match db {
+        Ok(db) => {
+            println!("Connected to the database");
+            match pending(&db).await {
+                Ok(pending) if pending.is_empty() => println!("The database is up to date"),
+                Ok(pending) => println!(
+                    "{} migrations are not applied yet, `migrate` or starting the bot applies them",
+                    pending.len()
+                ),
+                Err(err) => {
+                    eprintln!("Cannot read the migrations of the database: {err}");
+                    ok = false;
+                }
+            }
+            db.close().await;
+        }
+        Err(err) => {
+            eprintln!("Cannot connect to the database: {err}");
+            ok = false;
+        }
+    }
+
+    match discord_http(config).get_current_user().await {
+        Ok(user) => println!("Logged in to discord as {}", user.tag()),
+        Err(err) => {
+            eprintln!("Cannot log in to discord: {err}");
+            ok = false;
+        }
+    }
+
+    if ok {
+        EXIT_OK
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+async fn migrate_with(db: &PgPool, args: &MigrateArgs) -> Result<(), Error> {
+    if let Some(count) = args.revert {
+        return revert(db, count).await;
+    }
+
+    if args.status {
+        let applied = applied(db).await?;
+        for migration in migrations() {
+            let state = match applied.contains(&migration.version) {
+                true => "applied",
+                false => "pending",
+            };
+            println!("{} {state:<7} {}", migration.version, migration.description);
+        }
+        return Ok(());
+    }
+
+    let pending = pending(db).await?;
+    if pending.is_empty() {
+        println!("The database is up to date");
+        return Ok(());
+    }
+    for migration in &pending {
+        println!("{} {}", migration.version, migration.description);
+    }
+    if args.dry_run {
+        println!("{} migrations would be applied", pending.len());
+    } else {
+        MIGRATOR.run(db).await?;
+        println!("Applied {} migrations", pending.len());
+    }
+    Ok(())
+}
+
+/// Undoes the last `count` migrations that were applied, newest first
+async fn revert(db: &PgPool, count: usize) -> Result<(), Error> {
+    let applied = applied(db).await?;
+    let kept = applied.len().saturating_sub(count);
+    if kept == applied.len() {
+        println!("There are no migrations to revert");
+        return Ok(());
+    }
+
+    // Everything newer than the last migration that is kept is reverted
+    let target = kept.checked_sub(1).map_or(0, |i| applied[i]);
+    MIGRATOR.undo(db, target).await?;
+    for version in applied[kept..].iter().rev() {
+        let description = migrations()
+            .find(|x| x.version == *version)
+            .map_or("", |x| &x.description);
+        println!("Reverted {version} {description}");
+    }
+    Ok(())
+}
+
+/// The migrations that bring the database up to date, without the ones that revert them
+fn migrations() -> impl Iterator<Item = &'static Migration> {
+    MIGRATOR
+        .iter()
+        .filter(|x| !x.migration_type.is_down_migration())
+}
+
+async fn pending(db: &PgPool) -> Result<Vec<&'static Migration>, Error> {
+    let applied = applied(db).await?;
+    Ok(migrations()
+        .filter(|x| !applied.contains(&x.version))
+        .collect())
+}
+
+/// Versions of the applied migrations, oldest first
+async fn applied(db: &PgPool) -> Result<Vec<i64>, Error> {
+    // Looking shouldn't create the table of sqlx for a database that was never migrated
+    let exists: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(db)
+        .await?;
+    if !exists {
+        return Ok(Vec::new());
+    }
+
+    let mut conn = db.acquire().await?;
+    let mut applied: Vec<_> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|x| x.version)
+        .collect();
+    applied.sort_unstable();
+    Ok(applied)
+}
+
+fn exit_code(result: Result<(), Error>, what: &str) -> i32 {
+    match result {
+        Ok(()) => EXIT_OK,
+        Err(err) => {
+            eprintln!("{what}: {err}");
+            EXIT_FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+
+    use super::*;
+    use crate::commands;
+    use crate::testing::{FakeDiscord, BOT_ID, GUILD_ID};
+
+    fn args(revert: Option<usize>) -> MigrateArgs {
+        MigrateArgs {
+            dry_run: false,
+            revert,
+            status: false,
+        }
+    }
+
+    #[sqlx::test]
+    async fn reverts_and_applies_migrations(db: PgPool) {
+        let all = migrations().count();
+        assert!(pending(&db).await.unwrap().is_empty());
+
+        migrate_with(&db, &args(Some(2))).await.unwrap();
+        let pending_versions: Vec<_> = pending(&db)
+            .await
+            .unwrap()
+            .iter()
+            .map(|x| x.version)
+            .collect();
+        let newest: Vec<_> = migrations().skip(all - 2).map(|x| x.version).collect();
+        assert_eq!(pending_versions, newest);
+
+        let dry_run = MigrateArgs {
+            dry_run: true,
+            ..args(None)
+        };
+        migrate_with(&db, &dry_run).await.unwrap();
+        assert_eq!(pending(&db).await.unwrap().len(), 2);
+
+        migrate_with(&db, &args(None)).await.unwrap();
+        assert!(pending(&db).await.unwrap().is_empty());
+        // The tables of the reverted migrations are back
+        sqlx::query("SELECT * FROM cooldowns")
+            .execute(&db)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn registers_without_the_gateway() {
+        let discord = FakeDiscord::start().await;
+        let config = discord.config();
+        let commands = commands::all().unwrap();
+        let guild = format!("/applications/{BOT_ID}/guilds/{GUILD_ID}/commands");
+
+        let in_guild = RegisterArgs {
+            guild: None,
+            global: false,
+            clear: false,
+        };
+        assert_eq!(register(&config, &commands, &in_guild).await, EXIT_OK);
+        let put = discord
+            .wait_for("the slash commands", |x| {
+                x.method == Method::PUT && x.path == guild
+            })
+            .await;
+        assert!(!put.body.as_array().unwrap().is_empty());
+
+        let clear = RegisterArgs {
+            clear: true,
+            ..in_guild
+        };
+        assert_eq!(register(&config, &commands, &clear).await, EXIT_OK);
+        let puts: Vec<_> = discord
+            .requests()
+            .into_iter()
+            .filter(|x| x.method == Method::PUT && x.path == guild)
+            .collect();
+        assert_eq!(puts.last().unwrap().body, serde_json::json!([]));
+        assert!(discord.gateway_payloads().is_empty());
+    }
+}
@@ -1,2 +1,177 @@
-pub mod help;
-pub mod ping;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use serde_json::{json, Value};
+
+use crate::{Data, Error};
+
+pub type Command = poise::Command<Data, Error>;
+
+/// A command that added itself to the bot with [`register_command!`]
+pub struct CommandEntry(pub fn() -> Command);
+
+inventory::collect!(CommandEntry);
+
+/// Adds a command to the bot, put this next to the command function:
+///
+/// ```ignore
+/// register_command!(pong);
+/// ```
+///
+/// Subcommands are registered through their parent and don't need this.
+macro_rules! register_command {
+    ($command:path) => {
+        inventory::submit! { $crate::commands::CommandEntry($command) }
+    };
+}
+
+/// Gives a command (by its qualified name) a cooldown in one or more buckets, in seconds. Guilds can
+/// change them with `/cooldown`:
+///
+/// ```ignore
+/// cooldown!("pong", User = 5, Guild = 30);
+/// ```
+macro_rules! cooldown {
+    ($command:literal, $($bucket:ident = $seconds:literal),+ $(,)?) => {
+        inventory::submit! {
+            $crate::cooldowns::Declared {
+                command: $command,
+                buckets: &[$(($crate::cooldowns::Bucket::$bucket, $seconds)),+],
+            }
+        }
+    };
+}
+
+// Every file in `src/commands/` is a module, the list is generated by `build.rs`
+include!(concat!(env!("OUT_DIR"), "/command_modules.rs"));
+
+/// Every registered command, sorted by name.
+///
+/// Fails if two commands (or subcommands of the same parent) share a name or alias since only one
+/// of them would ever be reachable.
+pub fn all() -> Result<Vec<Command>, String> {
+    all_with(Vec::new())
+}
+
+/// Like [`all`], with commands that are not registered (like the ones of
+/// [`BotBuilder::command`](crate::BotBuilder::command)) next to them
+pub fn all_with(extra: Vec<Command>) -> Result<Vec<Command>, String> {
+    let mut commands: Vec<_> = inventory::iter::<CommandEntry>
+        .into_iter()
+        .map(|entry| (entry.0)())
+        .chain(extra)
+        .collect();
+    commands.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut problems = Vec::new();
+    check_duplicates(&commands, "", &mut problems);
+
+    if problems.is_empty() {
+        Ok(commands)
+    } else {
+        Err(problems.join("\n"))
+    }
+}
+
+fn check_duplicates(commands: &[Command], parent: &str, problems: &mut Vec<String>) {
+    let mut seen: HashMap<String, &str> = HashMap::new();
+
+    for command in commands {
+        let names = std::iter::once(command.name.as_str())
+            .chain(command.aliases.iter().copied())
+            // Prefix commands are matched case insensitively
+            .map(|x| x.to_lowercase());
+
+        for name in names {
+            if let Some(other) = seen.insert(name.clone(), &command.qualified_name) {
+                problems.push(format!(
+                    "`{parent}{name}` is used by both `{other}` and `{}`",
+                    command.qualified_name
+                ));
+            }
+        }
+
+        check_duplicates(
+            &command.subcommands,
+            &format!("{}{} ", parent, command.name),
+            problems,
+        );
+    }
+}
+
+/// Qualified names of every command and subcommand that is shown in the help, like `prefix add`
+pub fn visible_names(commands: &[Command]) -> Vec<String> {
+    let mut names = Vec::new();
+    for command in commands.iter().filter(|x| !x.hide_in_help) {
+        names.push(command.qualified_name.clone());
+        names.extend(visible_names(&command.subcommands));
+    }
+    names
+}
+
+/// Renders the command tree, one command per line with subcommands indented below their parent
+pub fn tree(commands: &[Command]) -> String {
+    fn render(commands: &[Command], depth: usize, out: &mut String) {
+        for command in commands {
+            write!(out, "\n{:indent$}{}", "", command.name, indent = depth * 2).unwrap();
+            if !command.aliases.is_empty() {
+                write!(out, " (aliases: {})", command.aliases.join(", ")).unwrap();
+            }
+            render(&command.subcommands, depth + 1, out);
+        }
+    }
+
+    let mut out = String::new();
+    render(commands, 0, &mut out);
+    out
+}
+
+/// The same tree as [`tree`] as JSON, for tools and documentation. Qualified names are only filled
+/// in after [`set_qualified_names`].
+pub fn json(commands: &[Command]) -> Value {
+    commands
+        .iter()
+        .map(|command| {
+            let parameters: Vec<_> = command
+                .parameters
+                .iter()
+                .map(|parameter| {
+                    json!({
+                        "name": parameter.name,
+                        "description": parameter.description,
+                        "required": parameter.required,
+                    })
+                })
+                .collect();
+            json!({
+                "name": command.name,
+                "qualified_name": command.qualified_name,
+                "description": command.description,
+                "category": command.category,
+                "aliases": command.aliases,
+                "slash": command.slash_action.is_some(),
+                "prefix": command.prefix_action.is_some(),
+                "context_menu": command.context_menu_name,
+                "owners_only": command.owners_only,
+                "guild_only": command.guild_only,
+                "hidden": command.hide_in_help,
+                "parameters": parameters,
+                "subcommands": json(&command.subcommands),
+            })
+        })
+        .collect()
+}
+
+/// Sets the qualified names of the commands, like `prefix add`. Done by the framework when it is
+/// built, commands that are dispatched without one need this.
+pub fn set_qualified_names(commands: &mut [Command]) {
+    fn set(commands: &mut [Command], parent: &str) {
+        for command in commands {
+            command.qualified_name = format!("{parent}{}", command.name);
+            let parent = format!("{} ", command.qualified_name);
+            set(&mut command.subcommands, &parent);
+        }
+    }
+
+    set(commands, "");
+}
@@ -0,0 +1,128 @@
+use chrono::Utc;
+use poise::serenity_prelude as serenity;
+use serenity::GuildId;
+
+use crate::blocklist::{Entry, Kind};
+use crate::duration::HumanDuration;
+use crate::error::BotError;
+use crate::paginate::paginate;
+use crate::{Context, Error};
+
+/// Entries per page of `/owner blocklist list`
+const ENTRIES_PER_PAGE: usize = 10;
+
+// Not registered on its own, this is a subcommand of `/owner`
+
+/// Keep users or whole servers from using the bot
+#[poise::command(
+    slash_command,
+    prefix_command,
+    owners_only,
+    hide_in_help,
+    subcommands("add", "remove", "list")
+)]
+pub async fn blocklist(ctx: Context<'_>) -> Result<(), Error> {
+    list_entries(ctx).await
+}
+
+/// Block a user or server, blocked users are ignored without a reply
+#[poise::command(slash_command, prefix_command, owners_only, hide_in_help)]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "Block a user or a server"] kind: Kind,
+    #[description = "Id of the user or server"] id: String,
+    #[description = "Unblock them again after this long, like 7d"] duration: Option<HumanDuration>,
+    #[description = "Why they are blocked"]
+    #[rest]
+    reason: Option<String>,
+) -> Result<(), Error> {
+    let id = parse_id(&id)?;
+    let now = Utc::now();
+    let expires_at = match duration {
+        Some(duration) => Some(now + chrono::Duration::from_std(duration.0)?),
+        None => None,
+    };
+
+    let data = ctx.data();
+    let entry = Entry {
+        kind,
+        id,
+        reason,
+        expires_at,
+        added_by: ctx.author().id.0,
+        created_at: now,
+    };
+    data.blocklist.add(&data.db, entry).await?;
+
+    let mut response = format!("Blocked {} `{id}`", kind.as_str());
+    if let Some(expires_at) = expires_at {
+        response += &format!(" until <t:{}:f>", expires_at.timestamp());
+    }
+
+    // There is no need to wait for the next `GuildCreate` to leave
+    if kind == Kind::Guild && ctx.serenity_context().cache.guild(id).is_some() {
+        GuildId(id).leave(ctx.serenity_context()).await?;
+        response += " and left it";
+    }
+
+    ctx.say(response).await?;
+    Ok(())
+}
+
+/// Unblock a user or server
+#[poise::command(slash_command, prefix_command, owners_only, hide_in_help)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "Unblock a user or a server"] kind: Kind,
+    #[description = "Id of the user or server"] id: String,
+) -> Result<(), Error> {
+    let id = parse_id(&id)?;
+
+    let data = ctx.data();
+    if data.blocklist.remove(&data.db, kind, id).await? {
+        ctx.say(format!("Unblocked {} `{id}`", kind.as_str()))
+            .await?;
+    } else {
+        ctx.say(format!("{} `{id}` is not blocked", kind.as_str()))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Show every blocked user and server
+#[poise::command(slash_command, prefix_command, owners_only, hide_in_help)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    list_entries(ctx).await
+}
+
+async fn list_entries(ctx: Context<'_>) -> Result<(), Error> {
+    let entries = ctx.data().blocklist.list();
+
+    let pages: Vec<String> = entries
+        .chunks(ENTRIES_PER_PAGE)
+        .map(|chunk| chunk.iter().map(line).collect::<Vec<_>>().join("\n"))
+        .collect();
+
+    paginate(ctx, &format!("{} blocked", entries.len()), &pages).await
+}
+
+/// One line like "user `1234`: spam (until <date>, by <mention>)"
+fn line(entry: &Entry) -> String {
+    let reason = entry.reason.as_deref().unwrap_or("No reason given");
+    let until = match entry.expires_at {
+        Some(expires_at) => format!("until <t:{}:R>", expires_at.timestamp()),
+        None => "for good".to_string(),
+    };
+    format!(
+        "{} `{}`: {reason} ({until}, by <@{}>)",
+        entry.kind.as_str(),
+        entry.id,
+        entry.added_by
+    )
+}
+
+fn parse_id(id: &str) -> Result<u64, Error> {
+    id.trim()
+        .parse()
+        .map_err(|_| BotError::user(format!("`{id}` is not a valid id")))
+}
@@ -0,0 +1,171 @@
+use poise::serenity_prelude as serenity;
+use serenity::{Mentionable, User, UserId};
+
+use crate::cases::{self, Case};
+use crate::duration::HumanDuration;
+use crate::error::BotError;
+use crate::{Context, Error};
+
+register_command!(case);
+register_command!(infractions);
+
+/// How many cases `/infractions` shows
+const INFRACTIONS_SHOWN: i64 = 15;
+
+/// Look at or change moderation cases
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    category = "Moderation",
+    required_permissions = "MODERATE_MEMBERS",
+    subcommands("view", "edit", "delete")
+)]
+pub async fn case(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.say("Use `case view`, `case edit` or `case delete`")
+        .await?;
+    Ok(())
+}
+
+/// Show a moderation case
+#[poise::command(slash_command, prefix_command, guild_only, category = "Moderation")]
+pub async fn view(
+    ctx: Context<'_>,
+    #[description = "Number of the case"] number: i32,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let case = cases::get(&ctx.data().db, guild_id, number)
+        .await?
+        .ok_or_else(|| BotError::user(format!("There is no case #{number}")))?;
+
+    let action = case.action()?;
+    let target = case
+        .target_id
+        .map(|id| UserId(id as u64).mention().to_string())
+        .unwrap_or_else(|| "-".to_string());
+    let moderator = UserId(case.moderator_id as u64).mention().to_string();
+    let reason = case.reason.as_deref().unwrap_or("No reason given");
+    let duration = case.duration().map(|x| HumanDuration(x).to_string());
+    let created_at = format!("<t:{}:f>", case.created_at.timestamp());
+
+    ctx.send(|b| {
+        b.embed(|e| {
+            e.title(format!(
+                "Case #{} | {}",
+                case.case_number,
+                action.past_tense()
+            ))
+            .field("Target", target, true)
+            .field("Moderator", moderator, true)
+            .field("Date", created_at, true);
+            if let Some(duration) = duration {
+                e.field("Duration", duration, true);
+            }
+            e.field("Reason", reason, false)
+        })
+    })
+    .await?;
+    Ok(())
+}
+
+/// Change the reason of a moderation case
+#[poise::command(slash_command, prefix_command, guild_only, category = "Moderation")]
+pub async fn edit(
+    ctx: Context<'_>,
+    #[description = "Number of the case"] number: i32,
+    #[description = "The new reason"]
+    #[rest]
+    reason: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+
+    if !cases::set_reason(&ctx.data().db, guild_id, number, &reason).await? {
+        return Err(BotError::user(format!("There is no case #{number}")));
+    }
+
+    ctx.say(format!("Updated the reason of case #{number}"))
+        .await?;
+    Ok(())
+}
+
+/// Delete a moderation case
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    category = "Moderation",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn delete(
+    ctx: Context<'_>,
+    #[description = "Number of the case"] number: i32,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+
+    if !cases::delete(&ctx.data().db, guild_id, number).await? {
+        return Err(BotError::user(format!("There is no case #{number}")));
+    }
+
+    ctx.say(format!("Deleted case #{number}")).await?;
+    Ok(())
+}
+
+/// Show the moderation history of a user
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    category = "Moderation",
+    required_permissions = "MODERATE_MEMBERS"
+)]
+pub async fn infractions(
+    ctx: Context<'_>,
+    #[description = "The user to look up"] user: User,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let db = &ctx.data().db;
+
+    let cases = cases::for_target(db, guild_id, user.id, INFRACTIONS_SHOWN).await?;
+    if cases.is_empty() {
+        ctx.say(format!("{} has no infractions", user.tag()))
+            .await?;
+        return Ok(());
+    }
+    let total = cases::count_for_target(db, guild_id, user.id).await?;
+
+    let lines: Vec<_> = cases.iter().map(summary).collect();
+    let footer = if total > cases.len() as i64 {
+        format!("Showing the latest {} of {total} cases", cases.len())
+    } else {
+        format!("{total} cases")
+    };
+
+    ctx.send(|b| {
+        b.embed(|e| {
+            e.title(format!("Infractions of {}", user.tag()))
+                .description(lines.join("\n"))
+                .footer(|f| f.text(footer))
+        })
+    })
+    .await?;
+    Ok(())
+}
+
+/// One line description of a case like "#4 Banned for 1d: spam (date)"
+fn summary(case: &Case) -> String {
+    let action = case
+        .action()
+        .map(|x| x.past_tense())
+        .unwrap_or(case.action.as_str());
+    let duration = case
+        .duration()
+        .map(|x| format!(" for {}", HumanDuration(x)))
+        .unwrap_or_default();
+    let reason = case.reason.as_deref().unwrap_or("No reason given");
+
+    format!(
+        "**#{}** {action}{duration}: {reason} (<t:{}:d>)",
+        case.case_number,
+        case.created_at.timestamp()
+    )
+}
@@ -0,0 +1,180 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::cooldowns::{describe, per, Bucket};
+use crate::duration::HumanDuration;
+use crate::error::BotError;
+use crate::overrides::find_command;
+use crate::{Context, Error};
+
+register_command!(cooldown);
+
+/// Autocomplete only offers this many commands at once, the most discord allows
+const SUGGESTIONS: usize = 25;
+
+/// Change how often commands can be used in this server
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    subcommands("set", "remove", "reset", "list")
+)]
+pub async fn cooldown(ctx: Context<'_>) -> Result<(), Error> {
+    // Only reachable as a prefix command since discord doesn't let you invoke a group directly
+    list_cooldowns(ctx).await
+}
+
+/// Set the cooldown of a command in this server
+///
+/// Examples:
+/// `/cooldown set pong User 30s`
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn set(
+    ctx: Context<'_>,
+    #[description = "The command, like `prefix add`"]
+    #[autocomplete = "autocomplete_command"]
+    command: String,
+    #[description = "Who the cooldown is for"] bucket: Bucket,
+    #[description = "How long, like 10s or 5m"] duration: HumanDuration,
+) -> Result<(), Error> {
+    let command = resolve(ctx, &command)?;
+    if bucket == Bucket::Global {
+        return Err(BotError::user(
+            "The global cooldown is shared with every server, so it can't be changed",
+        ));
+    }
+
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    data.cooldowns
+        .set(&data.db, guild_id, &command, bucket, duration.0)
+        .await?;
+
+    ctx.say(format!(
+        "`/{command}` now has a cooldown of {duration} {}",
+        per(bucket)
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Take away a cooldown of a command in this server
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "The command, like `prefix add`"]
+    #[autocomplete = "autocomplete_command"]
+    command: String,
+    #[description = "Which cooldown to take away"] bucket: Bucket,
+) -> Result<(), Error> {
+    let command = resolve(ctx, &command)?;
+    if bucket == Bucket::Global {
+        return Err(BotError::user(
+            "The global cooldown is shared with every server, so it can't be changed",
+        ));
+    }
+
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    data.cooldowns
+        .set(&data.db, guild_id, &command, bucket, Duration::ZERO)
+        .await?;
+
+    ctx.say(format!(
+        "`/{command}` no longer has a cooldown {}",
+        per(bucket)
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Go back to the cooldowns a command comes with
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn reset(
+    ctx: Context<'_>,
+    #[description = "The command, like `prefix add`"]
+    #[autocomplete = "autocomplete_command"]
+    #[rest]
+    command: String,
+) -> Result<(), Error> {
+    let command = resolve(ctx, &command)?;
+
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let response = if data.cooldowns.reset(&data.db, guild_id, &command).await? {
+        let declared = data.cooldowns.declared(&command);
+        format!(
+            "`/{command}` is back to its usual cooldowns: {}",
+            describe(&declared)
+        )
+    } else {
+        format!("The cooldowns of `/{command}` were not changed")
+    };
+
+    ctx.say(response).await?;
+    Ok(())
+}
+
+/// Show the cooldowns of every command in this server
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    list_cooldowns(ctx).await
+}
+
+async fn list_cooldowns(ctx: Context<'_>) -> Result<(), Error> {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let all = data.cooldowns.all(&data.db, guild_id).await?;
+
+    let response = if all.is_empty() {
+        "No command has a cooldown in this server".to_string()
+    } else {
+        let mut response = String::new();
+        for (command, config) in all {
+            writeln!(response, "`/{command}`: {}", describe(&config)).unwrap();
+        }
+        response
+    };
+
+    ctx.say(response).await?;
+    Ok(())
+}
+
+/// The qualified name of the command the user meant
+fn resolve(ctx: Context<'_>, input: &str) -> Result<String, Error> {
+    let words: Vec<_> = input
+        .trim()
+        .trim_start_matches('/')
+        .split_whitespace()
+        .collect();
+    find_command(&ctx.framework().options().commands, &words)
+        .filter(|x| !x.hide_in_help)
+        .map(|x| x.qualified_name.clone())
+        .ok_or_else(|| BotError::user(format!("There is no command called `{input}`")))
+}
+
+async fn autocomplete_command<'a>(
+    ctx: Context<'a>,
+    partial: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    let partial = partial.to_lowercase();
+    crate::commands::visible_names(&ctx.framework().options().commands)
+        .into_iter()
+        .filter(move |x| x.to_lowercase().contains(&partial))
+        .take(SUGGESTIONS)
+}
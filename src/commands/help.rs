@@ -1,17 +1,684 @@
-use crate::{Context, Error};
+use std::fmt::Write;
 
-/// Show this menu
+use poise::serenity_prelude as serenity;
+use serenity::{
+    ButtonStyle, CollectComponentInteraction, CreateComponents, CreateEmbed,
+    InteractionResponseType, Permissions,
+};
+
+use crate::commands::{visible_names, Command};
+use crate::overrides::{self, find_command};
+use crate::paginate::TIMEOUT;
+use crate::{cooldowns, Context, Error};
+
+register_command!(help);
+
+/// Commands per page of the menu
+const COMMANDS_PER_PAGE: usize = 10;
+/// Autocomplete only offers this many commands at once, the most discord allows
+const SUGGESTIONS: usize = 25;
+/// Commands without a category are listed under this name
+const GENERAL: &str = "General";
+
+const FOOTER: &str = "Use /help <command> for more on a command. You can edit most command \
+                      messages and the bot will edit its response.";
+
+/// Show what the bot can do, or everything about one command
 #[poise::command(slash_command, prefix_command, track_edits)]
 pub async fn help(
     ctx: Context<'_>,
-    #[description = "Specific command to show help about"] command: Option<String>,
+    #[description = "A command to show everything about, like `prefix add`"]
+    #[autocomplete = "autocomplete_command"]
+    #[rest]
+    command: Option<String>,
 ) -> Result<(), Error> {
-    let config = poise::builtins::HelpConfiguration {
-        extra_text_at_bottom: "\
-Use \"/help command\" for more info on a command.
-You can edit most command messages and the bot will edit its response.",
-        ..Default::default()
+    // Looked up once, every command that is listed needs them
+    let permissions = author_permissions(ctx).await;
+    let Some(command) = command else {
+        return menu(ctx, permissions).await;
     };
-    poise::builtins::help(ctx, command.as_deref(), config).await?;
+
+    match usable_path(ctx, &command, permissions).await? {
+        Some(path) => {
+            let embed = details(ctx, &path, permissions).await?;
+            ctx.send(|b| {
+                b.embeds.push(embed);
+                b.ephemeral(true)
+            })
+            .await?;
+        }
+        // Commands the user can't use are treated as if they don't exist
+        None => {
+            ctx.send(|b| {
+                b.content(format!("No such command `{command}`"))
+                    .ephemeral(true)
+            })
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// The commands of a category the user can use, each as the path from its top-level command
+struct Category<'a> {
+    name: &'a str,
+    commands: Vec<Vec<&'a Command>>,
+}
+
+impl Category<'_> {
+    fn pages(&self) -> usize {
+        self.commands.len().div_ceil(COMMANDS_PER_PAGE).max(1)
+    }
+}
+
+/// What the menu is showing
+#[derive(Clone, Copy, Default)]
+struct State {
+    category: usize,
+    page: usize,
+    /// Index of the command in the category whose details are shown
+    details: Option<usize>,
+}
+
+/// Every category with a select menu to switch between them, buttons to flip through the pages
+/// and a select menu to show the details of a command
+async fn menu(ctx: Context<'_>, permissions: Option<Permissions>) -> Result<(), Error> {
+    let categories = categories(ctx, permissions).await?;
+    if categories.is_empty() {
+        ctx.send(|b| {
+            b.content("There are no commands you can use here")
+                .ephemeral(true)
+        })
+        .await?;
+        return Ok(());
+    }
+
+    // The context id keeps the components apart from those of other invocations
+    let prefix = ctx.id().to_string();
+    let mut state = State::default();
+
+    let (embed, components) = render(ctx, &categories, state, &prefix, permissions).await?;
+    let reply = ctx
+        .send(|b| {
+            b.embeds.push(embed);
+            b.components = Some(components);
+            b.ephemeral(true)
+        })
+        .await?;
+
+    loop {
+        let (filter_prefix, author) = (prefix.clone(), ctx.author().id);
+        let collect = CollectComponentInteraction::new(ctx.serenity_context())
+            .filter(move |press| {
+                press.data.custom_id.starts_with(&filter_prefix) && press.user.id == author
+            })
+            .timeout(TIMEOUT);
+        // A shutdown waits for the command, it shouldn't wait for someone to close the menu
+        let press = tokio::select! {
+            press = collect => press,
+            () = ctx.data().shutdown.stopped() => None,
+        };
+        let Some(press) = press else {
+            break;
+        };
+
+        let value = press.data.values.first().and_then(|x| x.parse().ok());
+        let pages = categories[state.category].pages();
+        match &press.data.custom_id[prefix.len()..] {
+            "category" => {
+                state = State {
+                    category: value.unwrap_or(0).min(categories.len() - 1),
+                    ..State::default()
+                }
+            }
+            "command" => state.details = value,
+            "previous" => state.page = state.page.checked_sub(1).unwrap_or(pages - 1),
+            "next" => state.page = (state.page + 1) % pages,
+            "back" => state.details = None,
+            _ => {}
+        }
+
+        let (embed, components) = render(ctx, &categories, state, &prefix, permissions).await?;
+        press
+            .create_interaction_response(ctx.serenity_context(), |r| {
+                r.kind(InteractionResponseType::UpdateMessage)
+                    .interaction_response_data(|d| d.set_embed(embed).set_components(components))
+            })
+            .await?;
+    }
+
+    // Components that do nothing anymore would only confuse
+    let (embed, _) = render(ctx, &categories, state, &prefix, permissions).await?;
+    reply
+        .edit(ctx, |b| {
+            b.embeds.push(embed);
+            b.components(|c| c)
+        })
+        .await?;
     Ok(())
 }
+
+async fn render(
+    ctx: Context<'_>,
+    categories: &[Category<'_>],
+    state: State,
+    prefix: &str,
+    permissions: Option<Permissions>,
+) -> Result<(CreateEmbed, CreateComponents), Error> {
+    let category = &categories[state.category];
+    let mut components = CreateComponents::default();
+
+    if let Some(path) = state.details.and_then(|x| category.commands.get(x)) {
+        components.create_action_row(|r| {
+            r.create_button(|b| {
+                b.custom_id(format!("{prefix}back"))
+                    .label("Back")
+                    .style(ButtonStyle::Secondary)
+            })
+        });
+        return Ok((details(ctx, path, permissions).await?, components));
+    }
+
+    let page: &[Vec<&Command>] = category
+        .commands
+        .chunks(COMMANDS_PER_PAGE)
+        .nth(state.page)
+        .unwrap_or_default();
+    let first = state.page * COMMANDS_PER_PAGE;
+
+    let mut description = String::new();
+    for path in page {
+        let command = path[path.len() - 1];
+        let text = command.description.as_deref().unwrap_or("");
+        writeln!(
+            description,
+            "`{}{}` {text}",
+            prefix_of(ctx, command),
+            command.qualified_name
+        )
+        .unwrap();
+    }
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(format!("{} commands", category.name))
+        .description(description)
+        .footer(|f| {
+            f.text(format!(
+                "Page {} of {}. {FOOTER}",
+                state.page + 1,
+                category.pages()
+            ))
+        });
+
+    components
+        .create_action_row(|r| {
+            r.create_select_menu(|m| {
+                m.custom_id(format!("{prefix}category"))
+                    .placeholder("Pick a category")
+                    .options(|o| {
+                        for (i, x) in categories.iter().enumerate() {
+                            o.create_option(|o| {
+                                o.label(x.name)
+                                    .value(i)
+                                    .default_selection(i == state.category)
+                            });
+                        }
+                        o
+                    })
+            })
+        })
+        .create_action_row(|r| {
+            r.create_select_menu(|m| {
+                m.custom_id(format!("{prefix}command"))
+                    .placeholder("Show everything about a command")
+                    .options(|o| {
+                        for (i, path) in page.iter().enumerate() {
+                            let command = path[path.len() - 1];
+                            o.create_option(|o| {
+                                o.label(format!("/{}", command.qualified_name))
+                                    .value(first + i)
+                            });
+                        }
+                        o
+                    })
+            })
+        })
+        .create_action_row(|r| {
+            let single = category.pages() == 1;
+            r.create_button(|b| {
+                b.custom_id(format!("{prefix}previous"))
+                    .label("Previous")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(single)
+            })
+            .create_button(|b| {
+                b.custom_id(format!("{prefix}next"))
+                    .label("Next")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(single)
+            })
+        });
+
+    Ok((embed, components))
+}
+
+/// Everything about a command: how to use it, its parameters, examples, who can use it and its
+/// cooldowns in this server
+async fn details(
+    ctx: Context<'_>,
+    path: &[&Command],
+    permissions: Option<Permissions>,
+) -> Result<CreateEmbed, Error> {
+    let command = path[path.len() - 1];
+    let prefix = prefix_of(ctx, command);
+    let (text, examples) = split_examples(command.help_text.map(|f| f()).unwrap_or_default());
+
+    let mut embed = CreateEmbed::default();
+    let mut description = command
+        .description
+        .clone()
+        .unwrap_or_else(|| "No help available".to_string());
+    if !text.is_empty() {
+        write!(description, "\n\n{text}").unwrap();
+    }
+    embed
+        .title(format!("{prefix}{}", command.qualified_name))
+        .description(description);
+
+    let mut usage = format!("{prefix}{}", command.qualified_name);
+    for parameter in &command.parameters {
+        if parameter.required {
+            write!(usage, " <{}>", parameter.name).unwrap();
+        } else {
+            write!(usage, " [{}]", parameter.name).unwrap();
+        }
+    }
+    embed.field("Usage", format!("`{usage}`"), false);
+
+    if !command.parameters.is_empty() {
+        let mut parameters = String::new();
+        for parameter in &command.parameters {
+            let text = parameter.description.as_deref().unwrap_or("");
+            let optional = if parameter.required {
+                ""
+            } else {
+                " (optional)"
+            };
+            writeln!(parameters, "`{}`{optional}: {text}", parameter.name).unwrap();
+        }
+        embed.field("Parameters", parameters, false);
+    }
+
+    let mut subcommands = String::new();
+    for subcommand in &command.subcommands {
+        let mut sub_path = path.to_vec();
+        sub_path.push(subcommand);
+        if can_use(ctx, &sub_path, permissions).await? {
+            let text = subcommand.description.as_deref().unwrap_or("");
+            writeln!(
+                subcommands,
+                "`{prefix}{}` {text}",
+                subcommand.qualified_name
+            )
+            .unwrap();
+        }
+    }
+    if !subcommands.is_empty() {
+        embed.field("Subcommands", subcommands, false);
+    }
+
+    if !examples.is_empty() {
+        embed.field("Examples", examples.join("\n"), false);
+    }
+
+    // Groups pass their requirements on to their subcommands
+    let mut user_permissions = Permissions::empty();
+    let mut bot_permissions = Permissions::empty();
+    let mut requirements = Vec::new();
+    for x in path {
+        user_permissions |= x.required_permissions;
+        bot_permissions |= x.required_bot_permissions;
+    }
+    if !user_permissions.is_empty() {
+        requirements.push(format!("You need {user_permissions}"));
+    }
+    if !bot_permissions.is_empty() {
+        requirements.push(format!("The bot needs {bot_permissions}"));
+    }
+    if path.iter().any(|x| x.guild_only) {
+        requirements.push("Only in servers".to_string());
+    }
+    if path.iter().any(|x| x.dm_only) {
+        requirements.push("Only in DMs".to_string());
+    }
+    if path.iter().any(|x| x.nsfw_only) {
+        requirements.push("Only in NSFW channels".to_string());
+    }
+    if !requirements.is_empty() {
+        embed.field("Requirements", requirements.join("\n"), false);
+    }
+
+    let data = ctx.data();
+    let cooldowns = data
+        .cooldowns
+        .config(&data.db, ctx.guild_id(), &command.qualified_name)
+        .await?;
+    if !cooldowns.is_empty() {
+        embed.field("Cooldown", cooldowns::describe(&cooldowns), false);
+    }
+
+    Ok(embed)
+}
+
+/// Splits the help text of a command into the text and the lines after `Examples:`
+fn split_examples(help_text: String) -> (String, Vec<String>) {
+    let mut text = Vec::new();
+    let mut examples = Vec::new();
+    let mut in_examples = false;
+    for line in help_text.lines() {
+        if line.trim().eq_ignore_ascii_case("examples:") {
+            in_examples = true;
+        } else if in_examples && !line.trim().is_empty() {
+            examples.push(line.trim().to_string());
+        } else if !in_examples {
+            text.push(line);
+        }
+    }
+    (text.join("\n").trim().to_string(), examples)
+}
+
+/// Every command the user can use here by category, commands without a category first
+async fn categories(
+    ctx: Context<'_>,
+    permissions: Option<Permissions>,
+) -> Result<Vec<Category<'_>>, Error> {
+    // Groups are listed by their subcommands since only those can be used as slash commands
+    fn leaves<'a>(
+        commands: &'a [Command],
+        path: &mut Vec<&'a Command>,
+        out: &mut Vec<Vec<&'a Command>>,
+    ) {
+        for command in commands {
+            path.push(command);
+            if command.subcommands.is_empty() {
+                out.push(path.clone());
+            } else {
+                leaves(&command.subcommands, path, out);
+            }
+            path.pop();
+        }
+    }
+
+    let mut paths = Vec::new();
+    leaves(
+        &ctx.framework().options().commands,
+        &mut Vec::new(),
+        &mut paths,
+    );
+
+    let mut categories: Vec<Category> = Vec::new();
+    for path in paths {
+        if !can_use(ctx, &path, permissions).await? {
+            continue;
+        }
+        let name = overrides::category(&path).unwrap_or(GENERAL);
+        match categories.iter_mut().find(|x| x.name == name) {
+            Some(category) => category.commands.push(path),
+            None => categories.push(Category {
+                name,
+                commands: vec![path],
+            }),
+        }
+    }
+    categories.sort_by_key(|x| (x.name != GENERAL, x.name));
+
+    Ok(categories)
+}
+
+/// The command the user means (like `prefix add`) from its top-level command on, if they can use it
+async fn usable_path<'a>(
+    ctx: Context<'a>,
+    name: &str,
+    permissions: Option<Permissions>,
+) -> Result<Option<Vec<&'a Command>>, Error> {
+    let words: Vec<_> = name.trim_start_matches('/').split_whitespace().collect();
+    let commands = &ctx.framework().options().commands;
+
+    let mut path = Vec::new();
+    for i in 1..=words.len() {
+        match find_command(commands, &words[..i]) {
+            Some(command) => path.push(command),
+            None => return Ok(None),
+        }
+    }
+    if path.is_empty() || !can_use(ctx, &path, permissions).await? {
+        return Ok(None);
+    }
+    Ok(Some(path))
+}
+
+/// Whether the last command of `path` should be shown to the user, who has `permissions` from
+/// [`author_permissions`]
+async fn can_use(
+    ctx: Context<'_>,
+    path: &[&Command],
+    permissions: Option<Permissions>,
+) -> Result<bool, Error> {
+    let owner = ctx.framework().options().owners.contains(&ctx.author().id);
+    let in_guild = ctx.guild_id().is_some();
+    let mut required = Permissions::empty();
+    for command in path {
+        if command.hide_in_help || (command.owners_only && !owner) {
+            return Ok(false);
+        }
+        // Context menu commands can't be used by name
+        if command.slash_action.is_none() && command.prefix_action.is_none() {
+            return Ok(false);
+        }
+        // Poise checks these for owners too
+        if (command.guild_only && !in_guild) || (command.dm_only && in_guild) {
+            return Ok(false);
+        }
+        required |= command.required_permissions;
+    }
+
+    if !required.is_empty() && !permissions.is_some_and(|x| x.contains(required)) {
+        return Ok(false);
+    }
+    Ok(owner || overrides::denial(ctx, path).await?.is_none())
+}
+
+/// What the author may do in the channel, everything in DMs and `None` if discord doesn't say.
+/// Poise looks them up over HTTP the same way before running a command.
+async fn author_permissions(ctx: Context<'_>) -> Option<Permissions> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Some(Permissions::all());
+    };
+    // Interactions come with them, already worked out for the channel
+    if let Context::Application(ctx) = ctx {
+        if let Some(permissions) = ctx.interaction.member().and_then(|x| x.permissions) {
+            return Some(permissions);
+        }
+    }
+
+    // The cache knows them for guilds the bot got from the gateway, threads aren't in there
+    let cached = ctx.serenity_context().cache.guild_field(guild_id, |guild| {
+        let channel = match guild.channels.get(&ctx.channel_id())? {
+            serenity::Channel::Guild(channel) => channel,
+            _ => return None,
+        };
+        let member = guild.members.get(&ctx.author().id)?;
+        guild.user_permissions_in(channel, member).ok()
+    });
+    if let Some(permissions) = cached.flatten() {
+        return Some(permissions);
+    }
+
+    let guild = guild_id.to_partial_guild(ctx).await.ok()?;
+    let channel = ctx.channel_id().to_channel(ctx).await.ok()?.guild()?;
+    let member = guild.member(ctx, ctx.author().id).await.ok()?;
+    guild.user_permissions_in(&channel, &member).ok()
+}
+
+fn prefix_of(ctx: Context<'_>, command: &Command) -> String {
+    if command.slash_action.is_some() {
+        "/".to_string()
+    } else {
+        ctx.prefix().to_string()
+    }
+}
+
+async fn autocomplete_command<'a>(
+    ctx: Context<'a>,
+    partial: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    let partial = partial.to_lowercase();
+    let permissions = author_permissions(ctx).await;
+
+    let mut names = Vec::new();
+    for name in visible_names(&ctx.framework().options().commands) {
+        if !name.to_lowercase().contains(&partial) {
+            continue;
+        }
+        if let Ok(Some(_)) = usable_path(ctx, &name, permissions).await {
+            names.push(name);
+        }
+        if names.len() == SUGGESTIONS {
+            break;
+        }
+    }
+    names.into_iter()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use poise::serenity_prelude::Permissions;
+    use sqlx::PgPool;
+
+    use crate::overrides::Target;
+    use crate::testing::{Harness, Reply, GUILD_ID};
+
+    /// The value of the embed field called `name`
+    fn field<'a>(embed: &'a Value, name: &str) -> Option<&'a str> {
+        embed["fields"]
+            .as_array()?
+            .iter()
+            .find(|x| x["name"] == name)?["value"]
+            .as_str()
+    }
+
+    #[sqlx::test]
+    async fn opens_the_menu(db: PgPool) {
+        let harness = Harness::new(db)
+            .await
+            .in_guild()
+            .with_permissions(Permissions::all());
+        let replies = harness.slash("help", &[]).await;
+
+        // The menu, and the same menu without its components once nobody uses it anymore
+        assert_eq!(replies.len(), 2);
+        let menu = &replies[0];
+        assert!(menu.ephemeral);
+        assert_eq!(menu.embeds[0]["title"], "General commands");
+        assert!(menu.embeds[0]["footer"]["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("Page 1 of 2."));
+
+        let labels = menu.labels();
+        for label in ["General", "Moderation", "/help", "/pong", "Previous", "Next"] {
+            assert!(labels.iter().any(|x| x == label), "no {label} in {labels:?}");
+        }
+        assert!(!labels.iter().any(|x| x.starts_with("/owner")));
+
+        let closed = &replies[1];
+        assert!(closed.edit);
+        assert!(closed.components.is_empty());
+        assert_eq!(closed.embeds, menu.embeds);
+    }
+
+    #[sqlx::test]
+    async fn shows_a_command(db: PgPool) {
+        let harness = Harness::new(db).await;
+
+        let replies = harness.slash("help", &[("command", json!("pong"))]).await;
+        assert_eq!(replies.len(), 1);
+        assert!(replies[0].ephemeral);
+        let embed = &replies[0].embeds[0];
+        assert_eq!(embed["title"], "/pong");
+        assert_eq!(field(embed, "Usage"), Some("`/pong`"));
+        assert_eq!(field(embed, "Cooldown"), Some("5s per user"));
+
+        let replies = harness.prefix("help remind me").await;
+        let embed = &replies[0].embeds[0];
+        assert_eq!(embed["title"], "/remind me");
+        assert_eq!(
+            field(embed, "Usage"),
+            Some("`/remind me <when> <what> [every] [dm]`")
+        );
+        assert!(field(embed, "Examples").is_some());
+    }
+
+    #[sqlx::test]
+    async fn hides_disabled_commands(db: PgPool) {
+        let harness = Harness::new(db.clone()).await.in_guild();
+        let data = &harness.data;
+        let pong = Target::Command("pong".to_string());
+        data.overrides
+            .set_disabled(&data.db, GUILD_ID, &pong, true)
+            .await
+            .unwrap();
+
+        let replies = harness.slash("help", &[("command", json!("pong"))]).await;
+        assert_eq!(replies[0].content.as_deref(), Some("No such command `pong`"));
+        let replies = harness.slash("help", &[]).await;
+        assert!(!replies[0].labels().iter().any(|x| x == "/pong"));
+
+        // Owners can use every command
+        let harness = Harness::new(db).await.in_guild().by_owner();
+        let replies = harness.slash("help", &[("command", json!("pong"))]).await;
+        assert_eq!(replies[0].embeds[0]["title"], "/pong");
+    }
+
+    #[sqlx::test]
+    async fn hides_commands_the_member_cannot_use(db: PgPool) {
+        let title = |replies: Vec<Reply>| replies[0].embeds.first().map(|x| x["title"].clone());
+
+        // Moderation only works in servers
+        let harness = Harness::new(db.clone()).await;
+        let replies = harness.slash("help", &[("command", json!("ban"))]).await;
+        assert_eq!(replies[0].content.as_deref(), Some("No such command `ban`"));
+
+        let harness = Harness::new(db.clone()).await.in_guild();
+        for command in ["ban", "purge", "commands disable"] {
+            let replies = harness.slash("help", &[("command", json!(command))]).await;
+            assert_eq!(title(replies), None, "/{command} is shown");
+        }
+        // Looking doesn't take any permissions
+        let replies = harness.slash("help", &[("command", json!("commands list"))]).await;
+        assert_eq!(title(replies), Some(json!("/commands list")));
+        let replies = harness.slash("help", &[]).await;
+        assert!(!replies[0].labels().iter().any(|x| x == "Moderation"));
+
+        let harness = harness.with_permissions(Permissions::BAN_MEMBERS);
+        let replies = harness.slash("help", &[("command", json!("ban"))]).await;
+        assert_eq!(title(replies), Some(json!("/ban")));
+        let replies = harness.slash("help", &[("command", json!("purge"))]).await;
+        assert_eq!(title(replies), None);
+    }
+
+    #[sqlx::test]
+    async fn looks_up_the_permissions_once(db: PgPool) {
+        let harness = Harness::new(db).await.in_guild();
+        harness.prefix("help").await;
+
+        let lookups = harness
+            .requests()
+            .into_iter()
+            .filter(|x| x.path == format!("/guilds/{GUILD_ID}"))
+            .count();
+        assert_eq!(lookups, 1);
+    }
+}
@@ -0,0 +1,530 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use poise::serenity_prelude as serenity;
+use serenity::{Member, Mentionable, Role, User, UserId};
+use tracing::debug;
+
+use crate::cases::{CaseAction, NewCase};
+use crate::duration::HumanDuration;
+use crate::error::BotError;
+use crate::guild_settings;
+use crate::scheduler::{self, ScheduledAction};
+use crate::{Context, Error};
+
+register_command!(warn);
+register_command!(timeout);
+register_command!(kick);
+register_command!(ban);
+register_command!(unban);
+register_command!(mute);
+register_command!(unmute);
+register_command!(muterole);
+register_command!(purge);
+
+cooldown!("purge", Channel = 10);
+
+/// Discord doesn't allow timeouts longer than 28 days
+const MAX_TIMEOUT: Duration = Duration::from_secs(60 * 60 * 24 * 28);
+
+/// Warn a member
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    category = "Moderation",
+    required_permissions = "MODERATE_MEMBERS"
+)]
+pub async fn warn(
+    ctx: Context<'_>,
+    #[description = "The user to warn"] user: User,
+    #[description = "Why they are warned"]
+    #[rest]
+    reason: Option<String>,
+) -> Result<(), Error> {
+    check_hierarchy(ctx, user.id).await?;
+
+    notify(ctx, &user, CaseAction::Warn, reason.as_deref()).await;
+    record(ctx, CaseAction::Warn, Some(&user), reason.as_deref(), None).await?;
+    Ok(())
+}
+
+/// Time out a member so they can't talk or react
+///
+/// Examples:
+/// `/timeout @someone 10m`
+/// `/timeout @someone 1h30m Spamming in #general`
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    category = "Moderation",
+    required_permissions = "MODERATE_MEMBERS",
+    required_bot_permissions = "MODERATE_MEMBERS"
+)]
+pub async fn timeout(
+    ctx: Context<'_>,
+    #[description = "The member to time out"] mut member: Member,
+    #[description = "How long, like 10m or 1h30m (at most 28d)"] duration: HumanDuration,
+    #[description = "Why they are timed out"]
+    #[rest]
+    reason: Option<String>,
+) -> Result<(), Error> {
+    if duration.0 > MAX_TIMEOUT {
+        return Err(BotError::user("Timeouts can't be longer than 28 days"));
+    }
+    check_hierarchy(ctx, member.user.id).await?;
+
+    let until = serenity::Timestamp::from_unix_timestamp(
+        ctx.created_at().unix_timestamp() + duration.0.as_secs() as i64,
+    )?;
+
+    notify(ctx, &member.user, CaseAction::Timeout, reason.as_deref()).await;
+    member
+        .disable_communication_until_datetime(ctx, until)
+        .await?;
+
+    let user = member.user.clone();
+    record(
+        ctx,
+        CaseAction::Timeout,
+        Some(&user),
+        reason.as_deref(),
+        Some(duration.0),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Kick a member from the server
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    category = "Moderation",
+    required_permissions = "KICK_MEMBERS",
+    required_bot_permissions = "KICK_MEMBERS"
+)]
+pub async fn kick(
+    ctx: Context<'_>,
+    #[description = "The member to kick"] member: Member,
+    #[description = "Why they are kicked"]
+    #[rest]
+    reason: Option<String>,
+) -> Result<(), Error> {
+    check_hierarchy(ctx, member.user.id).await?;
+
+    // Has to happen before the kick since we can't message people without a shared server
+    notify(ctx, &member.user, CaseAction::Kick, reason.as_deref()).await;
+
+    let guild_id = member.guild_id;
+    match &reason {
+        Some(reason) => guild_id.kick_with_reason(ctx, &member, reason).await?,
+        None => guild_id.kick(ctx, &member).await?,
+    }
+
+    record(
+        ctx,
+        CaseAction::Kick,
+        Some(&member.user),
+        reason.as_deref(),
+        None,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Ban a user from the server
+///
+/// Examples:
+/// `/ban @someone`
+/// `/ban @someone 2w 1 Raiding`
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    category = "Moderation",
+    required_permissions = "BAN_MEMBERS",
+    required_bot_permissions = "BAN_MEMBERS"
+)]
+pub async fn ban(
+    ctx: Context<'_>,
+    #[description = "The user to ban"] user: User,
+    #[description = "Unban them again after this long, like 1d or 2w"] duration: Option<
+        HumanDuration,
+    >,
+    #[description = "Delete their messages of the last days (0 to 7)"]
+    #[min = 0]
+    #[max = 7]
+    delete_message_days: Option<u8>,
+    #[description = "Why they are banned"]
+    #[rest]
+    reason: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    check_hierarchy(ctx, user.id).await?;
+
+    let delete_message_days = delete_message_days.unwrap_or(0).min(7);
+
+    notify(ctx, &user, CaseAction::Ban, reason.as_deref()).await;
+    match &reason {
+        Some(reason) => {
+            guild_id
+                .ban_with_reason(ctx, &user, delete_message_days, reason)
+                .await?
+        }
+        None => guild_id.ban(ctx, &user, delete_message_days).await?,
+    }
+
+    let duration = duration.map(|x| x.0);
+    let case_number = record(
+        ctx,
+        CaseAction::Ban,
+        Some(&user),
+        reason.as_deref(),
+        duration,
+    )
+    .await?;
+
+    let db = &ctx.data().db;
+    match duration {
+        Some(duration) => {
+            let execute_at =
+                ctx.created_at().with_timezone(&Utc) + chrono::Duration::from_std(duration)?;
+            scheduler::schedule(
+                db,
+                guild_id,
+                user.id,
+                ScheduledAction::Unban,
+                execute_at,
+                case_number,
+            )
+            .await?;
+        }
+        // A permanent ban overrides the end of an earlier temporary one
+        None => scheduler::cancel(db, guild_id, user.id, ScheduledAction::Unban).await?,
+    }
+    Ok(())
+}
+
+/// Unban a user from the server
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    category = "Moderation",
+    required_permissions = "BAN_MEMBERS",
+    required_bot_permissions = "BAN_MEMBERS"
+)]
+pub async fn unban(
+    ctx: Context<'_>,
+    #[description = "The user to unban"] user: User,
+    #[description = "Why they are unbanned"]
+    #[rest]
+    reason: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+
+    match guild_id.unban(ctx, &user).await {
+        Ok(()) => {}
+        // Unknown Ban, anything else (like missing permissions) is a real error
+        Err(err) if scheduler::is_not_found(&err) => {
+            return Err(BotError::user(format!("{} is not banned", user.tag())));
+        }
+        Err(err) => return Err(err.into()),
+    }
+    scheduler::cancel(&ctx.data().db, guild_id, user.id, ScheduledAction::Unban).await?;
+
+    record(ctx, CaseAction::Unban, Some(&user), reason.as_deref(), None).await?;
+    Ok(())
+}
+
+/// Mute a member by giving them the mute role of the server
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    category = "Moderation",
+    required_permissions = "MODERATE_MEMBERS",
+    required_bot_permissions = "MANAGE_ROLES"
+)]
+pub async fn mute(
+    ctx: Context<'_>,
+    #[description = "The member to mute"] mut member: Member,
+    #[description = "Unmute them again after this long, like 30m or 1d"] duration: Option<
+        HumanDuration,
+    >,
+    #[description = "Why they are muted"]
+    #[rest]
+    reason: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = member.guild_id;
+    let db = &ctx.data().db;
+
+    let role_id = guild_settings::mute_role(db, guild_id)
+        .await?
+        .ok_or_else(|| BotError::user("This server has no mute role, set one with `muterole`"))?;
+    check_hierarchy(ctx, member.user.id).await?;
+
+    notify(ctx, &member.user, CaseAction::Mute, reason.as_deref()).await;
+    member.add_role(ctx, role_id).await?;
+
+    let user = member.user.clone();
+    let duration = duration.map(|x| x.0);
+    let case_number = record(
+        ctx,
+        CaseAction::Mute,
+        Some(&user),
+        reason.as_deref(),
+        duration,
+    )
+    .await?;
+
+    let action = ScheduledAction::RemoveRole(role_id);
+    match duration {
+        Some(duration) => {
+            let execute_at =
+                ctx.created_at().with_timezone(&Utc) + chrono::Duration::from_std(duration)?;
+            scheduler::schedule(db, guild_id, user.id, action, execute_at, case_number).await?;
+        }
+        None => scheduler::cancel(db, guild_id, user.id, action).await?,
+    }
+    Ok(())
+}
+
+/// Unmute a member by taking away the mute role of the server
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    category = "Moderation",
+    required_permissions = "MODERATE_MEMBERS",
+    required_bot_permissions = "MANAGE_ROLES"
+)]
+pub async fn unmute(
+    ctx: Context<'_>,
+    #[description = "The member to unmute"] mut member: Member,
+    #[description = "Why they are unmuted"]
+    #[rest]
+    reason: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = member.guild_id;
+    let db = &ctx.data().db;
+
+    let role_id = match guild_settings::mute_role(db, guild_id).await? {
+        Some(role_id) if member.roles.contains(&role_id) => role_id,
+        _ => {
+            return Err(BotError::user(format!(
+                "{} is not muted",
+                member.user.tag()
+            )))
+        }
+    };
+
+    member.remove_role(ctx, role_id).await?;
+    scheduler::cancel(
+        db,
+        guild_id,
+        member.user.id,
+        ScheduledAction::RemoveRole(role_id),
+    )
+    .await?;
+
+    let user = member.user.clone();
+    record(
+        ctx,
+        CaseAction::Unmute,
+        Some(&user),
+        reason.as_deref(),
+        None,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Set the role that muted members get
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    category = "Moderation",
+    required_permissions = "MANAGE_GUILD | MANAGE_ROLES"
+)]
+pub async fn muterole(
+    ctx: Context<'_>,
+    #[description = "The role muted members get"] role: Role,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    guild_settings::set_mute_role(&ctx.data().db, guild_id, role.id).await?;
+
+    ctx.say(format!("Muted members will now get {}", role.mention()))
+        .await?;
+    Ok(())
+}
+
+/// Delete recent messages in this channel
+///
+/// Examples:
+/// `/purge 50`
+/// `/purge 100 @someone`
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    category = "Moderation",
+    required_permissions = "MANAGE_MESSAGES",
+    required_bot_permissions = "MANAGE_MESSAGES | READ_MESSAGE_HISTORY"
+)]
+pub async fn purge(
+    ctx: Context<'_>,
+    #[description = "How many messages to look through (1 to 100)"]
+    #[min = 1]
+    #[max = 100]
+    amount: u8,
+    #[description = "Only delete messages of this user"] user: Option<User>,
+) -> Result<(), Error> {
+    let amount = amount.clamp(1, 100);
+    let channel_id = ctx.channel_id();
+
+    let messages = match ctx {
+        // The invoking message is not one of the messages that should be looked through
+        poise::Context::Prefix(prefix) => {
+            channel_id
+                .messages(ctx, |b| b.before(prefix.msg.id).limit(amount as u64))
+                .await?
+        }
+        poise::Context::Application(_) => {
+            channel_id.messages(ctx, |b| b.limit(amount as u64)).await?
+        }
+    };
+
+    // Discord refuses to bulk delete messages older than two weeks
+    let two_weeks_ago = ctx.created_at().unix_timestamp() - 60 * 60 * 24 * 14;
+    let to_delete: Vec<_> = messages
+        .iter()
+        .filter(|msg| user.as_ref().is_none_or(|user| msg.author.id == user.id))
+        .filter(|msg| msg.timestamp.unix_timestamp() > two_weeks_ago)
+        .map(|msg| msg.id)
+        .collect();
+
+    match to_delete.as_slice() {
+        [] => return Err(BotError::user("There are no messages to delete")),
+        [single] => channel_id.delete_message(ctx, single).await?,
+        many => channel_id.delete_messages(ctx, many).await?,
+    }
+
+    let reason = format!(
+        "Deleted {} messages in {}",
+        to_delete.len(),
+        channel_id.mention()
+    );
+    record(ctx, CaseAction::Purge, user.as_ref(), Some(&reason), None).await?;
+    Ok(())
+}
+
+/// Makes sure the action is not aimed at someone the moderator (or the bot) should not be able to
+/// act on: themselves, the bot, the owner or anyone with an equal or higher role
+pub async fn check_hierarchy(ctx: Context<'_>, target: UserId) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let author = ctx.author().id;
+    let bot = ctx.framework().bot_id;
+
+    if target == author {
+        return Err(BotError::user("You can't do that to yourself"));
+    }
+    if target == bot {
+        return Err(BotError::user("I can't do that to myself"));
+    }
+
+    let guild = guild_id.to_partial_guild(ctx).await?;
+    if target == guild.owner_id {
+        return Err(BotError::permission(
+            "Nobody can do that to the server owner",
+        ));
+    }
+
+    // Users that are not in the server have no roles to compare against
+    let target = match guild.member(ctx, target).await {
+        Ok(member) => member,
+        Err(_) => return Ok(()),
+    };
+
+    let position = |member: &Member| {
+        member
+            .roles
+            .iter()
+            .filter_map(|role| guild.roles.get(role))
+            .map(|role| role.position)
+            .max()
+            .unwrap_or(0)
+    };
+
+    if author != guild.owner_id {
+        let author = guild.member(ctx, author).await?;
+        if position(&author) <= position(&target) {
+            return Err(BotError::permission(
+                "You can only do that to members whose highest role is below yours",
+            ));
+        }
+    }
+
+    let bot = guild.member(ctx, bot).await?;
+    if position(&bot) <= position(&target) {
+        return Err(BotError::permission(
+            "My highest role has to be above the highest role of that member",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Lets the user know what happened to them, which fails if they don't accept DMs (that's fine)
+async fn notify(ctx: Context<'_>, user: &User, action: CaseAction, reason: Option<&str>) {
+    let guild_name = ctx
+        .guild()
+        .map(|guild| guild.name)
+        .unwrap_or_else(|| "a server".to_string());
+    let reason = reason.map(|x| format!(": {x}")).unwrap_or_default();
+    let content = format!(
+        "You were {} in {guild_name}{reason}",
+        action.past_tense().to_lowercase()
+    );
+
+    if let Err(err) = user.direct_message(ctx, |m| m.content(content)).await {
+        debug!("Could not notify {} about a {action}: {err}", user.id);
+    }
+}
+
+/// Saves the case and tells the moderator about it, returns the number of the case
+pub async fn record(
+    ctx: Context<'_>,
+    action: CaseAction,
+    target: Option<&User>,
+    reason: Option<&str>,
+    duration: Option<Duration>,
+) -> Result<i32, Error> {
+    let case = NewCase {
+        guild_id: ctx.guild_id().unwrap(),
+        action,
+        moderator_id: ctx.author().id,
+        target_id: target.map(|x| x.id),
+        reason,
+        duration,
+    };
+    let case_number = case.create(&ctx.data().db).await?;
+
+    let target = target
+        .map(|x| format!(" {}", x.mention()))
+        .unwrap_or_default();
+    let duration = duration
+        .map(|x| format!(" for {}", HumanDuration(x)))
+        .unwrap_or_default();
+    let reason = reason.map(|x| format!(": {x}")).unwrap_or_default();
+
+    ctx.say(format!(
+        "Case #{case_number}: {}{target}{duration}{reason}",
+        action.past_tense()
+    ))
+    .await?;
+    Ok(case_number)
+}
@@ -0,0 +1,218 @@
+use poise::serenity_prelude as serenity;
+use serenity::{GuildChannel, Role};
+
+use crate::error::BotError;
+use crate::overrides::{self, Target, MANAGE_COMMAND};
+use crate::{Context, Error};
+
+register_command!(commands);
+
+/// Autocomplete only offers this many commands at once, the most discord allows
+const SUGGESTIONS: usize = 25;
+
+/// Turn commands off in this server or restrict them to channels and roles
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    subcommands("disable", "enable", "restrict", "list")
+)]
+pub async fn commands(ctx: Context<'_>) -> Result<(), Error> {
+    // Only reachable as a prefix command since discord doesn't let you invoke a group directly
+    list_overrides(ctx).await
+}
+
+/// Turn a command or a whole category off in this server
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn disable(
+    ctx: Context<'_>,
+    #[description = "A command (like `prefix add`) or category"]
+    #[autocomplete = "autocomplete_target"]
+    #[rest]
+    target: String,
+) -> Result<(), Error> {
+    let target = resolve(ctx, &target)?;
+
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let response = if data
+        .overrides
+        .set_disabled(&data.db, guild_id, &target, true)
+        .await?
+    {
+        format!("Turned off {target}")
+    } else {
+        format!("{target} is already turned off")
+    };
+
+    ctx.say(response).await?;
+    Ok(())
+}
+
+/// Turn a command or category back on in this server
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn enable(
+    ctx: Context<'_>,
+    #[description = "A command (like `prefix add`) or category"]
+    #[autocomplete = "autocomplete_target"]
+    #[rest]
+    target: String,
+) -> Result<(), Error> {
+    let target = resolve(ctx, &target)?;
+
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let response = if data
+        .overrides
+        .set_disabled(&data.db, guild_id, &target, false)
+        .await?
+    {
+        format!("Turned on {target}")
+    } else {
+        format!("{target} is not turned off")
+    };
+
+    ctx.say(response).await?;
+    Ok(())
+}
+
+/// Only allow a command or category in a channel or for a role, leave both out to lift that again
+///
+/// Examples:
+/// `/commands restrict pong #bot-commands`
+/// `/commands restrict Moderation role:@Moderators`
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn restrict(
+    ctx: Context<'_>,
+    #[description = "A command (like `prefix add`) or category"]
+    #[autocomplete = "autocomplete_target"]
+    target: String,
+    #[description = "Also allow it in this channel"]
+    #[channel_types("Text", "News", "Voice")]
+    channel: Option<GuildChannel>,
+    #[description = "Also allow it for members with this role"] role: Option<Role>,
+) -> Result<(), Error> {
+    let target = resolve(ctx, &target)?;
+
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    data.overrides
+        .restrict(
+            &data.db,
+            guild_id,
+            &target,
+            channel.as_ref().map(|x| x.id),
+            role.as_ref().map(|x| x.id),
+        )
+        .await?;
+
+    let mut allowed = Vec::new();
+    if let Some(channel) = &channel {
+        allowed.push(format!("in <#{}>", channel.id));
+    }
+    if let Some(role) = &role {
+        allowed.push(format!("for {}", role.name));
+    }
+    let response = if allowed.is_empty() {
+        format!("{target} can be used anywhere by anyone again")
+    } else {
+        format!("{target} can now be used {}", allowed.join(" and "))
+    };
+
+    ctx.say(response).await?;
+    Ok(())
+}
+
+/// Show which commands are turned off or restricted in this server
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    list_overrides(ctx).await
+}
+
+async fn list_overrides(ctx: Context<'_>) -> Result<(), Error> {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let overrides = data.overrides.guild(&data.db, guild_id).await?;
+
+    let response = if overrides.is_empty() {
+        "Every command can be used anywhere in this server".to_string()
+    } else {
+        let lines: Vec<_> = overrides
+            .iter()
+            .map(|x| {
+                let mut rules = Vec::new();
+                if x.disabled {
+                    rules.push("turned off".to_string());
+                }
+                if !x.channels.is_empty() {
+                    let channels: Vec<_> = x.channels.iter().map(|x| format!("<#{x}>")).collect();
+                    rules.push(format!("only in {}", channels.join(", ")));
+                }
+                if !x.roles.is_empty() {
+                    let roles: Vec<_> = x.roles.iter().map(|x| format!("<@&{x}>")).collect();
+                    rules.push(format!("only for {}", roles.join(", ")));
+                }
+                format!("{}: {}", x.target, rules.join(", "))
+            })
+            .collect();
+        lines.join("\n")
+    };
+
+    // Listing the roles shouldn't ping them
+    ctx.send(|b| b.content(response).allowed_mentions(|a| a.empty_parse()))
+        .await?;
+    Ok(())
+}
+
+/// The command or category the user meant, `/commands` itself can't be targeted
+fn resolve(ctx: Context<'_>, input: &str) -> Result<Target, Error> {
+    let target = Target::resolve(&ctx.framework().options().commands, input).ok_or_else(|| {
+        BotError::user(format!("There is no command or category called `{input}`"))
+    })?;
+
+    if let Target::Command(name) = &target {
+        if name.split(' ').next() == Some(MANAGE_COMMAND) {
+            return Err(BotError::user(format!(
+                "`/{MANAGE_COMMAND}` can't be turned off or restricted"
+            )));
+        }
+    }
+    Ok(target)
+}
+
+async fn autocomplete_target<'a>(
+    ctx: Context<'a>,
+    partial: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    let commands = &ctx.framework().options().commands;
+    let mut targets: Vec<_> = crate::commands::visible_names(commands)
+        .into_iter()
+        .filter(|x| x.split(' ').next() != Some(MANAGE_COMMAND))
+        .collect();
+    targets.extend(
+        overrides::categories(commands)
+            .into_iter()
+            .map(str::to_string),
+    );
+
+    let partial = partial.to_lowercase();
+    targets
+        .into_iter()
+        .filter(move |x| x.to_lowercase().contains(&partial))
+        .take(SUGGESTIONS)
+}
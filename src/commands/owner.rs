@@ -0,0 +1,399 @@
+use std::fmt::Write;
+
+use poise::serenity_prelude as serenity;
+use serenity::{Activity, GuildId};
+use sqlx::{Column, Executor, PgPool, Row};
+
+use crate::error::BotError;
+use crate::paginate::paginate;
+use crate::registration::{self, Scope};
+use crate::{Context, Error};
+
+register_command!(owner);
+
+/// Guilds per page of `/owner guilds`
+const GUILDS_PER_PAGE: usize = 15;
+/// Rows shown by `/owner sql`, the query is cut off after that
+const SQL_MAX_ROWS: usize = 25;
+/// Longer values are shortened in the table of `/owner sql`
+const SQL_MAX_CELL_WIDTH: usize = 30;
+/// Leaves room for the code block around the table in a message of at most 2000 characters
+const SQL_MAX_TABLE_LENGTH: usize = 1900;
+
+/// Controls for the owners of the bot, every use is recorded in the `owner_audit` table
+#[poise::command(
+    slash_command,
+    prefix_command,
+    owners_only,
+    hide_in_help,
+    // Keeps it out of the slash command picker of everyone but server admins
+    default_member_permissions = "ADMINISTRATOR",
+    subcommands(
+        "shutdown",
+        "restart",
+        "register",
+        "guilds",
+        "leave",
+        "status",
+        "sql",
+        "maintenance",
+        "super::blocklist::blocklist"
+    )
+)]
+pub async fn owner(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.say(
+        "Use one of the subcommands: shutdown, restart, register, guilds, leave, status, sql, \
+        maintenance, blocklist",
+    )
+    .await?;
+    Ok(())
+}
+
+/// Shut the bot down once running commands and jobs are done
+#[poise::command(slash_command, prefix_command, owners_only, hide_in_help)]
+pub async fn shutdown(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.say("Shutting down...").await?;
+    ctx.data().shutdown.request(false);
+    Ok(())
+}
+
+/// Shut the bot down and start it again with the same arguments
+#[poise::command(slash_command, prefix_command, owners_only, hide_in_help)]
+pub async fn restart(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.say("Restarting...").await?;
+    ctx.data().shutdown.request(true);
+    Ok(())
+}
+
+#[derive(Debug, poise::ChoiceParameter)]
+pub enum RegisterChoice {
+    #[name = "Register globally"]
+    Global,
+    #[name = "Register in this server"]
+    Guild,
+    #[name = "Unregister globally"]
+    UnregisterGlobal,
+    #[name = "Unregister in this server"]
+    UnregisterGuild,
+}
+
+/// Register or unregister the slash commands of the bot
+#[poise::command(slash_command, prefix_command, owners_only, hide_in_help)]
+pub async fn register(
+    ctx: Context<'_>,
+    #[description = "Where to (un)register the commands"] choice: RegisterChoice,
+) -> Result<(), Error> {
+    let guild_scope = || {
+        ctx.guild_id()
+            .map(Scope::Guild)
+            .ok_or_else(|| BotError::user("This can only be done in a server"))
+    };
+
+    let scope = match choice {
+        RegisterChoice::Global | RegisterChoice::UnregisterGlobal => Scope::Global,
+        RegisterChoice::Guild | RegisterChoice::UnregisterGuild => guild_scope()?,
+    };
+
+    let http = &ctx.serenity_context().http;
+    let response = match choice {
+        RegisterChoice::Global | RegisterChoice::Guild => {
+            let commands = &ctx.framework().options().commands;
+            if registration::sync(http, commands, scope).await? {
+                format!("Registered {} commands {scope}", commands.len())
+            } else {
+                format!("Commands are already up to date {scope}")
+            }
+        }
+        RegisterChoice::UnregisterGlobal | RegisterChoice::UnregisterGuild => {
+            registration::clear(http, scope).await?;
+            format!("Unregistered all commands {scope}")
+        }
+    };
+
+    ctx.say(response).await?;
+    Ok(())
+}
+
+/// List the servers the bot is in, biggest first
+#[poise::command(slash_command, prefix_command, owners_only, hide_in_help)]
+pub async fn guilds(ctx: Context<'_>) -> Result<(), Error> {
+    let cache = &ctx.serenity_context().cache;
+    let mut guilds: Vec<_> = cache
+        .guilds()
+        .into_iter()
+        .filter_map(|id| cache.guild_field(id, |g| (id, g.name.clone(), g.member_count)))
+        .collect();
+    guilds.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.1.cmp(&b.1)));
+
+    let pages: Vec<String> = guilds
+        .chunks(GUILDS_PER_PAGE)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .map(|(id, name, members)| format!("**{name}** (`{id}`): {members} members"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect();
+
+    paginate(ctx, &format!("In {} servers", guilds.len()), &pages).await
+}
+
+/// Make the bot leave a server
+#[poise::command(slash_command, prefix_command, owners_only, hide_in_help)]
+pub async fn leave(
+    ctx: Context<'_>,
+    #[description = "Id of the server"] guild: String,
+) -> Result<(), Error> {
+    let guild_id = guild
+        .trim()
+        .parse()
+        .map(GuildId)
+        .map_err(|_| BotError::user(format!("`{guild}` is not a server id")))?;
+
+    let name = guild_id
+        .name(ctx.serenity_context())
+        .unwrap_or_else(|| guild_id.to_string());
+    guild_id.leave(ctx.serenity_context()).await?;
+
+    ctx.say(format!("Left {name}")).await?;
+    Ok(())
+}
+
+/// Set what the bot is shown as playing, leave the text out to clear it
+#[poise::command(slash_command, prefix_command, owners_only, hide_in_help)]
+pub async fn status(
+    ctx: Context<'_>,
+    #[description = "What the bot is playing"]
+    #[rest]
+    text: Option<String>,
+) -> Result<(), Error> {
+    let maintenance = &ctx.data().maintenance;
+    maintenance.set_status(text.clone());
+
+    // Maintenance mode keeps its own presence until it is turned off
+    if !maintenance.is_enabled() {
+        let activity = text.as_deref().map(Activity::playing);
+        // The presence is per shard, so every one of them has to be told
+        let runners = ctx.framework().shard_manager.lock().await.runners.clone();
+        for runner in runners.lock().await.values() {
+            runner.runner_tx.set_activity(activity.clone());
+        }
+    }
+
+    match (text, maintenance.is_enabled()) {
+        (Some(text), false) => ctx.say(format!("Now playing {text}")).await?,
+        (Some(text), true) => {
+            ctx.say(format!("Playing {text} once maintenance mode is off"))
+                .await?
+        }
+        (None, _) => ctx.say("Cleared the status").await?,
+    };
+    Ok(())
+}
+
+/// Turn maintenance mode on or off, only owners can use commands while it is on
+#[poise::command(slash_command, prefix_command, owners_only, hide_in_help)]
+pub async fn maintenance(
+    ctx: Context<'_>,
+    #[description = "Whether the bot is in maintenance mode"] enabled: bool,
+    #[description = "What users are told instead of the configured notice"]
+    #[rest]
+    message: Option<String>,
+) -> Result<(), Error> {
+    let data = ctx.data();
+    data.maintenance
+        .set(&data.db, enabled, message, Some(ctx.author().id))
+        .await?;
+
+    let (activity, status) = data.maintenance.presence();
+    let runners = ctx.framework().shard_manager.lock().await.runners.clone();
+    for runner in runners.lock().await.values() {
+        runner.runner_tx.set_presence(activity.clone(), status);
+    }
+
+    match data.maintenance.notice() {
+        Some(notice) => {
+            ctx.say(format!(
+                "Maintenance mode is on, users are told:\n> {notice}"
+            ))
+            .await?
+        }
+        None => ctx.say("Maintenance mode is off").await?,
+    };
+    Ok(())
+}
+
+/// Run a read-only SQL query and show the result as a table
+#[poise::command(slash_command, prefix_command, owners_only, hide_in_help)]
+pub async fn sql(
+    ctx: Context<'_>,
+    #[description = "The query, only SELECT and the like"]
+    #[rest]
+    query: String,
+) -> Result<(), Error> {
+    // Prefix commands might have it in a code block
+    let query = query
+        .trim()
+        .trim_start_matches("```sql")
+        .trim_matches('`')
+        .trim()
+        .trim_end_matches(';');
+
+    let (columns, rows, truncated) = match read_only_query(&ctx.data().db, query).await {
+        Ok(result) => result,
+        Err(sqlx::Error::Database(err)) => return Err(BotError::user(err.to_string())),
+        Err(err) => return Err(err.into()),
+    };
+    if columns.is_empty() {
+        return Err(BotError::user("Only queries that return rows can be run"));
+    }
+
+    let (table, shown) = table(&columns, &rows);
+    let note = if shown < rows.len() || truncated {
+        format!("First {shown} rows")
+    } else {
+        format!("{shown} rows")
+    };
+
+    ctx.say(format!("```\n{table}```{note}")).await?;
+    Ok(())
+}
+
+/// Every value of a row as text, `None` for NULL
+type TextRow = Vec<Option<String>>;
+
+/// Runs the query in a read-only transaction that is rolled back afterwards, at most
+/// [`SQL_MAX_ROWS`] rows are fetched.
+///
+/// The query is wrapped in a `SELECT` that turns every column into text, which also means it has to
+/// be a single statement that returns rows.
+async fn read_only_query(
+    db: &PgPool,
+    query: &str,
+) -> Result<(Vec<String>, Vec<TextRow>, bool), sqlx::Error> {
+    let mut transaction = db.begin().await?;
+    transaction.execute("SET TRANSACTION READ ONLY").await?;
+    transaction
+        .execute("SET LOCAL statement_timeout = '5s'")
+        .await?;
+
+    let description = transaction.describe(query).await?;
+    let columns: Vec<String> = description
+        .columns()
+        .iter()
+        .map(|x| x.name().to_string())
+        .collect();
+    if columns.is_empty() {
+        return Ok((columns, Vec::new(), false));
+    }
+
+    // The names are replaced since they don't have to be unique or valid identifiers
+    let aliases: Vec<_> = (0..columns.len()).map(|i| format!("c{i}")).collect();
+    let casts: Vec<_> = aliases.iter().map(|x| format!("{x}::text")).collect();
+    let wrapped = format!(
+        "SELECT {} FROM ({query}) AS q({}) LIMIT {}",
+        casts.join(", "),
+        aliases.join(", "),
+        SQL_MAX_ROWS + 1
+    );
+
+    let mut rows = sqlx::query(&wrapped)
+        .fetch_all(&mut transaction)
+        .await?
+        .iter()
+        .map(|row| (0..columns.len()).map(|i| row.try_get(i)).collect())
+        .collect::<Result<Vec<TextRow>, _>>()?;
+    transaction.rollback().await?;
+
+    let truncated = rows.len() > SQL_MAX_ROWS;
+    rows.truncate(SQL_MAX_ROWS);
+    Ok((columns, rows, truncated))
+}
+
+/// Lays out the rows in aligned columns, leaving out rows that don't fit into a message. Returns
+/// the table and how many rows are in it.
+fn table(columns: &[String], rows: &[TextRow]) -> (String, usize) {
+    let cell = |value: &str| {
+        let value = value.replace('\n', " ");
+        if value.chars().count() > SQL_MAX_CELL_WIDTH {
+            let short: String = value.chars().take(SQL_MAX_CELL_WIDTH - 1).collect();
+            format!("{short}…")
+        } else {
+            value
+        }
+    };
+    let columns: Vec<String> = columns.iter().map(|x| cell(x)).collect();
+    let rows: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|value| cell(value.as_deref().unwrap_or("NULL")))
+                .collect()
+        })
+        .collect();
+
+    let mut widths: Vec<usize> = columns.iter().map(|x| x.chars().count()).collect();
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+
+    let line = |values: &[String]| {
+        let cells: Vec<_> = values
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{value:width$}"))
+            .collect();
+        format!("{}\n", cells.join(" | ").trim_end())
+    };
+
+    let mut table = line(&columns);
+    let separator: Vec<_> = widths.iter().map(|x| "-".repeat(*x)).collect();
+    writeln!(table, "{}", separator.join("-+-")).unwrap();
+
+    let mut shown = 0;
+    for row in &rows {
+        let line = line(row);
+        if table.len() + line.len() > SQL_MAX_TABLE_LENGTH {
+            break;
+        }
+        table.push_str(&line);
+        shown += 1;
+    }
+    (table, shown)
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use crate::testing::{Harness, AUTHOR_ID};
+
+    async fn audited(db: &PgPool) -> Vec<(i64, String, bool)> {
+        sqlx::query_as("SELECT user_id, command, allowed FROM owner_audit ORDER BY id")
+            .fetch_all(db)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn records_attempts_of_users_that_are_not_owners(db: PgPool) {
+        let harness = Harness::new(db).await;
+        let replies = harness.prefix("owner guilds").await;
+        assert!(replies[0].content.as_deref().unwrap().contains("owners"));
+
+        let harness = harness.by_owner();
+        harness.prefix("owner guilds").await;
+
+        let author = AUTHOR_ID.0 as i64;
+        assert_eq!(
+            audited(&harness.data.db).await,
+            [
+                (author, "owner guilds".to_string(), false),
+                (author, "owner guilds".to_string(), true),
+            ]
+        );
+    }
+}
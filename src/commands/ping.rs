@@ -1,8 +1,50 @@
 use crate::{Context, Error};
 
+register_command!(pong);
+cooldown!("pong", User = 5);
+
 /// Pong!
 #[poise::command(slash_command, prefix_command, track_edits)]
 pub async fn pong(ctx: Context<'_>) -> Result<(), Error> {
     ctx.say("pong!").await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use crate::testing::Harness;
+
+    #[sqlx::test]
+    async fn replies_to_slash_and_prefix(db: PgPool) {
+        let harness = Harness::new(db.clone()).await;
+
+        let replies = harness.slash("pong", &[]).await;
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].content.as_deref(), Some("pong!"));
+        assert!(!replies[0].ephemeral);
+
+        // The cooldown the slash command started would get in the way
+        sqlx::query("DELETE FROM cooldowns")
+            .execute(&db)
+            .await
+            .unwrap();
+        let harness = Harness::new(db).await;
+
+        let replies = harness.prefix("pong").await;
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].content.as_deref(), Some("pong!"));
+    }
+
+    #[sqlx::test]
+    async fn has_a_cooldown(db: PgPool) {
+        let harness = Harness::new(db).await;
+        harness.prefix("pong").await;
+
+        let replies = harness.slash("pong", &[]).await;
+        assert_eq!(replies.len(), 1);
+        assert!(replies[0].content.as_deref().unwrap().contains("try again"));
+        assert!(replies[0].ephemeral);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::error::BotError;
+use crate::prefixes::{validate, AddOutcome, MAX_GUILD_PREFIXES};
+use crate::{Context, Error};
+
+register_command!(prefix);
+
+/// Manage the prefixes of this server
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    subcommands("set", "add", "remove", "list")
+)]
+pub async fn prefix(ctx: Context<'_>) -> Result<(), Error> {
+    // Only reachable as a prefix command since discord doesn't let you invoke a group directly
+    list_prefixes(ctx).await
+}
+
+/// Replace every prefix of this server with a single one
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn set(
+    ctx: Context<'_>,
+    #[description = "The new prefix"] prefix: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+
+    validate(&prefix).map_err(BotError::user)?;
+
+    let data = ctx.data();
+    data.prefixes.set(&data.db, guild_id, &prefix).await?;
+
+    ctx.say(format!("The prefix is now `{prefix}`")).await?;
+    Ok(())
+}
+
+/// Add another prefix to this server
+///
+/// Examples:
+/// `/prefix add ?`
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "The prefix to add"] prefix: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+
+    validate(&prefix).map_err(BotError::user)?;
+
+    let data = ctx.data();
+    match data.prefixes.add(&data.db, guild_id, &prefix).await? {
+        AddOutcome::Added => ctx.say(format!("Added `{prefix}` as a prefix")).await?,
+        AddOutcome::Exists => ctx.say(format!("`{prefix}` is already a prefix")).await?,
+        AddOutcome::Full => {
+            return Err(BotError::user(format!(
+                "A server can't have more than {MAX_GUILD_PREFIXES} prefixes"
+            )))
+        }
+    };
+    Ok(())
+}
+
+/// Remove a prefix from this server
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "The prefix to remove"] prefix: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+
+    let data = ctx.data();
+    if data.prefixes.remove(&data.db, guild_id, &prefix).await? {
+        ctx.say(format!("Removed the `{prefix}` prefix")).await?;
+    } else {
+        ctx.say(format!("`{prefix}` is not a prefix of this server"))
+            .await?;
+    }
+    Ok(())
+}
+
+/// List the prefixes of this server
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    list_prefixes(ctx).await
+}
+
+async fn list_prefixes(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+
+    let data = ctx.data();
+    let guild_prefixes = data.prefixes.guild(&data.db, guild_id).await?;
+
+    // Servers without their own prefixes fall back to the global ones
+    let (prefixes, note) = if guild_prefixes.is_empty() {
+        (data.prefixes.global(), " (default)")
+    } else {
+        (&*guild_prefixes, "")
+    };
+
+    let response = if prefixes.is_empty() {
+        "This server has no prefixes, use slash commands or mention me instead".to_string()
+    } else {
+        let prefixes: Vec<_> = prefixes.iter().map(|x| format!("`{x}`")).collect();
+        format!("Prefixes{note}: {}", prefixes.join(", "))
+    };
+
+    ctx.say(response).await?;
+    Ok(())
+}
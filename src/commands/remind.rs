@@ -0,0 +1,154 @@
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::duration::HumanDuration;
+use crate::error::BotError;
+use crate::reminders::{self, NewReminder, MAX_REMINDERS};
+use crate::{user_settings, when, Context, Error};
+
+register_command!(remind);
+cooldown!("remind me", User = 10);
+
+/// Repeating reminders can't come up more often than this
+const MIN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// The longest a reminder can be, in characters
+const MAX_MESSAGE_LENGTH: usize = 1500;
+
+/// Get reminded of something later
+#[poise::command(slash_command, prefix_command, subcommands("me", "list", "cancel"))]
+pub async fn remind(ctx: Context<'_>) -> Result<(), Error> {
+    // Only reachable as a prefix command since discord doesn't let you invoke a group directly
+    list_reminders(ctx).await
+}
+
+/// Set a reminder, like `remind me "tomorrow 9am" "water the plants"`
+///
+/// Examples:
+/// `/remind me "in 2h30m" "take the pizza out"`
+/// `/remind me "next friday 6pm" "call mom" 1w`
+#[poise::command(slash_command, prefix_command)]
+pub async fn me(
+    ctx: Context<'_>,
+    #[description = "When to remind you, like `in 2h30m`, `tomorrow 9am` or `next friday`"]
+    when: String,
+    #[description = "What to remind you of"] what: String,
+    #[description = "Remind you again every so often, like 1d or 1w"] every: Option<HumanDuration>,
+    #[description = "Send the reminder by DM instead of in this channel"] dm: Option<bool>,
+) -> Result<(), Error> {
+    let user_id = ctx.author().id;
+    let db = &ctx.data().db;
+
+    if what.chars().count() > MAX_MESSAGE_LENGTH {
+        return Err(BotError::user(format!(
+            "Reminders can't be longer than {MAX_MESSAGE_LENGTH} characters"
+        )));
+    }
+    let interval = every.map(|x| x.0);
+    if interval.is_some_and(|x| x < MIN_INTERVAL) {
+        return Err(BotError::user(format!(
+            "Reminders can't repeat more often than every {}",
+            HumanDuration(MIN_INTERVAL)
+        )));
+    }
+    if reminders::count_for_user(db, user_id).await? >= MAX_REMINDERS {
+        return Err(BotError::user(format!(
+            "You can't have more than {MAX_REMINDERS} reminders, cancel one with `remind cancel`"
+        )));
+    }
+
+    let timezone = user_settings::timezone(db, user_id).await?;
+    let remind_at = when::parse(&when, Utc::now(), timezone)
+        .map_err(|err| BotError::user(format!("I don't understand `{when}` as a time, {err}")))?;
+
+    // Reminders set in DMs end up there anyway
+    let channel_id = match (ctx.guild_id(), dm.unwrap_or(false)) {
+        (Some(_), false) => Some(ctx.channel_id()),
+        _ => None,
+    };
+
+    let id = NewReminder {
+        user_id,
+        guild_id: ctx.guild_id(),
+        channel_id,
+        message: &what,
+        remind_at,
+        interval,
+        timezone,
+    }
+    .create(db)
+    .await?;
+
+    let repeat = interval
+        .map(|x| format!(" and every {} after that", HumanDuration(x)))
+        .unwrap_or_default();
+    ctx.send(|b| {
+        b.content(format!(
+            "I'll remind you <t:{}:R>{repeat} (reminder #{id})",
+            remind_at.timestamp()
+        ))
+        .ephemeral(true)
+    })
+    .await?;
+    Ok(())
+}
+
+/// Show your reminders
+#[poise::command(slash_command, prefix_command)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    list_reminders(ctx).await
+}
+
+/// Cancel one of your reminders
+#[poise::command(slash_command, prefix_command)]
+pub async fn cancel(
+    ctx: Context<'_>,
+    #[description = "Number of the reminder, see `remind list`"] id: i64,
+) -> Result<(), Error> {
+    if !reminders::cancel(&ctx.data().db, ctx.author().id, id).await? {
+        return Err(BotError::user(format!("You have no reminder #{id}")));
+    }
+
+    ctx.send(|b| {
+        b.content(format!("Cancelled reminder #{id}"))
+            .ephemeral(true)
+    })
+    .await?;
+    Ok(())
+}
+
+async fn list_reminders(ctx: Context<'_>) -> Result<(), Error> {
+    let reminders = reminders::for_user(&ctx.data().db, ctx.author().id).await?;
+
+    if reminders.is_empty() {
+        ctx.send(|b| {
+            b.content("You have no reminders, set one with `remind me`")
+                .ephemeral(true)
+        })
+        .await?;
+        return Ok(());
+    }
+
+    let lines: Vec<_> = reminders
+        .iter()
+        .map(|reminder| {
+            let repeat = reminder
+                .interval()
+                .map(|x| format!(" (every {})", HumanDuration(x)))
+                .unwrap_or_default();
+            let message: String = reminder.message.chars().take(100).collect();
+            format!(
+                "**#{}** <t:{}:R>{repeat}: {message}",
+                reminder.id,
+                reminder.remind_at.timestamp()
+            )
+        })
+        .collect();
+
+    ctx.send(|b| {
+        b.embed(|e| e.title("Your reminders").description(lines.join("\n")))
+            .ephemeral(true)
+    })
+    .await?;
+    Ok(())
+}
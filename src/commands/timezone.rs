@@ -0,0 +1,56 @@
+use chrono_tz::{Tz, TZ_VARIANTS};
+
+use crate::error::BotError;
+use crate::{user_settings, Context, Error};
+
+register_command!(timezone);
+
+/// Autocomplete only offers this many time zones at once, the most discord allows
+const SUGGESTIONS: usize = 25;
+
+/// Show or set your time zone, used to understand times like `tomorrow 9am`
+///
+/// Examples:
+/// `/timezone Europe/Berlin`
+#[poise::command(slash_command, prefix_command)]
+pub async fn timezone(
+    ctx: Context<'_>,
+    #[description = "Your time zone, like Europe/Berlin or America/New_York"]
+    #[autocomplete = "autocomplete_timezone"]
+    timezone: Option<String>,
+) -> Result<(), Error> {
+    let user_id = ctx.author().id;
+    let db = &ctx.data().db;
+
+    let content = match timezone {
+        Some(name) => {
+            let timezone: Tz = name.parse().map_err(|_| {
+                BotError::user(format!(
+                    "`{name}` is not a time zone, use a name like `Europe/Berlin`"
+                ))
+            })?;
+            user_settings::set_timezone(db, user_id, timezone).await?;
+            format!("Your time zone is now {timezone}")
+        }
+        None => {
+            let timezone = user_settings::timezone(db, user_id).await?;
+            format!("Your time zone is {timezone}, change it with `timezone <name>`")
+        }
+    };
+
+    ctx.send(|b| b.content(content).ephemeral(true)).await?;
+    Ok(())
+}
+
+async fn autocomplete_timezone<'a>(
+    _ctx: Context<'_>,
+    partial: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    let partial = partial.to_lowercase();
+    TZ_VARIANTS
+        .iter()
+        .map(|tz| tz.name())
+        .filter(move |name| name.to_lowercase().contains(&partial))
+        .take(SUGGESTIONS)
+        .map(str::to_string)
+}
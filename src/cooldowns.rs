@@ -0,0 +1,486 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude as serenity;
+use serenity::GuildId;
+use sqlx::PgPool;
+
+use crate::commands::Command;
+use crate::duration::HumanDuration;
+use crate::{Context, Error};
+
+/// Who or what a cooldown applies to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, poise::ChoiceParameter)]
+pub enum Bucket {
+    /// Everyone everywhere
+    Global,
+    /// A user, in every server and DMs
+    User,
+    /// A user in one server
+    Member,
+    Channel,
+    #[name = "Server"]
+    Guild,
+}
+
+impl Bucket {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Bucket::Global => "global",
+            Bucket::User => "user",
+            Bucket::Member => "member",
+            Bucket::Channel => "channel",
+            Bucket::Guild => "guild",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "global" => Some(Bucket::Global),
+            "user" => Some(Bucket::User),
+            "member" => Some(Bucket::Member),
+            "channel" => Some(Bucket::Channel),
+            "guild" => Some(Bucket::Guild),
+            _ => None,
+        }
+    }
+
+    /// Identifies the user, channel or guild of the invocation, `None` for buckets that only
+    /// exist in guilds when used in DMs
+    fn key(self, ctx: Context<'_>) -> Option<String> {
+        match self {
+            Bucket::Global => Some(String::new()),
+            Bucket::User => Some(ctx.author().id.to_string()),
+            Bucket::Member => ctx.guild_id().map(|x| format!("{x}:{}", ctx.author().id)),
+            Bucket::Channel => Some(ctx.channel_id().to_string()),
+            Bucket::Guild => ctx.guild_id().map(|x| x.to_string()),
+        }
+    }
+}
+
+/// A cooldown that came with a command, added with [`cooldown!`]
+pub struct Declared {
+    /// Qualified name of the command, like `prefix add`
+    pub command: &'static str,
+    /// Length of the cooldown in seconds
+    pub buckets: &'static [(Bucket, u64)],
+}
+
+inventory::collect!(Declared);
+
+/// The length of the cooldown of every bucket a command has one in
+pub type Config = BTreeMap<Bucket, Duration>;
+
+/// Makes sure every cooldown belongs to a command, a typo would otherwise go unnoticed
+pub fn validate(commands: &[Command]) -> Result<(), String> {
+    // Subcommands only get their qualified name once the framework is built
+    fn exists(commands: &[Command], name: &str) -> bool {
+        commands
+            .iter()
+            .any(|x| match name.strip_prefix(x.name.as_str()) {
+                Some("") => true,
+                Some(rest) => rest
+                    .strip_prefix(' ')
+                    .is_some_and(|rest| exists(&x.subcommands, rest)),
+                None => false,
+            })
+    }
+
+    let problems: Vec<_> = inventory::iter::<Declared>
+        .into_iter()
+        .filter(|x| !exists(commands, x.command))
+        .map(|x| format!("`{}` has a cooldown but is not a command", x.command))
+        .collect();
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems.join("\n"))
+    }
+}
+
+/// A command with the bucket and the key in it, like the user id for `User`
+type Key = (String, Bucket, String);
+type Running = HashMap<Key, DateTime<Utc>>;
+
+// Running cooldowns are kept in memory since they are looked at before every command, and in the
+// database so restarting doesn't reset them
+pub struct Cooldowns {
+    declared: HashMap<&'static str, Config>,
+    /// Changes made with `/cooldown`, a zero duration turns a cooldown off
+    guilds: RwLock<HashMap<GuildId, Arc<HashMap<String, Config>>>>,
+    running: Mutex<Running>,
+}
+
+impl Cooldowns {
+    /// Loads the cooldowns that are still running, called once on startup
+    pub async fn load(db: &PgPool) -> Result<Self, Error> {
+        sqlx::query("DELETE FROM cooldowns WHERE ends_at <= now()")
+            .execute(db)
+            .await?;
+
+        let rows: Vec<(String, String, String, DateTime<Utc>)> =
+            sqlx::query_as("SELECT command, bucket, key, ends_at FROM cooldowns")
+                .fetch_all(db)
+                .await?;
+        let running = rows
+            .into_iter()
+            .filter_map(|(command, bucket, key, ends_at)| {
+                Some(((command, Bucket::parse(&bucket)?, key), ends_at))
+            })
+            .collect();
+
+        let mut declared: HashMap<_, Config> = HashMap::new();
+        for entry in inventory::iter::<Declared> {
+            let config = declared.entry(entry.command).or_default();
+            for (bucket, seconds) in entry.buckets {
+                config.insert(*bucket, Duration::from_secs(*seconds));
+            }
+        }
+
+        Ok(Self {
+            declared,
+            guilds: RwLock::new(HashMap::new()),
+            running: Mutex::new(running),
+        })
+    }
+
+    /// The cooldowns the command came with
+    pub fn declared(&self, command: &str) -> Config {
+        self.declared.get(command).cloned().unwrap_or_default()
+    }
+
+    /// Every command that has a cooldown, with the ones the guild changed
+    pub async fn all(
+        &self,
+        db: &PgPool,
+        guild_id: GuildId,
+    ) -> Result<Vec<(String, Config)>, Error> {
+        let overrides = self.overrides(db, guild_id).await?;
+
+        let mut commands: Vec<_> = self.declared.keys().map(|x| x.to_string()).collect();
+        commands.extend(overrides.keys().cloned());
+        commands.sort();
+        commands.dedup();
+
+        let all = commands
+            .into_iter()
+            .map(|command| {
+                let config = merge(self.declared(&command), overrides.get(&command));
+                (command, config)
+            })
+            .filter(|(_, config)| !config.is_empty())
+            .collect();
+        Ok(all)
+    }
+
+    /// The cooldowns of a command, as changed by the guild it is used in
+    pub async fn config(
+        &self,
+        db: &PgPool,
+        guild_id: Option<GuildId>,
+        command: &str,
+    ) -> Result<Config, Error> {
+        let declared = self.declared(command);
+        match guild_id {
+            Some(guild_id) => {
+                let overrides = self.overrides(db, guild_id).await?;
+                Ok(merge(declared, overrides.get(command)))
+            }
+            None => Ok(declared),
+        }
+    }
+
+    /// How long until the user can use the command of `ctx` again, `None` if they can now
+    pub async fn remaining(&self, ctx: Context<'_>) -> Result<Option<Duration>, Error> {
+        let keys = self.keys(ctx).await?;
+        let now = Utc::now();
+        let running = self.running.lock().unwrap();
+        let remaining = keys
+            .iter()
+            .filter_map(|(key, _)| (*running.get(key)? - now).to_std().ok())
+            .max();
+        Ok(remaining)
+    }
+
+    /// Starts the cooldowns of the command of `ctx`
+    pub async fn start(&self, ctx: Context<'_>) -> Result<(), Error> {
+        let keys = self.keys(ctx).await?;
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let now = Utc::now();
+        let started: Vec<_> = keys
+            .into_iter()
+            .filter_map(|(key, duration)| {
+                Some((key, now + chrono::Duration::from_std(duration).ok()?))
+            })
+            .collect();
+        {
+            let mut running = self.running.lock().unwrap();
+            // Nothing else cleans up cooldowns that ran out
+            running.retain(|_, ends_at| *ends_at > now);
+            running.extend(started.iter().cloned());
+        }
+
+        let db = &ctx.data().db;
+        for ((command, bucket, key), ends_at) in started {
+            sqlx::query(
+                "INSERT INTO cooldowns (command, bucket, key, ends_at) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (command, bucket, key) DO UPDATE SET ends_at = excluded.ends_at",
+            )
+            .bind(command)
+            .bind(bucket.as_str())
+            .bind(key)
+            .bind(ends_at)
+            .execute(db)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// The running cooldowns the command of `ctx` would look at, with their length
+    async fn keys(&self, ctx: Context<'_>) -> Result<Vec<(Key, Duration)>, Error> {
+        let command = &ctx.command().qualified_name;
+        let config = self.config(&ctx.data().db, ctx.guild_id(), command).await?;
+        let keys = config
+            .iter()
+            .filter_map(|(bucket, duration)| {
+                Some(((command.clone(), *bucket, bucket.key(ctx)?), *duration))
+            })
+            .collect();
+        Ok(keys)
+    }
+
+    /// Changes the cooldown of a command in a guild, `Duration::ZERO` turns it off
+    pub async fn set(
+        &self,
+        db: &PgPool,
+        guild_id: GuildId,
+        command: &str,
+        bucket: Bucket,
+        duration: Duration,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO guild_cooldowns (guild_id, command, bucket, seconds) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (guild_id, command, bucket) DO UPDATE SET seconds = excluded.seconds",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(command)
+        .bind(bucket.as_str())
+        .bind(duration.as_secs() as i64)
+        .execute(db)
+        .await?;
+
+        self.invalidate(guild_id);
+        Ok(())
+    }
+
+    /// Goes back to the cooldowns the command came with, returns false if the guild did not
+    /// change them
+    pub async fn reset(
+        &self,
+        db: &PgPool,
+        guild_id: GuildId,
+        command: &str,
+    ) -> Result<bool, Error> {
+        let reset = sqlx::query("DELETE FROM guild_cooldowns WHERE guild_id = $1 AND command = $2")
+            .bind(guild_id.0 as i64)
+            .bind(command)
+            .execute(db)
+            .await?
+            .rows_affected()
+            > 0;
+
+        self.invalidate(guild_id);
+        Ok(reset)
+    }
+
+    async fn overrides(
+        &self,
+        db: &PgPool,
+        guild_id: GuildId,
+    ) -> Result<Arc<HashMap<String, Config>>, Error> {
+        if let Some(overrides) = self.guilds.read().unwrap().get(&guild_id) {
+            return Ok(overrides.clone());
+        }
+
+        let rows: Vec<(String, String, i64)> = sqlx::query_as(
+            "SELECT command, bucket, seconds FROM guild_cooldowns WHERE guild_id = $1",
+        )
+        .bind(guild_id.0 as i64)
+        .fetch_all(db)
+        .await?;
+
+        let mut overrides: HashMap<String, Config> = HashMap::new();
+        for (command, bucket, seconds) in rows {
+            if let Some(bucket) = Bucket::parse(&bucket) {
+                overrides
+                    .entry(command)
+                    .or_default()
+                    .insert(bucket, Duration::from_secs(seconds as u64));
+            }
+        }
+
+        let overrides = Arc::new(overrides);
+        self.guilds
+            .write()
+            .unwrap()
+            .insert(guild_id, overrides.clone());
+        Ok(overrides)
+    }
+
+    fn invalidate(&self, guild_id: GuildId) {
+        self.guilds.write().unwrap().remove(&guild_id);
+    }
+}
+
+/// Like "10s per user, 1m per channel"
+pub fn describe(config: &Config) -> String {
+    if config.is_empty() {
+        return "none".to_string();
+    }
+    let parts: Vec<_> = config
+        .iter()
+        .map(|(bucket, duration)| format!("{} {}", HumanDuration(*duration), per(*bucket)))
+        .collect();
+    parts.join(", ")
+}
+
+/// Like "per user", or "for everyone" for the global bucket
+pub fn per(bucket: Bucket) -> String {
+    match bucket {
+        Bucket::Global => "for everyone".to_string(),
+        bucket => format!("per {}", bucket.name().to_lowercase()),
+    }
+}
+
+/// Applies the changes of a guild, dropping the cooldowns it turned off
+fn merge(mut config: Config, overrides: Option<&Config>) -> Config {
+    if let Some(overrides) = overrides {
+        config.extend(overrides);
+    }
+    config.retain(|_, duration| !duration.is_zero());
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::commands;
+    use crate::testing::{Harness, GUILD_ID};
+
+    fn config(buckets: &[(Bucket, u64)]) -> Config {
+        buckets
+            .iter()
+            .map(|(bucket, seconds)| (*bucket, Duration::from_secs(*seconds)))
+            .collect()
+    }
+
+    #[test]
+    fn parses_what_buckets_are_stored_as() {
+        let all = [
+            Bucket::Global,
+            Bucket::User,
+            Bucket::Member,
+            Bucket::Channel,
+            Bucket::Guild,
+        ];
+        for bucket in all {
+            assert_eq!(Bucket::parse(bucket.as_str()), Some(bucket));
+        }
+        assert_eq!(Bucket::parse("server"), None);
+        assert_eq!(Bucket::parse("User"), None);
+    }
+
+    #[test]
+    fn merges_the_changes_of_a_guild() {
+        let declared = config(&[(Bucket::User, 5), (Bucket::Guild, 30)]);
+        assert_eq!(merge(declared.clone(), None), declared);
+
+        let overrides = config(&[(Bucket::User, 10), (Bucket::Channel, 1)]);
+        assert_eq!(
+            merge(declared.clone(), Some(&overrides)),
+            config(&[
+                (Bucket::User, 10),
+                (Bucket::Channel, 1),
+                (Bucket::Guild, 30)
+            ])
+        );
+
+        // Zero turns a cooldown off, whether the command came with it or not
+        let overrides = config(&[(Bucket::Guild, 0), (Bucket::Global, 0)]);
+        assert_eq!(
+            merge(declared, Some(&overrides)),
+            config(&[(Bucket::User, 5)])
+        );
+    }
+
+    #[test]
+    fn validates_that_cooldowns_belong_to_commands() {
+        let mut commands = commands::all().unwrap();
+        assert_eq!(validate(&commands), Ok(()));
+
+        // Subcommands are looked up by their qualified name
+        let remind = commands.iter_mut().find(|x| x.name == "remind").unwrap();
+        remind.subcommands.retain(|x| x.name != "me");
+        let problems = validate(&commands).unwrap_err();
+        assert_eq!(problems, "`remind me` has a cooldown but is not a command");
+
+        let problems = validate(&[]).unwrap_err();
+        assert!(problems.contains("`pong` has a cooldown"));
+    }
+
+    #[sqlx::test]
+    async fn owners_do_not_start_cooldowns(db: PgPool) {
+        let harness = Harness::new(db.clone()).await.by_owner();
+        for _ in 0..2 {
+            let replies = harness.slash("pong", &[]).await;
+            assert_eq!(replies[0].content.as_deref(), Some("pong!"));
+        }
+
+        let running: i64 = sqlx::query_scalar("SELECT count(*) FROM cooldowns")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(running, 0);
+    }
+
+    #[sqlx::test]
+    async fn subcommands_start_their_cooldown_once(db: PgPool) {
+        let harness = Harness::new(db).await;
+        let arguments = [("when", json!("in 1h")), ("what", json!("tea"))];
+
+        let replies = harness.slash("remind me", &arguments).await;
+        assert!(!replies[0].content.as_deref().unwrap().contains("try again"));
+        let replies = harness.slash("remind me", &arguments).await;
+        assert!(replies[0].content.as_deref().unwrap().contains("try again"));
+    }
+
+    #[sqlx::test]
+    async fn autocomplete_does_not_start_cooldowns(db: PgPool) {
+        let harness = Harness::new(db.clone()).await.in_guild();
+        let cooldowns = &harness.data.cooldowns;
+        cooldowns
+            .set(&db, GUILD_ID, "help", Bucket::User, Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        for typed in ["p", "po", "pon"] {
+            let suggestions = harness
+                .autocomplete("help", &[("command", json!(typed))], "command")
+                .await;
+            assert!(suggestions.iter().any(|x| x == "pong"), "{suggestions:?}");
+        }
+
+        let replies = harness.slash("help", &[("command", json!("pong"))]).await;
+        assert_eq!(replies[0].embeds[0]["title"], "/pong");
+        let replies = harness.slash("help", &[("command", json!("pong"))]).await;
+        assert!(replies[0].content.as_deref().unwrap().contains("try again"));
+    }
+}
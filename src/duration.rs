@@ -0,0 +1,105 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A duration written like people write them, for example `10m`, `1h30m` or `2d 12h`.
+///
+/// Usable directly as a command parameter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HumanDuration(pub Duration);
+
+#[derive(Debug)]
+pub struct InvalidDuration;
+
+impl fmt::Display for InvalidDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("expected a duration like `30m`, `1h30m` or `7d`")
+    }
+}
+
+impl std::error::Error for InvalidDuration {}
+
+impl FromStr for HumanDuration {
+    type Err = InvalidDuration;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut total: u64 = 0;
+        let mut number = String::new();
+        let mut any_unit = false;
+
+        for c in s.chars().filter(|c| !c.is_whitespace()) {
+            if c.is_ascii_digit() {
+                number.push(c);
+                continue;
+            }
+
+            let unit = match c.to_ascii_lowercase() {
+                's' => 1,
+                'm' => 60,
+                'h' => 60 * 60,
+                'd' => 60 * 60 * 24,
+                'w' => 60 * 60 * 24 * 7,
+                _ => return Err(InvalidDuration),
+            };
+
+            let amount: u64 = number.parse().map_err(|_| InvalidDuration)?;
+            total = amount
+                .checked_mul(unit)
+                .and_then(|x| total.checked_add(x))
+                .ok_or(InvalidDuration)?;
+            number.clear();
+            any_unit = true;
+        }
+
+        // Every number needs a unit after it, a plain `10` is ambiguous
+        if !number.is_empty() || !any_unit || total == 0 {
+            return Err(InvalidDuration);
+        }
+
+        Ok(HumanDuration(Duration::from_secs(total)))
+    }
+}
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&crate::error::format_duration(self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Option<u64> {
+        s.parse::<HumanDuration>().ok().map(|x| x.0.as_secs())
+    }
+
+    #[test]
+    fn parses_durations() {
+        let cases = [
+            ("10s", Some(10)),
+            ("10m", Some(600)),
+            ("1h30m", Some(5400)),
+            ("2d 12h", Some(216_000)),
+            ("1W", Some(604_800)),
+            ("0m5s", Some(5)),
+            // Zero
+            ("0s", None),
+            ("0d 0h", None),
+            // A number without a unit
+            ("10", None),
+            ("1h30", None),
+            ("", None),
+            ("m", None),
+            ("10y", None),
+            ("-5m", None),
+            // Overflow, of the number itself and of the total
+            ("99999999999999999999s", None),
+            ("31000000000000w", None),
+            ("18446744073709551615s 1s", None),
+        ];
+        for (input, secs) in cases {
+            assert_eq!(parse(input), secs, "{input:?}");
+        }
+    }
+}
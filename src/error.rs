@@ -0,0 +1,378 @@
+use std::fmt;
+use std::time::Duration;
+
+use chrono::Utc;
+use poise::{serenity_prelude as serenity, FrameworkError};
+use rand::Rng;
+use sqlx::PgPool;
+use tracing::{debug, error, warn};
+
+use crate::{audit, hooks, Context, Data, Error};
+
+/// Errors with a meaning to the user, commands can return these (boxed into [`Error`]) to control
+/// what the user is told. Any other error is treated as an internal failure.
+#[derive(Debug)]
+pub enum BotError {
+    /// The user did something wrong, the message is shown to them as is
+    User(String),
+    /// The user (or the bot) is not allowed to do this
+    Permission(String),
+    /// The command was used again before its cooldown ran out, see `cooldowns.rs`
+    Cooldown(Duration),
+    /// An argument could not be understood
+    ArgumentParse {
+        input: Option<String>,
+        message: String,
+    },
+    /// The bot is in maintenance mode, the message is the notice shown to the user
+    Maintenance(String),
+    /// The user or guild is on the blocklist, they are ignored without a reply
+    Blocked,
+    /// Something broke on our side, the details are only logged
+    Internal(Error),
+}
+
+impl BotError {
+    /// Shorthand for returning a [`BotError::User`] from a command
+    pub fn user(message: impl Into<String>) -> Error {
+        Box::new(BotError::User(message.into()))
+    }
+
+    /// Shorthand for returning a [`BotError::Permission`] from a command
+    pub fn permission(message: impl Into<String>) -> Error {
+        Box::new(BotError::Permission(message.into()))
+    }
+
+    /// Name of the kind of error, as stored in the `error_reports` table
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BotError::User(_) => "user",
+            BotError::Permission(_) => "permission",
+            BotError::Cooldown(_) => "cooldown",
+            BotError::ArgumentParse { .. } => "argument_parse",
+            BotError::Maintenance(_) => "maintenance",
+            BotError::Blocked => "blocked",
+            BotError::Internal(_) => "internal",
+        }
+    }
+
+    /// Turns an error returned by user code into a [`BotError`], keeping it if it already was one
+    pub fn from_error(error: Error) -> Self {
+        match error.downcast::<BotError>() {
+            Ok(error) => *error,
+            Err(error) => BotError::Internal(error),
+        }
+    }
+
+    /// What we tell the user, internal details are never included
+    fn user_message(&self) -> String {
+        match self {
+            BotError::User(message)
+            | BotError::Permission(message)
+            | BotError::Maintenance(message) => message.clone(),
+            // Discord shows the time left and counts it down
+            BotError::Cooldown(remaining) => format!(
+                "You're doing that too often, try again <t:{}:R>",
+                Utc::now().timestamp() + remaining.as_secs_f64().ceil() as i64
+            ),
+            BotError::ArgumentParse {
+                input: Some(input),
+                message,
+            } => format!("Could not understand `{input}`: {message}"),
+            BotError::ArgumentParse {
+                input: None,
+                message,
+            } => format!("Could not understand the arguments: {message}"),
+            BotError::Blocked => "You can't use this bot".to_string(),
+            BotError::Internal(_) => "Something went wrong on our side".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BotError::User(message) => write!(f, "user error: {message}"),
+            BotError::Permission(message) => write!(f, "permission error: {message}"),
+            BotError::Cooldown(remaining) => write!(f, "cooldown hit: {remaining:?} remaining"),
+            BotError::ArgumentParse { input, message } => {
+                write!(f, "argument parse error: {message} (input: {input:?})")
+            }
+            BotError::Maintenance(_) => write!(f, "in maintenance mode"),
+            BotError::Blocked => write!(f, "blocked"),
+            BotError::Internal(error) => write!(f, "internal error: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for BotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BotError::Internal(error) => Some(&**error),
+            _ => None,
+        }
+    }
+}
+
+/// Random id shown to the user and stored with the report so support can look it up. 64 bits, so
+/// that two reports practically never get the same one
+fn new_error_id() -> String {
+    format!("{:016x}", rand::thread_rng().gen::<u64>())
+}
+
+/// Used as the `on_error` of the framework
+pub async fn on_error(error: FrameworkError<'_, Data, Error>) {
+    // The command is over once the user was told about these
+    let finished = match &error {
+        FrameworkError::Command { ctx, .. } | FrameworkError::ArgumentParse { ctx, .. } => {
+            Some(*ctx)
+        }
+        FrameworkError::CommandStructureMismatch { ctx, .. } => {
+            Some(poise::Context::Application(*ctx))
+        }
+        _ => None,
+    };
+
+    // Never gets to `pre_command`, but these are the attempts an audit is most interested in
+    if let FrameworkError::NotAnOwner { ctx } = &error {
+        if let Err(err) = audit::record(*ctx, false).await {
+            warn!("Could not record an attempt to use an owner command: {err}");
+        }
+    }
+
+    handle(error).await;
+
+    if let Some(ctx) = finished {
+        hooks::command_finished(ctx, "error").await;
+    }
+}
+
+async fn handle(error: FrameworkError<'_, Data, Error>) {
+    let (ctx, error) = match error {
+        FrameworkError::Command { error, ctx } => (ctx, BotError::from_error(error)),
+        FrameworkError::ArgumentParse { error, input, ctx } => (
+            ctx,
+            BotError::ArgumentParse {
+                input,
+                message: error.to_string(),
+            },
+        ),
+        FrameworkError::CommandStructureMismatch { description, ctx } => (
+            poise::Context::Application(ctx),
+            BotError::Internal(
+                format!("command structure mismatch: {description} (are commands registered?)")
+                    .into(),
+            ),
+        ),
+        FrameworkError::CooldownHit {
+            remaining_cooldown,
+            ctx,
+        } => (ctx, BotError::Cooldown(remaining_cooldown)),
+        FrameworkError::MissingBotPermissions {
+            missing_permissions,
+            ctx,
+        } => (
+            ctx,
+            BotError::Permission(format!(
+                "I need the {missing_permissions} permission(s) to do this"
+            )),
+        ),
+        FrameworkError::MissingUserPermissions {
+            missing_permissions: Some(missing_permissions),
+            ctx,
+        } => (
+            ctx,
+            BotError::Permission(format!(
+                "You need the {missing_permissions} permission(s) to use this command"
+            )),
+        ),
+        FrameworkError::MissingUserPermissions {
+            missing_permissions: None,
+            ctx,
+        } => (
+            ctx,
+            BotError::Permission(
+                "Could not check your permissions, so you can't use this command".to_string(),
+            ),
+        ),
+        FrameworkError::NotAnOwner { ctx } => (
+            ctx,
+            BotError::Permission("Only the owners of the bot can use this command".to_string()),
+        ),
+        FrameworkError::GuildOnly { ctx } => (
+            ctx,
+            BotError::User("This command can only be used in servers".to_string()),
+        ),
+        FrameworkError::DmOnly { ctx } => (
+            ctx,
+            BotError::User("This command can only be used in DMs".to_string()),
+        ),
+        FrameworkError::NsfwOnly { ctx } => (
+            ctx,
+            BotError::User("This command can only be used in NSFW channels".to_string()),
+        ),
+        FrameworkError::CommandCheckFailed {
+            error: Some(error),
+            ctx,
+        } => (ctx, BotError::from_error(error)),
+        FrameworkError::CommandCheckFailed { error: None, ctx } => (
+            ctx,
+            BotError::Permission("You can't use this command here".to_string()),
+        ),
+        // Errors below happen outside of a command, so there is nobody to reply to
+        FrameworkError::Setup { error, .. } => {
+            error!("Failed to set up the bot: {error}");
+            return;
+        }
+        FrameworkError::EventHandler {
+            error,
+            event,
+            framework,
+            ..
+        } => {
+            let id = new_error_id();
+            error!(error_id = %id, "Event handler for {} failed: {error}", event.name());
+
+            let data = framework.user_data().await;
+            data.metrics.error("internal");
+            let report = Report {
+                id: &id,
+                kind: "internal",
+                command: None,
+                guild_id: None,
+                channel_id: None,
+                user_id: None,
+                message: &format!("event handler for {}: {error}", event.name()),
+            };
+            report.save(&data.db).await;
+            return;
+        }
+        FrameworkError::DynamicPrefix { error, ctx, msg } => {
+            let id = new_error_id();
+            error!(error_id = %id, "Failed to resolve the prefix of a message: {error}");
+
+            let report = Report {
+                id: &id,
+                kind: "internal",
+                command: None,
+                guild_id: msg.guild_id,
+                channel_id: Some(msg.channel_id),
+                user_id: Some(msg.author.id),
+                message: &format!("dynamic prefix: {error}"),
+            };
+            ctx.data.metrics.error("internal");
+            report.save(&ctx.data.db).await;
+            return;
+        }
+        // Not every message starting with a prefix is meant for us
+        FrameworkError::UnknownCommand { .. } | FrameworkError::UnknownInteraction { .. } => return,
+        FrameworkError::__NonExhaustive => unreachable!(),
+    };
+
+    handle_command_error(ctx, error).await;
+}
+
+async fn handle_command_error(ctx: Context<'_>, error: BotError) {
+    let command = ctx.command().qualified_name.as_str();
+    let mut content = error.user_message();
+    ctx.data().metrics.error(error.kind());
+
+    // Replying would only let them know it's worth trying to get around the block
+    if let BotError::Blocked = error {
+        debug!(
+            command,
+            user = ctx.author().id.0,
+            "Ignored a blocked command"
+        );
+        return;
+    }
+
+    // Cooldowns and maintenance are not really failures, so they don't need to be tracked
+    if !matches!(error, BotError::Cooldown(_) | BotError::Maintenance(_)) {
+        let id = new_error_id();
+
+        match &error {
+            BotError::Internal(internal) => {
+                error!(error_id = %id, command, "Command failed: {internal}")
+            }
+            other => debug!(error_id = %id, command, "Command was rejected: {other}"),
+        }
+
+        let message = error.to_string();
+        let report = Report {
+            id: &id,
+            kind: error.kind(),
+            command: Some(command),
+            guild_id: ctx.guild_id(),
+            channel_id: Some(ctx.channel_id()),
+            user_id: Some(ctx.author().id),
+            message: &message,
+        };
+        report.save(&ctx.data().db).await;
+
+        if let BotError::Internal(_) = error {
+            content = format!(
+                "{content}. If this keeps happening, contact support with the error ID `{id}`"
+            );
+        } else {
+            content = format!("{content}\n(error ID: `{id}`)");
+        }
+    }
+
+    let reply = ctx.send(|b| b.content(content).ephemeral(true)).await;
+    if let Err(err) = reply {
+        warn!("Could not tell the user about an error: {err}");
+    }
+}
+
+/// A row of the `error_reports` table
+struct Report<'a> {
+    id: &'a str,
+    kind: &'a str,
+    command: Option<&'a str>,
+    guild_id: Option<serenity::GuildId>,
+    channel_id: Option<serenity::ChannelId>,
+    user_id: Option<serenity::UserId>,
+    message: &'a str,
+}
+
+impl Report<'_> {
+    async fn save(&self, db: &PgPool) {
+        let result = sqlx::query(
+            "INSERT INTO error_reports (id, kind, command, guild_id, channel_id, user_id, message)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(self.id)
+        .bind(self.kind)
+        .bind(self.command)
+        .bind(self.guild_id.map(|x| x.0 as i64))
+        .bind(self.channel_id.map(|x| x.0 as i64))
+        .bind(self.user_id.map(|x| x.0 as i64))
+        .bind(self.message)
+        .execute(db)
+        .await;
+
+        if let Err(err) = result {
+            error!(error_id = %self.id, "Could not save the error report: {err}");
+        }
+    }
+}
+
+/// Formats a duration the way a person would say it, like `1m 30s`
+pub fn format_duration(duration: Duration) -> String {
+    let total = duration.as_secs().max(1);
+    let (days, hours, minutes, seconds) = (
+        total / 86400,
+        total / 3600 % 24,
+        total / 60 % 60,
+        total % 60,
+    );
+
+    let parts: Vec<_> = [(days, "d"), (hours, "h"), (minutes, "m"), (seconds, "s")]
+        .into_iter()
+        .filter(|(amount, _)| *amount > 0)
+        .map(|(amount, unit)| format!("{amount}{unit}"))
+        .collect();
+
+    parts.join(" ")
+}
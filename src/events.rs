@@ -0,0 +1,37 @@
+use poise::serenity_prelude as serenity;
+use tracing::{info, warn};
+
+use crate::blocklist::Kind;
+use crate::{Data, Error};
+
+/// Used as the `event_handler` of the framework, gets every event from discord
+pub async fn handle(
+    ctx: &serenity::Context,
+    event: &poise::Event<'_>,
+    framework: poise::FrameworkContext<'_, Data, Error>,
+    data: &Data,
+) -> Result<(), Error> {
+    match event {
+        // Every shard starts out with the default presence
+        poise::Event::Ready { .. } if data.maintenance.is_enabled() => {
+            let (activity, status) = data.maintenance.presence();
+            ctx.set_presence(activity, status).await;
+        }
+        // Also happens for every guild on startup, so blocked guilds are left even if they were
+        // blocked while the bot was offline
+        poise::Event::GuildCreate { guild, .. }
+            if data.blocklist.is_blocked(Kind::Guild, guild.id.0) =>
+        {
+            info!(guild = guild.id.0, "Leaving blocked guild {}", guild.name);
+            if let Err(err) = guild.id.leave(ctx).await {
+                warn!(guild = guild.id.0, "Could not leave blocked guild: {err}");
+            }
+        }
+        _ => {}
+    }
+
+    for handler in &data.event_handlers {
+        handler(ctx, event, framework, data).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// State of commands that aren't part of the bot, one value per type. Added with
+/// [`BotBuilder::data`](crate::BotBuilder::data) and used like `ctx.data().extensions.get::<T>()`.
+#[derive(Default)]
+pub struct Extensions(HashMap<TypeId, Box<dyn Any + Send + Sync>>);
+
+impl Extensions {
+    /// Replaces the value of the same type if there is one
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) {
+        self.0.insert(TypeId::of::<T>(), Box::new(value));
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.0.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+}
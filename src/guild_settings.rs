@@ -0,0 +1,27 @@
+use poise::serenity_prelude as serenity;
+use serenity::{GuildId, RoleId};
+use sqlx::PgPool;
+
+use crate::Error;
+
+/// The role given to muted members of the guild, if one was set
+pub async fn mute_role(db: &PgPool, guild_id: GuildId) -> Result<Option<RoleId>, Error> {
+    let role_id: Option<Option<i64>> =
+        sqlx::query_scalar("SELECT mute_role_id FROM guild_settings WHERE guild_id = $1")
+            .bind(guild_id.0 as i64)
+            .fetch_optional(db)
+            .await?;
+    Ok(role_id.flatten().map(|x| RoleId(x as u64)))
+}
+
+pub async fn set_mute_role(db: &PgPool, guild_id: GuildId, role_id: RoleId) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO guild_settings (guild_id, mute_role_id) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET mute_role_id = excluded.mute_role_id",
+    )
+    .bind(guild_id.0 as i64)
+    .bind(role_id.0 as i64)
+    .execute(db)
+    .await?;
+    Ok(())
+}
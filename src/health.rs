@@ -0,0 +1,143 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use ::serenity::gateway::ConnectionStage;
+use poise::serenity_prelude as serenity;
+use serde::Serialize;
+use serenity::ShardManager;
+use sqlx::PgPool;
+use tokio::sync::Mutex;
+
+use crate::shutdown::Shutdown;
+
+/// The database counts as down if it doesn't answer a query within this time
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How far starting the bot got, filled in by `main` as it goes
+#[derive(Default)]
+pub struct Health {
+    migrated: AtomicBool,
+    registered: AtomicBool,
+    shard_manager: OnceLock<Arc<Mutex<ShardManager>>>,
+}
+
+impl Health {
+    pub fn set_migrated(&self) {
+        self.migrated.store(true, Ordering::SeqCst);
+    }
+
+    /// Slash commands were registered in the `setup` of the framework
+    pub fn set_registered(&self) {
+        self.registered.store(true, Ordering::SeqCst);
+    }
+
+    pub fn set_shard_manager(&self, shard_manager: Arc<Mutex<ShardManager>>) {
+        let _ = self.shard_manager.set(shard_manager);
+    }
+
+    /// Only there once the framework was built
+    pub fn shard_manager(&self) -> Option<&Arc<Mutex<ShardManager>>> {
+        self.shard_manager.get()
+    }
+
+    /// Runs every check, the bot is ready when all of them passed
+    pub async fn readiness(&self, db: &PgPool, shutdown: &Shutdown) -> Readiness {
+        let checks = vec![
+            Check::flag(
+                "migrations",
+                self.migrated.load(Ordering::SeqCst),
+                "not applied yet",
+            ),
+            Check::flag(
+                "commands_registered",
+                self.registered.load(Ordering::SeqCst),
+                "setup has not finished",
+            ),
+            self.shards().await,
+            database(db).await,
+            Check::flag(
+                "accepting_commands",
+                !shutdown.is_stopping(),
+                "shutting down",
+            ),
+        ];
+
+        Readiness {
+            ready: checks.iter().all(|x| x.ok),
+            checks,
+        }
+    }
+
+    async fn shards(&self) -> Check {
+        let Some(shard_manager) = self.shard_manager() else {
+            return Check::failed("shards", "not started yet".to_string());
+        };
+
+        let runners = shard_manager.lock().await.runners.clone();
+        let runners = runners.lock().await;
+        let connected = runners
+            .values()
+            .filter(|x| x.stage == ConnectionStage::Connected)
+            .count();
+
+        let detail = format!("{connected} of {} connected", runners.len());
+        if connected > 0 && connected == runners.len() {
+            Check::passed("shards", detail)
+        } else {
+            Check::failed("shards", detail)
+        }
+    }
+}
+
+async fn database(db: &PgPool) -> Check {
+    let query = sqlx::query("SELECT 1").execute(db);
+    match tokio::time::timeout(DATABASE_TIMEOUT, query).await {
+        Ok(Ok(_)) => Check::passed("database", "SELECT 1 succeeded".to_string()),
+        Ok(Err(err)) => Check::failed("database", err.to_string()),
+        Err(_) => Check::failed(
+            "database",
+            format!("no answer within {}s", DATABASE_TIMEOUT.as_secs()),
+        ),
+    }
+}
+
+/// Answer of `/readyz`
+#[derive(Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: Vec<Check>,
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    name: &'static str,
+    ok: bool,
+    detail: String,
+}
+
+impl Check {
+    fn passed(name: &'static str, detail: String) -> Self {
+        Self {
+            name,
+            ok: true,
+            detail,
+        }
+    }
+
+    fn failed(name: &'static str, detail: String) -> Self {
+        Self {
+            name,
+            ok: false,
+            detail,
+        }
+    }
+
+    fn flag(name: &'static str, ok: bool, failed: &str) -> Self {
+        if ok {
+            Self::passed(name, "ok".to_string())
+        } else {
+            Self::failed(name, failed.to_string())
+        }
+    }
+}
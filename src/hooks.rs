@@ -0,0 +1,58 @@
+use std::time::Instant;
+
+use tracing::warn;
+
+use crate::shutdown::RunningCommand;
+use crate::{audit, Context};
+
+/// Kept in the invocation data of the context, which lives as long as the command runs
+struct Started {
+    at: Instant,
+    /// Lets a shutdown wait for the command, however it ends
+    _running: RunningCommand,
+}
+
+/// Used as the `pre_command` of the framework
+pub async fn pre_command(ctx: Context<'_>) {
+    let data = ctx.data();
+    // Keeps track of running commands so shutting down can wait for them
+    let running = data.shutdown.command_started();
+    data.metrics.command_started(&ctx.command().qualified_name);
+
+    ctx.set_invocation_data(Started {
+        at: Instant::now(),
+        _running: running,
+    })
+    .await;
+
+    // Owners have no cooldowns
+    if !ctx.framework().options().owners.contains(&ctx.author().id) {
+        if let Err(err) = data.cooldowns.start(ctx).await {
+            warn!("Could not start the cooldown of a command: {err}");
+        }
+    }
+
+    if audit::is_audited(ctx) {
+        if let Err(err) = audit::record(ctx, true).await {
+            warn!("Could not record the use of an owner command: {err}");
+        }
+    }
+}
+
+/// Used as the `post_command` of the framework
+pub async fn post_command(ctx: Context<'_>) {
+    command_finished(ctx, "ok").await;
+}
+
+/// Called once a command is over, `outcome` is `ok` or `error`. Errors can also come from before
+/// `pre_command` (or from autocomplete), those have no [`Started`] and aren't counted.
+pub async fn command_finished(ctx: Context<'_>, outcome: &str) {
+    let started = ctx.invocation_data::<Started>().await.map(|x| x.at);
+    if let Some(started) = started {
+        ctx.data().metrics.command_finished(
+            &ctx.command().qualified_name,
+            outcome,
+            started.elapsed(),
+        );
+    }
+}
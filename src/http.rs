@@ -0,0 +1,70 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::{error, info};
+
+use crate::health::Health;
+use crate::metrics::Metrics;
+use crate::shutdown::Shutdown;
+
+/// What the HTTP handlers get to look at
+#[derive(Clone)]
+pub struct HttpState {
+    pub metrics: Arc<Metrics>,
+    pub health: Arc<Health>,
+    pub shutdown: Arc<Shutdown>,
+    pub db: PgPool,
+}
+
+/// Serves `/metrics`, `/healthz` and `/readyz` until the process exits, started in `main` when
+/// `HTTP_ADDRESS` is set
+pub async fn serve(address: SocketAddr, state: HttpState) {
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(state);
+
+    let server = match axum::Server::try_bind(&address) {
+        Ok(server) => server,
+        Err(err) => {
+            error!("Cannot listen on {address}: {err}");
+            return;
+        }
+    };
+
+    info!("Serving HTTP on {address}");
+    if let Err(err) = server.serve(app.into_make_service()).await {
+        error!("The HTTP server stopped: {err}");
+    }
+}
+
+async fn metrics(State(state): State<HttpState>) -> impl IntoResponse {
+    let shard_manager = state.health.shard_manager();
+    let body = state.metrics.render(shard_manager, &state.db).await;
+    ([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], body)
+}
+
+/// The process is alive and able to answer, nothing more
+async fn healthz() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Whether the bot is usable, with the result of every check
+async fn readyz(State(state): State<HttpState>) -> impl IntoResponse {
+    let readiness = state.health.readiness(&state.db, &state.shutdown).await;
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
@@ -0,0 +1,476 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use poise::futures_util::FutureExt;
+use poise::serenity_prelude as serenity;
+use poise::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serenity::Http;
+use sqlx::{PgPool, Postgres, Transaction};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+
+use crate::Error;
+
+/// How long an idle worker waits before looking for new jobs again
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// A job that has been running this long is assumed to belong to a worker that died
+const STALE_AFTER: Duration = Duration::from_secs(10 * 60);
+/// How often a running job tells the others it is still alive, so slow jobs never count as stale
+const HEARTBEAT: Duration = Duration::from_secs(60);
+/// Retries wait `BACKOFF_BASE * 2^(attempt - 1)`, but never longer than `BACKOFF_MAX`
+const BACKOFF_BASE: Duration = Duration::from_secs(10);
+const BACKOFF_MAX: Duration = Duration::from_secs(60 * 60);
+
+/// What jobs get to work with
+#[derive(Clone)]
+pub struct JobContext {
+    pub http: Arc<Http>,
+    pub db: PgPool,
+}
+
+/// A kind of work that is stored in the `jobs` table and run later by a worker.
+///
+/// The job itself is the payload, saved as JSON, so it should only contain what is needed to do
+/// the work. Every kind has to be added to the [`Registry`] in `main`.
+pub trait Job: Serialize + DeserializeOwned + Send + 'static {
+    /// Name of the job in the database, must never change once jobs of this kind were queued
+    const KIND: &'static str;
+    /// How often the job runs before it is given up on and marked as dead
+    const MAX_ATTEMPTS: i32 = 5;
+
+    /// Pending jobs of the same kind with the same key replace each other, which also makes them
+    /// cancellable with [`cancel`]
+    fn key(&self) -> Option<String> {
+        None
+    }
+
+    fn run(self, ctx: &JobContext) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+/// Queues a job to run at `run_at`, replacing pending jobs with the same key
+pub async fn enqueue_at<J: Job>(db: &PgPool, job: &J, run_at: DateTime<Utc>) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let id = enqueue_in(&mut transaction, job, run_at).await?;
+    transaction.commit().await?;
+    Ok(id)
+}
+
+/// Like [`enqueue_at`], but as part of a bigger transaction so the job is only queued if
+/// everything else worked out too
+pub async fn enqueue_in<J: Job>(
+    transaction: &mut Transaction<'_, Postgres>,
+    job: &J,
+    run_at: DateTime<Utc>,
+) -> Result<i64, Error> {
+    let key = job.key();
+    let payload = serde_json::to_value(job)?;
+
+    if let Some(key) = &key {
+        sqlx::query("DELETE FROM jobs WHERE kind = $1 AND key = $2 AND status = 'pending'")
+            .bind(J::KIND)
+            .bind(key)
+            .execute(&mut *transaction)
+            .await?;
+    }
+
+    let id = sqlx::query_scalar(
+        "INSERT INTO jobs (kind, key, payload, run_at, max_attempts) VALUES ($1, $2, $3, $4, $5)
+        RETURNING id",
+    )
+    .bind(J::KIND)
+    .bind(key)
+    .bind(payload)
+    .bind(run_at)
+    .bind(J::MAX_ATTEMPTS)
+    .fetch_one(&mut *transaction)
+    .await?;
+    Ok(id)
+}
+
+/// Removes the pending job of kind `J` with this key, returns false if there was none
+pub async fn cancel<J: Job>(db: &PgPool, key: &str) -> Result<bool, Error> {
+    let cancelled =
+        sqlx::query("DELETE FROM jobs WHERE kind = $1 AND key = $2 AND status = 'pending'")
+            .bind(J::KIND)
+            .bind(key)
+            .execute(db)
+            .await?
+            .rows_affected()
+            > 0;
+    Ok(cancelled)
+}
+
+type Handler = fn(
+    serde_json::Value,
+    JobContext,
+) -> Result<BoxFuture<'static, Result<(), Error>>, serde_json::Error>;
+
+fn handler<J: Job>(
+    payload: serde_json::Value,
+    ctx: JobContext,
+) -> Result<BoxFuture<'static, Result<(), Error>>, serde_json::Error> {
+    let job: J = serde_json::from_value(payload)?;
+    Ok(Box::pin(async move { job.run(&ctx).await }))
+}
+
+/// The kinds of jobs the workers know how to run
+#[derive(Default)]
+pub struct Registry {
+    handlers: HashMap<&'static str, Handler>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<J: Job>(mut self) -> Self {
+        if self.handlers.insert(J::KIND, handler::<J>).is_some() {
+            panic!("job kind `{}` was registered twice", J::KIND);
+        }
+        self
+    }
+}
+
+/// A claimed row of the `jobs` table
+#[derive(sqlx::FromRow)]
+struct Claimed {
+    id: i64,
+    kind: String,
+    payload: serde_json::Value,
+    attempts: i32,
+    max_attempts: i32,
+}
+
+/// The running workers, see [`start`]
+pub struct Workers {
+    shutdown: watch::Sender<bool>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Workers {
+    /// Stops the workers, letting each finish the job it is running, returns false if some were
+    /// still busy at `deadline`.
+    ///
+    /// Jobs that were cut off stay claimed and are picked up again once they count as stale.
+    pub async fn shutdown(self, deadline: Instant) -> bool {
+        let _ = self.shutdown.send(true);
+
+        let mut finished = true;
+        for mut handle in self.handles {
+            match tokio::time::timeout_at(deadline, &mut handle).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => error!("A job worker panicked: {err}"),
+                Err(_) => {
+                    handle.abort();
+                    finished = false;
+                }
+            }
+        }
+        finished
+    }
+}
+
+/// Starts `count` workers that run jobs until [`Workers::shutdown`] is called.
+///
+/// Jobs that are overdue, for example because the bot was offline, run right away.
+pub fn start(registry: Registry, ctx: JobContext, count: usize) -> Workers {
+    let registry = Arc::new(registry);
+    let (shutdown, shutdown_rx) = watch::channel(false);
+
+    let handles = (0..count)
+        .map(|worker| {
+            let registry = registry.clone();
+            let ctx = ctx.clone();
+            let shutdown_rx = shutdown_rx.clone();
+            tokio::spawn(work(worker, registry, ctx, shutdown_rx))
+        })
+        .collect();
+
+    Workers { shutdown, handles }
+}
+
+async fn work(
+    worker: usize,
+    registry: Arc<Registry>,
+    ctx: JobContext,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let kinds: Vec<&str> = registry.handlers.keys().copied().collect();
+
+    while !*shutdown.borrow() {
+        let claimed = match claim(&ctx.db, &kinds).await {
+            Ok(claimed) => claimed,
+            Err(err) => {
+                error!("Worker {worker} failed to claim a job: {err}");
+                None
+            }
+        };
+
+        match claimed {
+            Some(job) => run(&registry, &ctx, job).await,
+            // Nothing to do, wait until there might be
+            None => {
+                tokio::select! {
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                    _ = shutdown.changed() => {}
+                }
+            }
+        }
+    }
+
+    debug!("Worker {worker} stopped");
+}
+
+/// Takes the next due job, skipping the ones other workers are holding on to
+async fn claim(db: &PgPool, kinds: &[&str]) -> Result<Option<Claimed>, Error> {
+    // Jobs of workers that died halfway, for example when the bot crashed, get another go unless
+    // that was their last attempt
+    sqlx::query(
+        "UPDATE jobs SET locked_at = NULL,
+            status = CASE WHEN attempts >= max_attempts THEN 'dead' ELSE 'pending' END,
+            finished_at = CASE WHEN attempts >= max_attempts THEN now() END,
+            last_error = CASE WHEN attempts >= max_attempts
+                THEN 'the worker running it went away' ELSE last_error END
+        WHERE status = 'running' AND locked_at < now() - make_interval(secs => $1)",
+    )
+    .bind(STALE_AFTER.as_secs_f64())
+    .execute(db)
+    .await?;
+
+    let claimed = sqlx::query_as(
+        "UPDATE jobs SET status = 'running', locked_at = now(), attempts = attempts + 1
+        WHERE id = (
+            SELECT id FROM jobs
+            WHERE status = 'pending' AND run_at <= now() AND kind = ANY($1)
+            ORDER BY run_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, kind, payload, attempts, max_attempts",
+    )
+    .bind(kinds)
+    .fetch_optional(db)
+    .await?;
+    Ok(claimed)
+}
+
+async fn run(registry: &Registry, ctx: &JobContext, job: Claimed) {
+    // `claim` only hands out kinds that are registered
+    let handler = registry.handlers[job.kind.as_str()];
+
+    let result = match handler(job.payload, ctx.clone()) {
+        // A panic counts as a failed attempt instead of taking the worker down with the job
+        Ok(future) => {
+            match keep_alive(&ctx.db, job.id, AssertUnwindSafe(future).catch_unwind()).await {
+                Ok(result) => result,
+                Err(panic) => Err(format!("panicked: {}", panic_message(&*panic)).into()),
+            }
+        }
+        // Retrying won't make the payload any more readable
+        Err(err) => {
+            let err = format!("invalid payload: {err}");
+            if let Err(err) = finish(&ctx.db, job.id, "dead", Some(&err)).await {
+                error!("Failed to update job {}: {err}", job.id);
+            }
+            error!("Job {} ({}) is dead: {err}", job.id, job.kind);
+            return;
+        }
+    };
+
+    let update = match result {
+        Ok(()) => finish(&ctx.db, job.id, "done", None).await,
+        Err(err) if job.attempts >= job.max_attempts => {
+            error!(
+                "Job {} ({}) is dead after {} attempts: {err}",
+                job.id, job.kind, job.attempts
+            );
+            finish(&ctx.db, job.id, "dead", Some(&err.to_string())).await
+        }
+        Err(err) => {
+            let delay = backoff(job.attempts);
+            warn!(
+                "Job {} ({}) failed (attempt {}), retrying in {}s: {err}",
+                job.id,
+                job.kind,
+                job.attempts,
+                delay.as_secs()
+            );
+            retry(&ctx.db, job.id, delay, &err.to_string()).await
+        }
+    };
+
+    match update {
+        Ok(()) => info!("Ran job {} ({})", job.id, job.kind),
+        Err(err) => error!("Failed to update job {}: {err}", job.id),
+    }
+}
+
+/// Runs `future` while refreshing the lock of the job every [`HEARTBEAT`]
+async fn keep_alive<F: Future + Unpin>(db: &PgPool, id: i64, mut future: F) -> F::Output {
+    let mut heartbeat = tokio::time::interval_at(Instant::now() + HEARTBEAT, HEARTBEAT);
+    loop {
+        tokio::select! {
+            output = &mut future => return output,
+            _ = heartbeat.tick() => {
+                let touched = sqlx::query("UPDATE jobs SET locked_at = now() WHERE id = $1")
+                    .bind(id)
+                    .execute(db)
+                    .await;
+                if let Err(err) = touched {
+                    warn!("Failed to refresh the lock of job {id}: {err}");
+                }
+            }
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown reason"
+    }
+}
+
+fn backoff(attempts: i32) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    BACKOFF_BASE
+        .saturating_mul(2u32.pow(exponent))
+        .min(BACKOFF_MAX)
+}
+
+async fn finish(db: &PgPool, id: i64, status: &str, error: Option<&str>) -> Result<(), Error> {
+    sqlx::query(
+        "UPDATE jobs SET status = $2, last_error = coalesce($3, last_error), locked_at = NULL, finished_at = now()
+        WHERE id = $1",
+    )
+    .bind(id)
+    .bind(status)
+    .bind(error)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+async fn retry(db: &PgPool, id: i64, delay: Duration, error: &str) -> Result<(), Error> {
+    sqlx::query(
+        "UPDATE jobs SET status = 'pending', last_error = $3, locked_at = NULL,
+            run_at = now() + make_interval(secs => $2)
+        WHERE id = $1",
+    )
+    .bind(id)
+    .bind(delay.as_secs_f64())
+    .bind(error)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Panics;
+
+    impl Job for Panics {
+        const KIND: &'static str = "panics";
+        const MAX_ATTEMPTS: i32 = 1;
+
+        async fn run(self, _: &JobContext) -> Result<(), Error> {
+            panic!("the job broke")
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Works;
+
+    impl Job for Works {
+        const KIND: &'static str = "works";
+
+        async fn run(self, _: &JobContext) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    async fn status(db: &PgPool, id: i64) -> (String, Option<String>) {
+        sqlx::query_as("SELECT status, last_error FROM jobs WHERE id = $1")
+            .bind(id)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+
+    async fn wait_until_finished(db: &PgPool, id: i64) -> (String, Option<String>) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let (status, error) = status(db, id).await;
+            if status == "done" || status == "dead" {
+                return (status, error);
+            }
+            assert!(Instant::now() < deadline, "job {id} is still {status}");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[sqlx::test]
+    async fn a_panic_is_a_failed_attempt(db: PgPool) {
+        let ctx = JobContext {
+            http: Arc::new(Http::new("")),
+            db: db.clone(),
+        };
+        let registry = Registry::new().register::<Panics>().register::<Works>();
+        let workers = start(registry, ctx, 1);
+
+        let panics = enqueue_at(&db, &Panics, Utc::now()).await.unwrap();
+        let (status, error) = wait_until_finished(&db, panics).await;
+        assert_eq!(status, "dead");
+        assert_eq!(error.as_deref(), Some("panicked: the job broke"));
+
+        // The worker is still around for the next one
+        let works = enqueue_at(&db, &Works, Utc::now()).await.unwrap();
+        assert_eq!(wait_until_finished(&db, works).await.0, "done");
+
+        assert!(
+            workers
+                .shutdown(Instant::now() + Duration::from_secs(5))
+                .await
+        );
+    }
+
+    #[sqlx::test]
+    async fn stale_jobs_on_their_last_attempt_are_dead(db: PgPool) {
+        let last = enqueue_at(&db, &Panics, Utc::now()).await.unwrap();
+        let retried = enqueue_at(&db, &Works, Utc::now() + chrono::Duration::hours(1))
+            .await
+            .unwrap();
+        sqlx::query(
+            "UPDATE jobs SET status = 'running', attempts = 1, locked_at = now() - interval '1 day'",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        // Nothing is due, but the stale jobs are dealt with
+        assert!(claim(&db, &[Panics::KIND, Works::KIND])
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(status(&db, last).await.0, "dead");
+        assert_eq!(status(&db, retried).await.0, "pending");
+    }
+}
@@ -0,0 +1,132 @@
+//! The bot as a library, so other services can run it with commands, state, event handlers and
+//! background tasks of their own:
+//!
+//! ```ignore
+//! let bot = BotBuilder::new(config)
+//!     .command(my_command())
+//!     .data(MyState::default())
+//!     .task(|ctx| async move { my_task(ctx).await })
+//!     .build()
+//!     .await?;
+//! let code = bot.start().await;
+//! ```
+//!
+//! `src/main.rs` is the binary on top of it.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use blocklist::Blocklist;
+use cooldowns::Cooldowns;
+use sqlx::migrate::Migrator;
+use sqlx::PgPool;
+
+use maintenance::Maintenance;
+use metrics::Metrics;
+use overrides::OverrideCache;
+use poise::serenity_prelude as serenity;
+use prefixes::PrefixCache;
+use serenity::GatewayIntents;
+use shutdown::Shutdown;
+
+pub use bot::{Bot, BotBuilder, EventHandler, TaskContext};
+pub use extensions::Extensions;
+
+mod audit;
+mod blocklist;
+mod bot;
+mod cases;
+mod checks;
+pub mod cli;
+pub mod commands;
+pub mod config;
+mod cooldowns;
+mod duration;
+mod error;
+mod events;
+mod extensions;
+mod guild_settings;
+mod health;
+mod hooks;
+mod http;
+mod jobs;
+pub mod logging;
+mod maintenance;
+mod metrics;
+mod overrides;
+mod paginate;
+mod prefixes;
+mod recording;
+mod registration;
+mod reminders;
+mod scheduler;
+pub mod shutdown;
+#[cfg(feature = "otel")]
+mod telemetry;
+#[cfg(test)]
+mod testing;
+mod user_settings;
+mod when;
+
+// You might want to change this to include more privileged intents or to make it not be so broad
+const INTENTS: GatewayIntents =
+    GatewayIntents::non_privileged().union(serenity::GatewayIntents::MESSAGE_CONTENT);
+
+// How many jobs of the job queue can run at the same time
+const JOB_WORKERS: usize = 4;
+
+// The migrations in `migrations/`, applied when the bot starts and by the `migrate` subcommand
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+pub type Context<'a> = poise::Context<'a, Data, Error>;
+pub type Framework = poise::Framework<Data, Error>;
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+// Data shared across commands and events
+pub struct Data {
+    pub db: PgPool,
+    pub prefixes: PrefixCache,
+    pub shutdown: Arc<Shutdown>,
+    pub metrics: Arc<Metrics>,
+    pub blocklist: Blocklist,
+    pub maintenance: Maintenance,
+    pub overrides: OverrideCache,
+    pub cooldowns: Cooldowns,
+    /// What was added with [`BotBuilder::data`]
+    pub extensions: Extensions,
+    /// Added with [`BotBuilder::event_handler`], run after the bot handled the event itself
+    pub event_handlers: Vec<EventHandler>,
+}
+
+/// Everything the framework does around commands, shared with the tests so they run commands the
+/// same way the bot does
+fn framework_options(
+    commands: Vec<commands::Command>,
+    owners: HashSet<serenity::UserId>,
+) -> poise::FrameworkOptions<Data, Error> {
+    poise::FrameworkOptions {
+        prefix_options: poise::PrefixFrameworkOptions {
+            // Guilds can set their own prefixes, the ones from `PREFIXES` are used otherwise
+            stripped_dynamic_prefix: Some(prefixes::strip_prefix),
+            edit_tracker: Some(poise::EditTracker::for_timespan(
+                std::time::Duration::from_secs(120),
+            )),
+            ..Default::default()
+        },
+        commands,
+        // The owner of the application (or its team) is added to these on startup
+        owners,
+        // Replies to the user and keeps a report of what went wrong
+        on_error: |error| Box::pin(error::on_error(error)),
+        // Keep track of running commands for metrics and shutting down
+        pre_command: |ctx| Box::pin(hooks::pre_command(ctx)),
+        post_command: |ctx| Box::pin(hooks::post_command(ctx)),
+        command_check: Some(|ctx| Box::pin(checks::command_check(ctx))),
+        // Cooldowns are checked in `command_check` instead, so they survive restarts
+        manual_cooldowns: true,
+        event_handler: |ctx, event, framework, data| {
+            Box::pin(events::handle(ctx, event, framework, data))
+        },
+        ..Default::default()
+    }
+}
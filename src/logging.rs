@@ -0,0 +1,442 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use poise::{BoxFuture, FrameworkError};
+use tracing::metadata::LevelFilter;
+use tracing::{info_span, Instrument, Span, Subscriber};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::commands::Command;
+use crate::config::Config;
+use crate::{Context, Data, Error};
+
+/// Secrets are replaced with this in log lines
+const REDACTED: &str = "<redacted>";
+
+/// How log lines are written, set with `LOG_FORMAT`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One line per event with every field
+    #[default]
+    Full,
+    /// Multiple lines per event, easy on the eyes while developing
+    Pretty,
+    /// One JSON object per line, for log collectors
+    Json,
+    /// Like `Full`, but shorter
+    Compact,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "full" => Ok(LogFormat::Full),
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            "compact" => Ok(LogFormat::Compact),
+            _ => Err("LOG_FORMAT must be one of full, pretty, json or compact".to_string()),
+        }
+    }
+}
+
+/// Logging to a file next to the terminal, set with `LOG_FILE`
+#[derive(Clone, Debug)]
+pub struct LogFile {
+    pub path: PathBuf,
+    /// The file is rotated once it would grow past this many bytes, and every day
+    pub max_size: u64,
+    /// Rotated files are deleted after this many days
+    pub max_days: u64,
+}
+
+/// Parses sizes like `512KB`, `10MB` or `1GB`, a plain number is in bytes
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim().to_uppercase();
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+
+    let factor = match unit.trim() {
+        "" | "B" => 1,
+        "K" | "KB" => 1024,
+        "M" | "MB" => 1024 * 1024,
+        "G" | "GB" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(factor)
+}
+
+/// Sets up logging to the terminal and optionally a file, and with the `otel` feature exporting
+/// traces.
+///
+/// What is logged is configured with `RUST_LOG` via the `env-filter` feature, warnings and errors
+/// by default. The discord token and the database password never show up in a log line.
+pub fn init(config: &Config) -> Result<(), Error> {
+    let secrets: Arc<[String]> = config
+        .secrets()
+        .into_iter()
+        .filter(|x| !x.is_empty())
+        .collect();
+
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::WARN.into())
+        .from_env_lossy();
+
+    let stdout = Redact {
+        inner: io::stdout,
+        secrets: secrets.clone(),
+    };
+    let file = match &config.log_file {
+        Some(file) => {
+            let writer = Redact {
+                inner: Mutex::new(RotatingFile::open(file.clone())?),
+                secrets,
+            };
+            Some(layer(config.log_format, writer, false))
+        }
+        None => None,
+    };
+
+    // The filter only applies to logs, traces pick what they need themselves
+    let registry = tracing_subscriber::registry().with(
+        layer(config.log_format, stdout, true)
+            .and_then(file)
+            .with_filter(filter),
+    );
+
+    #[cfg(feature = "otel")]
+    let registry = registry.with(match &config.otlp_endpoint {
+        Some(endpoint) => Some(crate::telemetry::layer(endpoint)?),
+        None => None,
+    });
+
+    registry.try_init()?;
+    Ok(())
+}
+
+/// Makes sure everything was written or sent before the process exits
+pub async fn shutdown() {
+    #[cfg(feature = "otel")]
+    crate::telemetry::shutdown().await;
+}
+
+fn layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+
+    match format {
+        LogFormat::Full => layer.boxed(),
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Json => layer.json().with_span_list(true).boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+    }
+}
+
+/// Replaces secrets in everything written through it
+struct Redact<M> {
+    inner: M,
+    secrets: Arc<[String]>,
+}
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redact<M> {
+    type Writer = RedactWriter<'a, M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactWriter {
+            inner: self.inner.make_writer(),
+            secrets: &self.secrets,
+            pending: Vec::new(),
+        }
+    }
+}
+
+/// Holds on to everything up to the end of a line, so a secret that is split between two writes
+/// is still found
+struct RedactWriter<'a, W: Write> {
+    inner: W,
+    secrets: &'a [String],
+    pending: Vec<u8>,
+}
+
+impl<W: Write> RedactWriter<'_, W> {
+    /// Writes the first `len` pending bytes with the secrets in them replaced
+    fn write_pending(&mut self, len: usize) -> io::Result<()> {
+        let pending: Vec<u8> = self.pending.drain(..len).collect();
+        let text = String::from_utf8_lossy(&pending);
+        if !self
+            .secrets
+            .iter()
+            .any(|secret| text.contains(secret.as_str()))
+        {
+            return self.inner.write_all(&pending);
+        }
+
+        let mut text = text.into_owned();
+        for secret in self.secrets {
+            text = text.replace(secret.as_str(), REDACTED);
+        }
+        self.inner.write_all(text.as_bytes())
+    }
+}
+
+impl<W: Write> Write for RedactWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        if let Some(end) = self.pending.iter().rposition(|x| *x == b'\n') {
+            self.write_pending(end + 1)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_pending(self.pending.len())?;
+        self.inner.flush()
+    }
+}
+
+// A writer is made for every event, whatever didn't end in a newline is written when it is done
+impl<W: Write> Drop for RedactWriter<'_, W> {
+    fn drop(&mut self) {
+        if !self.pending.is_empty() {
+            let _ = self.write_pending(self.pending.len());
+        }
+    }
+}
+
+/// A log file that is moved aside to `<path>.<date>_<time>` (with milliseconds) once it gets too big or a new day
+/// starts, deleting old ones as it goes
+struct RotatingFile {
+    options: LogFile,
+    file: File,
+    size: u64,
+    day: NaiveDate,
+}
+
+impl RotatingFile {
+    fn open(options: LogFile) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&options.path)?;
+
+        // Picks up where the last run left off, which may have been yesterday
+        let metadata = file.metadata()?;
+        let modified: DateTime<Utc> = metadata.modified()?.into();
+
+        let rotating = Self {
+            options,
+            file,
+            size: metadata.len(),
+            day: modified.date_naive(),
+        };
+        rotating.delete_old();
+        Ok(rotating)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let now = Utc::now();
+        let mut name = self.options.path.clone().into_os_string();
+        name.push(now.format(".%Y-%m-%d_%H-%M-%S%.3f").to_string());
+        // Rotating twice in the same millisecond shouldn't overwrite the first one
+        let mut rotated = PathBuf::from(&name);
+        let mut counter = 1;
+        while rotated.exists() {
+            let mut numbered = name.clone();
+            numbered.push(format!(".{counter}"));
+            rotated = PathBuf::from(numbered);
+            counter += 1;
+        }
+
+        self.file.flush()?;
+        fs::rename(&self.options.path, rotated)?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.options.path)?;
+        self.size = 0;
+        self.day = now.date_naive();
+
+        self.delete_old();
+        Ok(())
+    }
+
+    /// Deletes rotated files that are older than `max_days`
+    fn delete_old(&self) {
+        let path = &self.options.path;
+        let (Some(directory), Some(name)) = (path.parent(), path.file_name()) else {
+            return;
+        };
+        let directory = if directory.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            directory.to_path_buf()
+        };
+        let prefix = format!("{}.", name.to_string_lossy());
+        let max_age = Duration::from_secs(self.options.max_days * 24 * 60 * 60);
+
+        let Ok(entries) = fs::read_dir(directory) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if !entry.file_name().to_string_lossy().starts_with(&prefix) {
+                continue;
+            }
+            let age = entry
+                .metadata()
+                .and_then(|x| x.modified())
+                .ok()
+                .and_then(|x| SystemTime::now().duration_since(x).ok());
+            if age.is_some_and(|age| age > max_age) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let too_big = self.size > 0 && self.size + buf.len() as u64 > self.options.max_size;
+        if too_big || Utc::now().date_naive() != self.day {
+            // Logging on into the old file beats losing the line
+            if let Err(err) = self.rotate() {
+                eprintln!("Could not rotate the log file: {err}");
+            }
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+type PrefixAction = for<'a> fn(
+    poise::PrefixContext<'a, Data, Error>,
+) -> BoxFuture<'a, Result<(), FrameworkError<'a, Data, Error>>>;
+type SlashAction = for<'a> fn(
+    poise::ApplicationContext<'a, Data, Error>,
+) -> BoxFuture<'a, Result<(), FrameworkError<'a, Data, Error>>>;
+
+/// The actions of a command before [`instrument`] wrapped them, kept in its `custom_data`
+struct Actions {
+    prefix: Option<PrefixAction>,
+    slash: Option<SlashAction>,
+}
+
+/// Runs every command (and subcommand) in a `command` span with the command, guild, channel, user
+/// and shard as fields, so everything logged while a command runs can be traced back to it.
+///
+/// This takes over `custom_data` of the commands.
+pub fn instrument(commands: &mut [Command]) {
+    for command in commands {
+        let actions = Actions {
+            prefix: command.prefix_action,
+            slash: command.slash_action,
+        };
+        command.prefix_action = actions.prefix.map(|_| traced_prefix as PrefixAction);
+        command.slash_action = actions.slash.map(|_| traced_slash as SlashAction);
+        command.custom_data = Box::new(actions);
+
+        instrument(&mut command.subcommands);
+    }
+}
+
+fn actions(command: &Command) -> &Actions {
+    command
+        .custom_data
+        .downcast_ref()
+        .expect("commands are instrumented before they are used")
+}
+
+fn traced_prefix(
+    ctx: poise::PrefixContext<'_, Data, Error>,
+) -> BoxFuture<'_, Result<(), FrameworkError<'_, Data, Error>>> {
+    let action = actions(ctx.command).prefix.unwrap();
+    let span = command_span(poise::Context::Prefix(ctx));
+    Box::pin(action(ctx).instrument(span))
+}
+
+fn traced_slash(
+    ctx: poise::ApplicationContext<'_, Data, Error>,
+) -> BoxFuture<'_, Result<(), FrameworkError<'_, Data, Error>>> {
+    let action = actions(ctx.command).slash.unwrap();
+    let span = command_span(poise::Context::Application(ctx));
+    Box::pin(action(ctx).instrument(span))
+}
+
+fn command_span(ctx: Context<'_>) -> Span {
+    // Without a parent so every command is a trace of its own when exporting traces
+    info_span!(
+        parent: None,
+        "command",
+        command = %ctx.command().qualified_name,
+        guild = ctx.guild_id().map(|x| x.0),
+        channel = ctx.channel_id().0,
+        user = ctx.author().id.0,
+        shard = ctx.serenity_context().shard_id,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_secrets_split_between_writes() {
+        let secrets = vec!["hunter2".to_string()];
+        let mut out = Vec::new();
+        {
+            let mut writer = RedactWriter {
+                inner: &mut out,
+                secrets: &secrets,
+                pending: Vec::new(),
+            };
+            writer.write_all(b"token hun").unwrap();
+            writer.write_all(b"ter2 and\nthen hunt").unwrap();
+            writer.write_all(b"er2").unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("token {REDACTED} and\nthen {REDACTED}")
+        );
+    }
+
+    #[test]
+    fn keeps_every_rotated_file() {
+        let directory = std::env::temp_dir().join(format!("logs-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let options = LogFile {
+            path: directory.join("bot.log"),
+            max_size: 1024,
+            max_days: 1,
+        };
+
+        let mut file = RotatingFile::open(options).unwrap();
+        for _ in 0..3 {
+            file.write_all(b"line\n").unwrap();
+            file.rotate().unwrap();
+        }
+        let files = fs::read_dir(&directory).unwrap().count();
+        fs::remove_dir_all(&directory).unwrap();
+        // Three rotated ones and the current one
+        assert_eq!(files, 4);
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::RwLock;
+
+use poise::serenity_prelude as serenity;
+use serenity::{Activity, OnlineStatus, UserId};
+use sqlx::PgPool;
+
+use crate::Error;
+
+/// Shown as what the bot is playing while in maintenance mode
+const PRESENCE: &str = "Under maintenance";
+
+#[derive(Clone, Debug, Default)]
+struct State {
+    enabled: bool,
+    /// Replaces the notice from `MAINTENANCE_MESSAGE` when set
+    message: Option<String>,
+}
+
+// While in maintenance mode only owners can use commands. The state is stored in the database so
+// it survives restarts, and cached since it is checked before every command.
+pub struct Maintenance {
+    default_message: String,
+    state: RwLock<State>,
+    /// What `/owner status` set, shown again once maintenance mode is turned off
+    status: RwLock<Option<String>>,
+}
+
+impl Maintenance {
+    /// Loads the state from the last run, `enable` (from `MAINTENANCE`) turns it on no matter what
+    /// it was
+    pub async fn load(db: &PgPool, default_message: String, enable: bool) -> Result<Self, Error> {
+        let row: Option<(bool, Option<String>)> =
+            sqlx::query_as("SELECT enabled, message FROM maintenance")
+                .fetch_optional(db)
+                .await?;
+
+        let maintenance = Self {
+            default_message,
+            state: RwLock::new(match row {
+                Some((enabled, message)) => State { enabled, message },
+                None => State::default(),
+            }),
+            status: RwLock::default(),
+        };
+
+        if enable && !maintenance.is_enabled() {
+            let message = maintenance.state.read().unwrap().message.clone();
+            maintenance.set(db, true, message, None).await?;
+        }
+        Ok(maintenance)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.read().unwrap().enabled
+    }
+
+    /// What users are told when they try to use a command, `None` when not in maintenance mode
+    pub fn notice(&self) -> Option<String> {
+        let state = self.state.read().unwrap();
+        state.enabled.then(|| {
+            state
+                .message
+                .clone()
+                .unwrap_or_else(|| self.default_message.clone())
+        })
+    }
+
+    /// Turns maintenance mode on or off, the message replaces the configured notice until it is
+    /// turned on again
+    pub async fn set(
+        &self,
+        db: &PgPool,
+        enabled: bool,
+        message: Option<String>,
+        by: Option<UserId>,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO maintenance (enabled, message, updated_by, updated_at)
+            VALUES ($1, $2, $3, now())
+            ON CONFLICT (id) DO UPDATE SET enabled = excluded.enabled,
+                message = excluded.message, updated_by = excluded.updated_by,
+                updated_at = excluded.updated_at",
+        )
+        .bind(enabled)
+        .bind(&message)
+        .bind(by.map(|x| x.0 as i64))
+        .execute(db)
+        .await?;
+
+        *self.state.write().unwrap() = State { enabled, message };
+        Ok(())
+    }
+
+    /// Remembers what the bot is playing outside of maintenance mode, `None` clears it
+    pub fn set_status(&self, text: Option<String>) {
+        *self.status.write().unwrap() = text;
+    }
+
+    /// How the bot shows up in the member list, with "Do Not Disturb" while in maintenance mode and
+    /// the status from `/owner status` otherwise
+    pub fn presence(&self) -> (Option<Activity>, OnlineStatus) {
+        if self.is_enabled() {
+            (
+                Some(Activity::playing(PRESENCE)),
+                OnlineStatus::DoNotDisturb,
+            )
+        } else {
+            let status = self.status.read().unwrap();
+            (
+                status.as_deref().map(Activity::playing),
+                OnlineStatus::Online,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn restores_the_status_after_maintenance(db: PgPool) {
+        let maintenance = Maintenance::load(&db, "Back soon".into(), false)
+            .await
+            .unwrap();
+        maintenance.set_status(Some("chess".into()));
+
+        maintenance.set(&db, true, None, None).await.unwrap();
+        let (activity, status) = maintenance.presence();
+        assert_eq!(activity.unwrap().name, PRESENCE);
+        assert_eq!(status, OnlineStatus::DoNotDisturb);
+
+        maintenance.set(&db, false, None, None).await.unwrap();
+        let (activity, status) = maintenance.presence();
+        assert_eq!(activity.unwrap().name, "chess");
+        assert_eq!(status, OnlineStatus::Online);
+    }
+}
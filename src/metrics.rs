@@ -0,0 +1,160 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use poise::serenity_prelude as serenity;
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, Gauge, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+use serenity::ShardManager;
+use sqlx::PgPool;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// Everything the bot counts, served in the Prometheus text format on `/metrics`
+pub struct Metrics {
+    registry: Registry,
+    commands: IntCounterVec,
+    command_duration: HistogramVec,
+    errors: IntCounterVec,
+    shard_latency: GaugeVec,
+    shard_state: IntGaugeVec,
+    db_connections: IntGauge,
+    db_idle_connections: IntGauge,
+    db_acquire: Gauge,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let registry = Registry::new_custom(Some("bot".to_string()), None)
+            .expect("the metric prefix is valid");
+
+        let commands = IntCounterVec::new(
+            Opts::new("commands_total", "Commands that were invoked"),
+            &["command"],
+        )
+        .unwrap();
+        let command_duration = HistogramVec::new(
+            HistogramOpts::new("command_duration_seconds", "How long commands took to run"),
+            &["command", "outcome"],
+        )
+        .unwrap();
+        let errors = IntCounterVec::new(
+            Opts::new("errors_total", "Errors by their kind, see `BotError`"),
+            &["kind"],
+        )
+        .unwrap();
+        let shard_latency = GaugeVec::new(
+            Opts::new("shard_latency_seconds", "Heartbeat latency of each shard"),
+            &["shard"],
+        )
+        .unwrap();
+        let shard_state = IntGaugeVec::new(
+            Opts::new(
+                "shard_state",
+                "Connection stage of each shard, 1 for the current one",
+            ),
+            &["shard", "stage"],
+        )
+        .unwrap();
+        let db_connections =
+            IntGauge::new("db_connections", "Open connections of the database pool").unwrap();
+        let db_idle_connections = IntGauge::new(
+            "db_idle_connections",
+            "Idle connections of the database pool",
+        )
+        .unwrap();
+        let db_acquire = Gauge::new(
+            "db_acquire_seconds",
+            "How long getting a connection from the pool took at the last scrape",
+        )
+        .unwrap();
+
+        let metrics = Self {
+            registry,
+            commands,
+            command_duration,
+            errors,
+            shard_latency,
+            shard_state,
+            db_connections,
+            db_idle_connections,
+            db_acquire,
+        };
+
+        // Names are fixed and unique, so registering can't fail
+        let collectors: [Box<dyn Collector>; 8] = [
+            Box::new(metrics.commands.clone()),
+            Box::new(metrics.command_duration.clone()),
+            Box::new(metrics.errors.clone()),
+            Box::new(metrics.shard_latency.clone()),
+            Box::new(metrics.shard_state.clone()),
+            Box::new(metrics.db_connections.clone()),
+            Box::new(metrics.db_idle_connections.clone()),
+            Box::new(metrics.db_acquire.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+
+        metrics
+    }
+}
+
+impl Metrics {
+    pub fn command_started(&self, command: &str) {
+        self.commands.with_label_values(&[command]).inc();
+    }
+
+    /// `outcome` is `ok` or `error`
+    pub fn command_finished(&self, command: &str, outcome: &str, took: Duration) {
+        self.command_duration
+            .with_label_values(&[command, outcome])
+            .observe(took.as_secs_f64());
+    }
+
+    pub fn error(&self, kind: &str) {
+        self.errors.with_label_values(&[kind]).inc();
+    }
+
+    /// Takes a look at the shards and the database and encodes everything
+    pub async fn render(
+        &self,
+        shard_manager: Option<&Arc<Mutex<ShardManager>>>,
+        db: &PgPool,
+    ) -> String {
+        self.shard_latency.reset();
+        self.shard_state.reset();
+        // The shards only exist once the framework was built
+        if let Some(shard_manager) = shard_manager {
+            let runners = shard_manager.lock().await.runners.clone();
+            for (id, runner) in runners.lock().await.iter() {
+                let shard = id.0.to_string();
+                if let Some(latency) = runner.latency {
+                    self.shard_latency
+                        .with_label_values(&[&shard])
+                        .set(latency.as_secs_f64());
+                }
+                self.shard_state
+                    .with_label_values(&[&shard, &runner.stage.to_string()])
+                    .set(1);
+            }
+        }
+
+        self.db_connections.set(db.size().into());
+        self.db_idle_connections.set(db.num_idle() as i64);
+        // There are no statistics for waiting on the pool, so this measures it instead
+        let started = Instant::now();
+        match db.acquire().await {
+            Ok(_) => self.db_acquire.set(started.elapsed().as_secs_f64()),
+            Err(err) => warn!("Could not get a database connection for metrics: {err}"),
+        }
+
+        let mut buffer = Vec::new();
+        if let Err(err) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            warn!("Could not encode metrics: {err}");
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
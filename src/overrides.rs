@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use poise::serenity_prelude as serenity;
+use serenity::{ChannelId, GuildId, RoleId};
+use sqlx::PgPool;
+
+use crate::commands::Command;
+use crate::{Context, Error};
+
+/// Name of the command that manages overrides, it can't be overridden itself so admins can't lock
+/// themselves out
+pub const MANAGE_COMMAND: &str = "commands";
+
+/// What an override applies to
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Target {
+    /// A command by its qualified name, which includes its subcommands
+    Command(String),
+    /// Every command of a category
+    Category(String),
+}
+
+impl Target {
+    fn kind(&self) -> &'static str {
+        match self {
+            Target::Command(_) => "command",
+            Target::Category(_) => "category",
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Target::Command(name) | Target::Category(name) => name,
+        }
+    }
+
+    fn from_row(kind: &str, name: String) -> Option<Self> {
+        match kind {
+            "command" => Some(Target::Command(name)),
+            "category" => Some(Target::Category(name)),
+            _ => None,
+        }
+    }
+
+    /// Finds the command (like `prefix add`) or category a user means, ignoring case
+    pub fn resolve(commands: &[Command], input: &str) -> Option<Self> {
+        let input = input.trim().trim_start_matches('/');
+        let words: Vec<_> = input.split_whitespace().collect();
+        if let Some(command) = find_command(commands, &words) {
+            return Some(Target::Command(command.qualified_name.clone()));
+        }
+        categories(commands)
+            .into_iter()
+            .find(|x| x.eq_ignore_ascii_case(input))
+            .map(|x| Target::Category(x.to_string()))
+    }
+
+    /// Whether it applies to the last command of `path`, which starts at the top-level command
+    fn matches(&self, path: &[&Command]) -> bool {
+        match self {
+            Target::Command(name) => path.iter().any(|x| x.qualified_name == *name),
+            Target::Category(name) => category(path) == Some(name.as_str()),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Command(name) => write!(f, "`/{name}`"),
+            Target::Category(name) => write!(f, "the {name} category"),
+        }
+    }
+}
+
+/// A row of the `command_overrides` table
+#[derive(Clone, Debug)]
+pub struct Override {
+    pub target: Target,
+    pub disabled: bool,
+    /// Only usable in these channels, anywhere when empty
+    pub channels: Vec<ChannelId>,
+    /// Only usable by members with one of these roles, by everyone when empty
+    pub roles: Vec<RoleId>,
+}
+
+type Row = (String, String, bool, Vec<i64>, Vec<i64>);
+
+// Overrides of every guild a command was used in since startup, since they are looked at before
+// every command and for every command in `/help`
+#[derive(Default)]
+pub struct OverrideCache {
+    guilds: RwLock<HashMap<GuildId, Arc<[Override]>>>,
+}
+
+impl OverrideCache {
+    /// Every override of the guild, loaded from the database if they are not cached yet
+    pub async fn guild(&self, db: &PgPool, guild_id: GuildId) -> Result<Arc<[Override]>, Error> {
+        if let Some(overrides) = self.guilds.read().unwrap().get(&guild_id) {
+            return Ok(overrides.clone());
+        }
+
+        let rows: Vec<Row> = sqlx::query_as(
+            "SELECT kind, name, disabled, channel_ids, role_ids FROM command_overrides
+            WHERE guild_id = $1 ORDER BY kind, name",
+        )
+        .bind(guild_id.0 as i64)
+        .fetch_all(db)
+        .await?;
+
+        let overrides: Arc<[Override]> = rows
+            .into_iter()
+            .filter_map(|(kind, name, disabled, channels, roles)| {
+                Some(Override {
+                    target: Target::from_row(&kind, name)?,
+                    disabled,
+                    channels: channels.into_iter().map(|x| ChannelId(x as u64)).collect(),
+                    roles: roles.into_iter().map(|x| RoleId(x as u64)).collect(),
+                })
+            })
+            // Restrictions that were lifted leave an empty row behind
+            .filter(|x| x.disabled || !x.channels.is_empty() || !x.roles.is_empty())
+            .collect();
+
+        self.guilds
+            .write()
+            .unwrap()
+            .insert(guild_id, overrides.clone());
+
+        Ok(overrides)
+    }
+
+    /// Turns a command or category off or back on, returns false if it already was
+    pub async fn set_disabled(
+        &self,
+        db: &PgPool,
+        guild_id: GuildId,
+        target: &Target,
+        disabled: bool,
+    ) -> Result<bool, Error> {
+        // Only touches rows that actually change, so the count says whether anything did
+        let query = if disabled {
+            "INSERT INTO command_overrides (guild_id, kind, name, disabled) VALUES ($1, $2, $3, TRUE)
+            ON CONFLICT (guild_id, kind, name) DO UPDATE SET disabled = TRUE, updated_at = now()
+            WHERE NOT command_overrides.disabled"
+        } else {
+            "UPDATE command_overrides SET disabled = FALSE, updated_at = now()
+            WHERE guild_id = $1 AND kind = $2 AND name = $3 AND disabled"
+        };
+
+        let changed = sqlx::query(query)
+            .bind(guild_id.0 as i64)
+            .bind(target.kind())
+            .bind(target.name())
+            .execute(db)
+            .await?
+            .rows_affected()
+            > 0;
+
+        self.invalidate(guild_id);
+        Ok(changed)
+    }
+
+    /// Adds a channel and/or role to the ones a command or category is restricted to, or lifts
+    /// every restriction when neither is given
+    pub async fn restrict(
+        &self,
+        db: &PgPool,
+        guild_id: GuildId,
+        target: &Target,
+        channel: Option<ChannelId>,
+        role: Option<RoleId>,
+    ) -> Result<(), Error> {
+        let lift = channel.is_none() && role.is_none();
+        let query = if lift {
+            "UPDATE command_overrides SET channel_ids = '{}', role_ids = '{}', updated_at = now()
+            WHERE guild_id = $1 AND kind = $2 AND name = $3"
+        } else {
+            // The new ids are added to the ones that are already allowed
+            "INSERT INTO command_overrides (guild_id, kind, name, channel_ids, role_ids)
+            VALUES ($1, $2, $3, array_remove(ARRAY[$4::BIGINT], NULL),
+                array_remove(ARRAY[$5::BIGINT], NULL))
+            ON CONFLICT (guild_id, kind, name) DO UPDATE SET
+                channel_ids = ARRAY(SELECT DISTINCT unnest(
+                    command_overrides.channel_ids || excluded.channel_ids)),
+                role_ids = ARRAY(SELECT DISTINCT unnest(
+                    command_overrides.role_ids || excluded.role_ids)),
+                updated_at = now()"
+        };
+
+        let mut query = sqlx::query(query)
+            .bind(guild_id.0 as i64)
+            .bind(target.kind())
+            .bind(target.name());
+        if !lift {
+            query = query
+                .bind(channel.map(|x| x.0 as i64))
+                .bind(role.map(|x| x.0 as i64));
+        }
+        query.execute(db).await?;
+
+        self.invalidate(guild_id);
+        Ok(())
+    }
+
+    fn invalidate(&self, guild_id: GuildId) {
+        self.guilds.write().unwrap().remove(&guild_id);
+    }
+}
+
+/// Why the last command of `path` (which starts at the top-level command) can't be used here,
+/// `None` if it can
+pub async fn denial(ctx: Context<'_>, path: &[&Command]) -> Result<Option<String>, Error> {
+    let (Some(guild_id), Some(command)) = (ctx.guild_id(), path.last()) else {
+        return Ok(None);
+    };
+    if path[0].name == MANAGE_COMMAND {
+        return Ok(None);
+    }
+
+    let data = ctx.data();
+    let overrides = data.overrides.guild(&data.db, guild_id).await?;
+    let applicable: Vec<_> = overrides
+        .iter()
+        .filter(|x| x.target.matches(path))
+        .collect();
+    let name = &command.qualified_name;
+
+    if let Some(disabled) = applicable.iter().find(|x| x.disabled) {
+        let reason = match &disabled.target {
+            Target::Command(_) => format!("`/{name}` is turned off in this server"),
+            category => format!("`/{name}` is turned off in this server, like {category}"),
+        };
+        return Ok(Some(reason));
+    }
+
+    // Channels are only looked up when a command is actually restricted to some
+    if applicable.iter().any(|x| !x.channels.is_empty()) {
+        let channel = restricted_channel(ctx).await?;
+        for x in &applicable {
+            if !x.channels.is_empty() && !x.channels.contains(&channel) {
+                let channels: Vec<_> = x.channels.iter().map(|x| format!("<#{x}>")).collect();
+                return Ok(Some(format!(
+                    "`/{name}` can only be used in {}",
+                    channels.join(", ")
+                )));
+            }
+        }
+    }
+
+    // Members are only looked up when a role is actually needed
+    if applicable.iter().any(|x| !x.roles.is_empty()) {
+        let roles = match ctx.author_member().await {
+            Some(member) => member.roles.clone(),
+            None => Vec::new(),
+        };
+        for x in &applicable {
+            if !x.roles.is_empty() && !x.roles.iter().any(|role| roles.contains(role)) {
+                return Ok(Some(format!(
+                    "You don't have a role that is allowed to use `/{name}`"
+                )));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// The channel that restrictions are checked against, which is the parent channel inside of
+/// threads
+async fn restricted_channel(ctx: Context<'_>) -> Result<ChannelId, Error> {
+    // Threads usually aren't cached, `to_channel` asks discord then
+    let channel = ctx.channel_id().to_channel(ctx).await?;
+    Ok(match channel.guild() {
+        Some(channel) if channel.thread_metadata.is_some() => {
+            channel.parent_id.unwrap_or(channel.id)
+        }
+        _ => ctx.channel_id(),
+    })
+}
+
+/// The category of the last command of `path`, subcommands are in the category of their parent
+/// unless they have their own
+pub fn category<'a>(path: &[&'a Command]) -> Option<&'a str> {
+    path.iter().rev().find_map(|x| x.category)
+}
+
+/// Every category a command is in, sorted
+pub fn categories(commands: &[Command]) -> Vec<&str> {
+    let mut categories: Vec<_> = commands.iter().filter_map(|x| x.category).collect();
+    categories.sort_unstable();
+    categories.dedup();
+    categories
+}
+
+/// Finds a command by the words of its name, like `["prefix", "add"]`, ignoring case
+pub fn find_command<'a>(commands: &'a [Command], words: &[&str]) -> Option<&'a Command> {
+    let (first, rest) = words.split_first()?;
+    let command = commands
+        .iter()
+        .find(|x| x.name.eq_ignore_ascii_case(first))?;
+    if rest.is_empty() {
+        Some(command)
+    } else {
+        find_command(&command.subcommands, rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands;
+    use crate::testing::{Harness, Reply, CHANNEL_ID, GUILD_ID};
+
+    fn all() -> Vec<Command> {
+        let mut commands = commands::all().unwrap();
+        commands::set_qualified_names(&mut commands);
+        commands
+    }
+
+    #[test]
+    fn finds_commands_by_their_words() {
+        let commands = all();
+        let find = |words: &[&str]| find_command(&commands, words).map(|x| &x.qualified_name);
+
+        assert_eq!(find(&["prefix", "add"]).unwrap(), "prefix add");
+        assert_eq!(find(&["PREFIX", "Add"]).unwrap(), "prefix add");
+        assert_eq!(find(&["pong"]).unwrap(), "pong");
+        assert_eq!(find(&["prefix", "nothing"]), None);
+        assert_eq!(find(&["pong", "add"]), None);
+        assert_eq!(find(&[]), None);
+    }
+
+    #[test]
+    fn resolves_commands_and_categories() {
+        let commands = all();
+        let command = |name: &str| Some(Target::Command(name.to_string()));
+
+        assert_eq!(
+            Target::resolve(&commands, "/prefix add"),
+            command("prefix add")
+        );
+        assert_eq!(
+            Target::resolve(&commands, " Remind  ME "),
+            command("remind me")
+        );
+        assert_eq!(
+            Target::resolve(&commands, "moderation"),
+            Some(Target::Category("Moderation".to_string()))
+        );
+        assert_eq!(Target::resolve(&commands, "prefix nothing"), None);
+        assert_eq!(Target::resolve(&commands, "nothing"), None);
+    }
+
+    #[test]
+    fn subcommands_are_in_the_category_of_their_parent() {
+        let commands = all();
+        let case = find_command(&commands, &["case"]).unwrap();
+        let view = find_command(&commands, &["case", "view"]).unwrap();
+        let prefix = find_command(&commands, &["prefix"]).unwrap();
+        let add = find_command(&commands, &["prefix", "add"]).unwrap();
+
+        assert_eq!(category(&[case]), Some("Moderation"));
+        assert_eq!(category(&[case, view]), Some("Moderation"));
+        assert_eq!(category(&[prefix, add]), None);
+
+        let moderation = Target::Category("Moderation".to_string());
+        assert!(moderation.matches(&[case, view]));
+        assert!(!moderation.matches(&[prefix, add]));
+        assert!(Target::Command("prefix".to_string()).matches(&[prefix, add]));
+    }
+
+    #[sqlx::test]
+    async fn denies_what_was_turned_off_or_restricted(db: PgPool) {
+        let harness = Harness::new(db).await.in_guild();
+        let data = &harness.data;
+        let pong = Target::Command("pong".to_string());
+        let reply = |replies: Vec<Reply>| replies[0].content.clone().unwrap();
+
+        data.overrides
+            .set_disabled(&data.db, GUILD_ID, &pong, true)
+            .await
+            .unwrap();
+        let replies = harness.slash("pong", &[]).await;
+        assert!(reply(replies).starts_with("`/pong` is turned off in this server"));
+        data.overrides
+            .set_disabled(&data.db, GUILD_ID, &pong, false)
+            .await
+            .unwrap();
+
+        data.overrides
+            .restrict(&data.db, GUILD_ID, &pong, Some(ChannelId(1)), None)
+            .await
+            .unwrap();
+        let replies = harness.slash("pong", &[]).await;
+        assert!(reply(replies).starts_with("`/pong` can only be used in <#1>"));
+    }
+
+    #[sqlx::test]
+    async fn threads_are_restricted_like_their_channel(db: PgPool) {
+        let harness = Harness::new(db).await.in_thread();
+        let data = &harness.data;
+        let pong = Target::Command("pong".to_string());
+        data.overrides
+            .restrict(&data.db, GUILD_ID, &pong, Some(CHANNEL_ID), None)
+            .await
+            .unwrap();
+
+        let replies = harness.slash("pong", &[]).await;
+        assert_eq!(replies[0].content.as_deref(), Some("pong!"));
+    }
+}
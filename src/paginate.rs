@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use poise::serenity_prelude as serenity;
+use serenity::{ButtonStyle, CollectComponentInteraction, InteractionResponseType};
+
+use crate::{Context, Error};
+
+/// How long the buttons keep working after the last press
+pub const TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Shows `pages` one at a time in an embed, with buttons to flip through them
+pub async fn paginate(ctx: Context<'_>, title: &str, pages: &[String]) -> Result<(), Error> {
+    // The context id keeps the buttons apart from those of other invocations
+    let prefix = ctx.id().to_string();
+    let previous_id = format!("{prefix}previous");
+    let next_id = format!("{prefix}next");
+    let footer = |page: usize| format!("Page {} of {}", page + 1, pages.len());
+
+    let reply = ctx
+        .send(|b| {
+            b.embed(|e| {
+                e.title(title)
+                    .description(pages.first().map_or("Nothing here", |x| x.as_str()))
+                    .footer(|f| f.text(footer(0)))
+            });
+            if pages.len() > 1 {
+                b.components(|c| {
+                    c.create_action_row(|r| {
+                        r.create_button(|b| {
+                            b.custom_id(&previous_id)
+                                .label("Previous")
+                                .style(ButtonStyle::Secondary)
+                        })
+                        .create_button(|b| {
+                            b.custom_id(&next_id)
+                                .label("Next")
+                                .style(ButtonStyle::Secondary)
+                        })
+                    })
+                });
+            }
+            b
+        })
+        .await?;
+    if pages.len() <= 1 {
+        return Ok(());
+    }
+
+    let mut page = 0;
+    loop {
+        let prefix = prefix.clone();
+        let author = ctx.author().id;
+        let collect = CollectComponentInteraction::new(ctx.serenity_context())
+            // Only whoever used the command can flip through the pages
+            .filter(move |press| {
+                press.data.custom_id.starts_with(&prefix) && press.user.id == author
+            })
+            .timeout(TIMEOUT);
+        // A shutdown waits for the command, it shouldn't wait for the buttons to time out
+        let press = tokio::select! {
+            press = collect => press,
+            () = ctx.data().shutdown.stopped() => None,
+        };
+        let Some(press) = press else {
+            break;
+        };
+
+        page = if press.data.custom_id == next_id {
+            (page + 1) % pages.len()
+        } else {
+            page.checked_sub(1).unwrap_or(pages.len() - 1)
+        };
+
+        press
+            .create_interaction_response(ctx.serenity_context(), |r| {
+                r.kind(InteractionResponseType::UpdateMessage)
+                    .interaction_response_data(|d| {
+                        d.embed(|e| {
+                            e.title(title)
+                                .description(&pages[page])
+                                .footer(|f| f.text(footer(page)))
+                        })
+                    })
+            })
+            .await?;
+    }
+
+    // Buttons that do nothing anymore would only confuse
+    reply
+        .edit(ctx, |b| {
+            b.components(|c| c).embed(|e| {
+                e.title(title)
+                    .description(&pages[page])
+                    .footer(|f| f.text(footer(page)))
+            })
+        })
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use poise::{serenity_prelude as serenity, BoxFuture};
+use serenity::GuildId;
+use sqlx::PgPool;
+
+use crate::{Data, Error};
+
+/// Maximum amount of prefixes a single guild can have
+pub const MAX_GUILD_PREFIXES: usize = 10;
+/// Maximum length (in characters) of a single prefix
+pub const MAX_PREFIX_LENGTH: usize = 16;
+
+// Prefixes of every guild we have seen a message from since startup, so we don't have to ask the
+// database on every single message. A guild with an empty list uses the global prefixes.
+pub struct PrefixCache {
+    global: Vec<String>,
+    guilds: RwLock<HashMap<GuildId, Arc<[String]>>>,
+    /// Counts invalidations, so a load that raced with a change doesn't cache what it read
+    generation: AtomicU64,
+}
+
+/// What [`PrefixCache::add`] did
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddOutcome {
+    Added,
+    /// The guild already had the prefix
+    Exists,
+    /// The guild has [`MAX_GUILD_PREFIXES`] already
+    Full,
+}
+
+impl PrefixCache {
+    pub fn new(global: Vec<String>) -> Self {
+        Self {
+            global,
+            guilds: RwLock::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// The prefixes configured through the `PREFIXES` environment variable
+    pub fn global(&self) -> &[String] {
+        &self.global
+    }
+
+    /// Prefixes set by the guild itself, loaded from the database if they are not cached yet
+    pub async fn guild(&self, db: &PgPool, guild_id: GuildId) -> Result<Arc<[String]>, Error> {
+        if let Some(prefixes) = self.guilds.read().unwrap().get(&guild_id) {
+            return Ok(prefixes.clone());
+        }
+
+        let generation = self.generation.load(Ordering::SeqCst);
+        let prefixes: Arc<[String]> = sqlx::query_scalar(
+            "SELECT prefix FROM guild_prefixes WHERE guild_id = $1 ORDER BY created_at, prefix",
+        )
+        .bind(guild_id.0 as i64)
+        .fetch_all(db)
+        .await?
+        .into();
+
+        // The prefixes might have changed since they were read, they are read again next time then
+        let mut guilds = self.guilds.write().unwrap();
+        if self.generation.load(Ordering::SeqCst) == generation {
+            guilds.entry(guild_id).or_insert_with(|| prefixes.clone());
+        }
+
+        Ok(prefixes)
+    }
+
+    /// Replaces every prefix of the guild with a single one
+    pub async fn set(&self, db: &PgPool, guild_id: GuildId, prefix: &str) -> Result<(), Error> {
+        let mut transaction = db.begin().await?;
+
+        sqlx::query("DELETE FROM guild_prefixes WHERE guild_id = $1")
+            .bind(guild_id.0 as i64)
+            .execute(&mut transaction)
+            .await?;
+
+        sqlx::query("INSERT INTO guild_prefixes (guild_id, prefix) VALUES ($1, $2)")
+            .bind(guild_id.0 as i64)
+            .bind(prefix)
+            .execute(&mut transaction)
+            .await?;
+
+        transaction.commit().await?;
+
+        self.invalidate(guild_id);
+        Ok(())
+    }
+
+    /// Adds a prefix to the guild, unless it has it or [`MAX_GUILD_PREFIXES`] already
+    pub async fn add(
+        &self,
+        db: &PgPool,
+        guild_id: GuildId,
+        prefix: &str,
+    ) -> Result<AddOutcome, Error> {
+        let mut transaction = db.begin().await?;
+
+        // Adds at the same time would all see room for one more prefix otherwise
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(guild_id.0 as i64)
+            .execute(&mut transaction)
+            .await?;
+
+        let inserted = sqlx::query(
+            "INSERT INTO guild_prefixes (guild_id, prefix) SELECT $1, $2
+            WHERE (SELECT count(*) FROM guild_prefixes WHERE guild_id = $1) < $3
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(prefix)
+        .bind(MAX_GUILD_PREFIXES as i64)
+        .execute(&mut transaction)
+        .await?
+        .rows_affected()
+            > 0;
+
+        let added = if inserted {
+            AddOutcome::Added
+        } else {
+            let exists: bool = sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM guild_prefixes WHERE guild_id = $1 AND prefix = $2)",
+            )
+            .bind(guild_id.0 as i64)
+            .bind(prefix)
+            .fetch_one(&mut transaction)
+            .await?;
+            if exists {
+                AddOutcome::Exists
+            } else {
+                AddOutcome::Full
+            }
+        };
+        transaction.commit().await?;
+
+        self.invalidate(guild_id);
+        Ok(added)
+    }
+
+    /// Removes a prefix from the guild, returns false if it did not have it
+    pub async fn remove(
+        &self,
+        db: &PgPool,
+        guild_id: GuildId,
+        prefix: &str,
+    ) -> Result<bool, Error> {
+        let removed = sqlx::query("DELETE FROM guild_prefixes WHERE guild_id = $1 AND prefix = $2")
+            .bind(guild_id.0 as i64)
+            .bind(prefix)
+            .execute(db)
+            .await?
+            .rows_affected()
+            > 0;
+
+        self.invalidate(guild_id);
+        Ok(removed)
+    }
+
+    fn invalidate(&self, guild_id: GuildId) {
+        let mut guilds = self.guilds.write().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        guilds.remove(&guild_id);
+    }
+}
+
+/// Used as poise's `stripped_dynamic_prefix`, this strips the guild's own prefixes off a message
+/// or the global ones if the guild has not set any (or the message is not from a guild)
+pub fn strip_prefix<'a>(
+    _ctx: &'a serenity::Context,
+    msg: &'a serenity::Message,
+    data: &'a Data,
+) -> BoxFuture<'a, Result<Option<(&'a str, &'a str)>, Error>> {
+    Box::pin(async move {
+        let guild_prefixes = match msg.guild_id {
+            Some(guild_id) => Some(data.prefixes.guild(&data.db, guild_id).await?),
+            None => None,
+        };
+
+        let prefixes = match &guild_prefixes {
+            Some(prefixes) if !prefixes.is_empty() => prefixes,
+            _ => data.prefixes.global(),
+        };
+
+        // The longest match wins so a prefix like `!` does not shadow `!!`
+        let prefix = prefixes
+            .iter()
+            .filter(|prefix| msg.content.starts_with(prefix.as_str()))
+            .max_by_key(|prefix| prefix.len());
+
+        Ok(prefix.map(|prefix| msg.content.split_at(prefix.len())))
+    })
+}
+
+/// Checks if a prefix can be used, returning the reason why it can't if not
+pub fn validate(prefix: &str) -> Result<(), String> {
+    if prefix.is_empty() {
+        return Err("A prefix can't be empty".to_string());
+    }
+
+    if prefix.chars().count() > MAX_PREFIX_LENGTH {
+        return Err(format!(
+            "A prefix can't be longer than {MAX_PREFIX_LENGTH} characters"
+        ));
+    }
+
+    if prefix.contains(char::is_whitespace) {
+        return Err("A prefix can't contain whitespace".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD_ID: GuildId = GuildId(1);
+
+    #[sqlx::test]
+    async fn adds_up_to_the_maximum(db: PgPool) {
+        let cache = PrefixCache::new(vec!["!".to_string()]);
+        for i in 0..MAX_GUILD_PREFIXES {
+            let added = cache.add(&db, GUILD_ID, &format!("{i}!")).await.unwrap();
+            assert_eq!(added, AddOutcome::Added);
+        }
+        assert_eq!(
+            cache.add(&db, GUILD_ID, "0!").await.unwrap(),
+            AddOutcome::Exists
+        );
+        assert_eq!(
+            cache.add(&db, GUILD_ID, "?").await.unwrap(),
+            AddOutcome::Full
+        );
+        let prefixes = cache.guild(&db, GUILD_ID).await.unwrap();
+        assert_eq!(prefixes.len(), MAX_GUILD_PREFIXES);
+    }
+
+    #[sqlx::test]
+    async fn concurrent_adds_stay_within_the_maximum(db: PgPool) {
+        let cache = Arc::new(PrefixCache::new(Vec::new()));
+        let adds: Vec<_> = (0..MAX_GUILD_PREFIXES * 2)
+            .map(|i| {
+                let (cache, db) = (cache.clone(), db.clone());
+                tokio::spawn(async move { cache.add(&db, GUILD_ID, &format!("{i}!")).await })
+            })
+            .collect();
+        for add in adds {
+            add.await.unwrap().unwrap();
+        }
+
+        let prefixes = cache.guild(&db, GUILD_ID).await.unwrap();
+        assert_eq!(prefixes.len(), MAX_GUILD_PREFIXES);
+    }
+}
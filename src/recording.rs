@@ -0,0 +1,321 @@
+//! Records the events the shards receive to a file and replays them later, see `RECORD_EVENTS`
+//! and `--replay`.
+//!
+//! A recording is gzip compressed JSON with one event per line, like
+//! `{"at":1520,"shard":0,"t":"MESSAGE_CREATE","d":{...}}`. `at` is the number of milliseconds since
+//! the recording started and `d` is the event the way discord sends it.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ::serenity::cache::{Cache, CacheUpdate};
+use ::serenity::model::event::{deserialize_event_with_type, Event, EventType};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serenity::ShardManager;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+use crate::{Data, Error};
+
+/// Lines read ahead of the replay
+const READ_AHEAD: usize = 64;
+
+/// One line of a recording
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Recorded {
+    /// Milliseconds since the recording started
+    pub at: u64,
+    pub shard: u64,
+    /// The name of the event, like `MESSAGE_CREATE`
+    #[serde(rename = "t")]
+    pub kind: String,
+    #[serde(rename = "d")]
+    pub data: Value,
+}
+
+impl Recorded {
+    fn new(at: u64, shard: u64, event: &Event) -> serde_json::Result<Self> {
+        let kind = EventType::from(event)
+            .name()
+            .unwrap_or_default()
+            .to_string();
+        let data = match event {
+            Event::Unknown(unknown) => unknown.value.clone(),
+            event => serde_json::to_value(event)?,
+        };
+        Ok(Self {
+            at,
+            shard,
+            kind,
+            data,
+        })
+    }
+
+    /// Parses the event again, like the shard did when it was recorded
+    pub fn event(self) -> Result<Event, Error> {
+        let kind: EventType = serde_json::from_value(Value::String(self.kind))?;
+        Ok(deserialize_event_with_type(kind, self.data)?)
+    }
+}
+
+type Writer = GzEncoder<BufWriter<File>>;
+
+/// Writes every event to a file, added to the client as its raw event handler
+#[derive(Clone)]
+pub struct Recorder {
+    /// `None` once the recording is finished
+    writer: Arc<Mutex<Option<Writer>>>,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Starts a new recording, replacing the file if it exists
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = File::create(path)?;
+        let writer = GzEncoder::new(BufWriter::new(file), Compression::default());
+        Ok(Self {
+            writer: Arc::new(Mutex::new(Some(writer))),
+            started: Instant::now(),
+        })
+    }
+
+    /// Completes the file, events that come in afterwards aren't recorded
+    pub fn finish(&self) -> io::Result<()> {
+        if let Some(writer) = self.writer.lock().unwrap().take() {
+            writer.finish()?.flush()?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, recorded: &Recorded) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        let Some(writer) = writer.as_mut() else {
+            return Ok(());
+        };
+        serde_json::to_writer(&mut *writer, recorded)?;
+        writer.write_all(b"\n")?;
+        // Flushed right away so the recording of a bot that crashed can still be replayed
+        writer.flush()
+    }
+}
+
+#[serenity::async_trait]
+impl serenity::RawEventHandler for Recorder {
+    async fn raw_event(&self, ctx: serenity::Context, event: Event) {
+        let at = self.started.elapsed().as_millis() as u64;
+        let result = Recorded::new(at, ctx.shard_id, &event)
+            .map_err(io::Error::from)
+            .and_then(|recorded| self.write(&recorded));
+        if let Err(err) = result {
+            warn!("Cannot record a gateway event: {err}");
+        }
+    }
+}
+
+/// Everything events are dispatched with, what the framework has while the bot runs
+pub struct Replayer {
+    pub options: poise::FrameworkOptions<Data, Error>,
+    pub data: Data,
+    pub shard_manager: Arc<tokio::sync::Mutex<ShardManager>>,
+    /// The cache and http client of a client that isn't started
+    pub ctx: serenity::Context,
+}
+
+/// Sends the events of a recording through the cache, the command dispatcher and the event
+/// handler. Events keep the time between them divided by `speed`, `0` replays them without any
+/// pause. Returns the number of events replayed once every event has been handled.
+///
+/// There is no shard to route clicks to collectors, so buttons and select menus stop waiting
+/// right away.
+pub async fn replay(replayer: Replayer, path: &Path, speed: f64) -> Result<u64, Error> {
+    let replayer = Arc::new(replayer);
+    let mut lines = read(path)?;
+    let mut handlers = JoinSet::new();
+    let mut count = 0;
+
+    let started = tokio::time::Instant::now();
+    let mut number = 0;
+    while let Some(line) = lines.recv().await {
+        number += 1;
+        let recorded = match line {
+            Ok(recorded) => recorded,
+            // A bot that crashed leaves an incomplete line behind
+            Err(err) => {
+                warn!("Stopping the replay at line {number}: {err}");
+                break;
+            }
+        };
+
+        if speed > 0.0 {
+            let at = Duration::from_millis(recorded.at).div_f64(speed);
+            tokio::time::sleep_until(started + at).await;
+        }
+
+        let shard = recorded.shard;
+        let event = match recorded.event() {
+            Ok(event) => event,
+            Err(err) => {
+                warn!("Skipping the event on line {number}: {err}");
+                continue;
+            }
+        };
+        count += 1;
+
+        // The shards tell the http client which application it is once they are ready
+        if let Event::Ready(event) = &event {
+            replayer
+                .ctx
+                .http
+                .set_application_id(event.ready.application.id.0);
+        }
+
+        // The cache is updated in order, only the handlers run at the same time like they do
+        // in the shards
+        if let Some(event) = update(&replayer.ctx.cache, event) {
+            let replayer = replayer.clone();
+            handlers.spawn(async move {
+                let mut ctx = replayer.ctx.clone();
+                ctx.shard_id = shard;
+                let framework = poise::FrameworkContext {
+                    bot_id: ctx.cache.current_user_id(),
+                    options: &replayer.options,
+                    user_data: &replayer.data,
+                    shard_manager: &replayer.shard_manager,
+                };
+                poise::dispatch_event(framework, &ctx, &event).await;
+            });
+        }
+    }
+
+    while handlers.join_next().await.is_some() {}
+    info!("Replayed {count} events");
+    Ok(count)
+}
+
+/// Reads the lines of a recording on another thread, since the file is read and decompressed
+/// with blocking IO
+fn read(path: &Path) -> io::Result<mpsc::Receiver<io::Result<Recorded>>> {
+    let file = File::open(path)?;
+    let (tx, rx) = mpsc::channel(READ_AHEAD);
+    std::thread::spawn(move || {
+        for line in BufReader::new(MultiGzDecoder::new(file)).lines() {
+            let recorded = line.and_then(|line| Ok(serde_json::from_str(&line)?));
+            let failed = recorded.is_err();
+            if tx.blocking_send(recorded).is_err() || failed {
+                break;
+            }
+        }
+    });
+    Ok(rx)
+}
+
+/// Updates the cache like the shards do, and turns the event into what the event handler gets.
+/// `None` for events that only change the cache or that the bot never looks at.
+fn update(cache: &Cache, event: Event) -> Option<poise::Event<'static>> {
+    let event = match event {
+        Event::Ready(mut event) => {
+            cache.update(&mut event);
+            poise::Event::Ready {
+                data_about_bot: event.ready,
+            }
+        }
+        Event::GuildCreate(mut event) => {
+            let is_new = !cache.unavailable_guilds().contains(&event.guild.id);
+            cache.update(&mut event);
+            poise::Event::GuildCreate {
+                guild: event.guild,
+                is_new,
+            }
+        }
+        Event::GuildDelete(mut event) => {
+            let full = cache.update(&mut event);
+            poise::Event::GuildDelete {
+                incomplete: event.guild,
+                full,
+            }
+        }
+        Event::GuildMemberAdd(mut event) => {
+            cache.update(&mut event);
+            poise::Event::GuildMemberAddition {
+                new_member: event.member,
+            }
+        }
+        Event::GuildMemberRemove(mut event) => {
+            let member = cache.update(&mut event);
+            poise::Event::GuildMemberRemoval {
+                guild_id: event.guild_id,
+                user: event.user,
+                member_data_if_available: member,
+            }
+        }
+        Event::MessageCreate(mut event) => {
+            cache.update(&mut event);
+            poise::Event::Message {
+                new_message: event.message,
+            }
+        }
+        Event::MessageUpdate(mut event) => {
+            let old = cache.update(&mut event);
+            poise::Event::MessageUpdate {
+                old_if_available: old,
+                new: cache.message(event.channel_id, event.id),
+                event,
+            }
+        }
+        Event::MessageDelete(event) => poise::Event::MessageDelete {
+            channel_id: event.channel_id,
+            deleted_message_id: event.message_id,
+            guild_id: event.guild_id,
+        },
+        Event::ReactionAdd(event) => poise::Event::ReactionAdd {
+            add_reaction: event.reaction,
+        },
+        Event::ReactionRemove(event) => poise::Event::ReactionRemove {
+            removed_reaction: event.reaction,
+        },
+        Event::InteractionCreate(event) => poise::Event::InteractionCreate {
+            interaction: event.interaction,
+        },
+        Event::Unknown(event) => poise::Event::Unknown {
+            name: event.kind,
+            raw: event.value,
+        },
+
+        Event::ChannelCreate(event) => return cached(cache, event),
+        Event::ChannelDelete(event) => return cached(cache, event),
+        Event::ChannelUpdate(event) => return cached(cache, event),
+        Event::ChannelPinsUpdate(event) => return cached(cache, event),
+        Event::GuildEmojisUpdate(event) => return cached(cache, event),
+        Event::GuildMemberUpdate(event) => return cached(cache, event),
+        Event::GuildMembersChunk(event) => return cached(cache, event),
+        Event::GuildRoleCreate(event) => return cached(cache, event),
+        Event::GuildRoleDelete(event) => return cached(cache, event),
+        Event::GuildRoleUpdate(event) => return cached(cache, event),
+        Event::GuildStickersUpdate(event) => return cached(cache, event),
+        Event::GuildUnavailable(event) => return cached(cache, event),
+        Event::GuildUpdate(event) => return cached(cache, event),
+        Event::PresencesReplace(event) => return cached(cache, event),
+        Event::PresenceUpdate(event) => return cached(cache, event),
+        Event::ThreadCreate(event) => return cached(cache, event),
+        Event::ThreadDelete(event) => return cached(cache, event),
+        Event::ThreadUpdate(event) => return cached(cache, event),
+        Event::UserUpdate(event) => return cached(cache, event),
+        Event::VoiceStateUpdate(event) => return cached(cache, event),
+        _ => return None,
+    };
+    Some(event)
+}
+
+fn cached(cache: &Cache, mut event: impl CacheUpdate) -> Option<poise::Event<'static>> {
+    cache.update(&mut event);
+    None
+}
@@ -0,0 +1,308 @@
+use std::fmt;
+
+use poise::serenity_prelude as serenity;
+use serde_json::{json, Map, Value};
+use serenity::{GuildId, Http};
+use tracing::info;
+
+use crate::commands::Command;
+use crate::Error;
+
+/// Where slash commands are registered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// Every guild and DMs, but changes can take a while to show up
+    Global,
+    /// A single guild, changes show up immediately which makes this nice for development
+    Guild(GuildId),
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Global => f.write_str("globally"),
+            Scope::Guild(guild_id) => write!(f, "in guild {guild_id}"),
+        }
+    }
+}
+
+/// Registers the commands in the given scope, but only if they differ from what Discord already
+/// has. Returns whether anything was changed.
+pub async fn sync(http: &Http, commands: &[Command], scope: Scope) -> Result<bool, Error> {
+    let wanted = poise::builtins::create_application_commands(commands).0;
+
+    let registered = match scope {
+        Scope::Global => http.get_global_application_commands().await?,
+        Scope::Guild(guild_id) => http.get_guild_application_commands(guild_id.0).await?,
+    };
+    let registered = registered
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if canonical_set(&wanted, scope) == canonical_set(&registered, scope) {
+        info!("Slash commands are up to date {scope}");
+        return Ok(false);
+    }
+
+    info!("Registering {} slash commands {scope}", wanted.len());
+    put(http, scope, &Value::Array(wanted)).await?;
+    Ok(true)
+}
+
+/// Removes every slash command in the given scope
+pub async fn clear(http: &Http, scope: Scope) -> Result<(), Error> {
+    info!("Removing all slash commands {scope}");
+    put(http, scope, &json!([])).await
+}
+
+// Overwrites every command in the scope with the given ones
+async fn put(http: &Http, scope: Scope, commands: &Value) -> Result<(), Error> {
+    match scope {
+        Scope::Global => http.create_global_application_commands(commands).await?,
+        Scope::Guild(guild_id) => {
+            http.create_guild_application_commands(guild_id.0, commands)
+                .await?
+        }
+    };
+    Ok(())
+}
+
+/// Commands as Discord sees them, sorted so the order they are defined in doesn't matter
+fn canonical_set(commands: &[Value], scope: Scope) -> Vec<Value> {
+    let mut commands: Vec<_> = commands
+        .iter()
+        .map(|command| canonical_command(command, scope))
+        .collect();
+    commands.sort_by_key(|command| command.to_string());
+    commands
+}
+
+// What we send and what Discord sends back differ in which fields are left out and how some are
+// typed, so both are brought to the same shape before comparing them
+fn canonical_command(command: &Value, scope: Scope) -> Value {
+    let mut out = Map::new();
+    out.insert("type".into(), field(command, "type").unwrap_or(json!(1)));
+    copy_common(command, &mut out);
+    out.insert(
+        "default_member_permissions".into(),
+        match field(command, "default_member_permissions") {
+            // Permissions are sometimes a number and sometimes a string of a number
+            Some(Value::Number(permissions)) => json!(permissions.to_string()),
+            Some(permissions) => permissions,
+            None => Value::Null,
+        },
+    );
+    // Only global commands have it, Discord leaves it out for the commands of a guild
+    let dm_permission = match scope {
+        Scope::Global => field(command, "dm_permission").unwrap_or(json!(true)),
+        Scope::Guild(_) => Value::Null,
+    };
+    out.insert("dm_permission".into(), dm_permission);
+    Value::Object(out)
+}
+
+fn canonical_option(option: &Value) -> Value {
+    let mut out = Map::new();
+    out.insert("type".into(), field(option, "type").unwrap_or(Value::Null));
+    copy_common(option, &mut out);
+    out.insert(
+        "required".into(),
+        field(option, "required").unwrap_or(json!(false)),
+    );
+    out.insert(
+        "autocomplete".into(),
+        field(option, "autocomplete").unwrap_or(json!(false)),
+    );
+    out.insert(
+        "channel_types".into(),
+        field(option, "channel_types").unwrap_or(json!([])),
+    );
+    out.insert(
+        "choices".into(),
+        Value::Array(
+            list(option, "choices")
+                .iter()
+                .map(|choice| {
+                    json!({
+                        "name": field(choice, "name"),
+                        "name_localizations": localizations(choice, "name_localizations"),
+                        "value": field(choice, "value"),
+                    })
+                })
+                .collect(),
+        ),
+    );
+    for key in ["min_value", "max_value"] {
+        // `1` and `1.0` are the same limit
+        let value = field(option, key).and_then(|x| x.as_f64());
+        out.insert(key.into(), json!(value));
+    }
+    for key in ["min_length", "max_length"] {
+        out.insert(key.into(), field(option, key).unwrap_or(Value::Null));
+    }
+    Value::Object(out)
+}
+
+// Fields shared by commands and their options
+fn copy_common(value: &Value, out: &mut Map<String, Value>) {
+    out.insert("name".into(), field(value, "name").unwrap_or(json!("")));
+    out.insert(
+        "description".into(),
+        field(value, "description").unwrap_or(json!("")),
+    );
+    for key in ["name_localizations", "description_localizations"] {
+        out.insert(key.into(), localizations(value, key));
+    }
+    out.insert(
+        "options".into(),
+        Value::Array(
+            list(value, "options")
+                .iter()
+                .map(canonical_option)
+                .collect(),
+        ),
+    );
+}
+
+fn field(value: &Value, key: &str) -> Option<Value> {
+    value.get(key).filter(|x| !x.is_null()).cloned()
+}
+
+fn list<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+    value
+        .get(key)
+        .and_then(|x| x.as_array())
+        .map_or(&[], |x| x.as_slice())
+}
+
+// Missing and empty localizations mean the same thing
+fn localizations(value: &Value, key: &str) -> Value {
+    match field(value, key) {
+        Some(Value::Object(map)) if !map.is_empty() => Value::Object(map),
+        _ => json!({}),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+
+    use super::*;
+    use crate::bot::discord_http;
+    use crate::testing::{FakeDiscord, BOT_ID, GUILD_ID};
+    use crate::{Context, Error};
+
+    /// Set how loud the bot is
+    #[poise::command(slash_command, guild_only, default_member_permissions = "MANAGE_GUILD")]
+    async fn volume(
+        _ctx: Context<'_>,
+        #[description = "How loud, from 1 to 10"]
+        #[min = 1]
+        #[max = 10]
+        level: u8,
+        #[description = "Only in this channel"] channel: Option<String>,
+    ) -> Result<(), Error> {
+        let _ = (level, channel);
+        Ok(())
+    }
+
+    /// What `GET /applications/{id}/commands` answered after registering `volume` globally
+    fn answer() -> Value {
+        json!({
+            "id": "1085187367014436925",
+            "application_id": "1000",
+            "version": "1085187367014436926",
+            "default_member_permissions": "32",
+            "type": 1,
+            "name": "volume",
+            "name_localizations": null,
+            "description": "Set how loud the bot is",
+            "description_localizations": null,
+            "dm_permission": false,
+            "nsfw": false,
+            "options": [
+                {
+                    "type": 4,
+                    "name": "level",
+                    "name_localizations": null,
+                    "description": "How loud, from 1 to 10",
+                    "description_localizations": null,
+                    "required": true,
+                    "min_value": 1,
+                    "max_value": 10
+                },
+                {
+                    "type": 3,
+                    "name": "channel",
+                    "name_localizations": null,
+                    "description": "Only in this channel",
+                    "description_localizations": null
+                }
+            ]
+        })
+    }
+
+    fn sent() -> Vec<Value> {
+        poise::builtins::create_application_commands(&[volume()]).0
+    }
+
+    #[test]
+    fn matches_what_discord_answers() {
+        let wanted = canonical_set(&sent(), Scope::Global);
+        assert_eq!(wanted, canonical_set(&[answer()], Scope::Global));
+
+        // Permissions can also come as a number
+        let mut registered = answer();
+        registered["default_member_permissions"] = json!(32);
+        assert_eq!(wanted, canonical_set(&[registered], Scope::Global));
+
+        // Guild commands come without `dm_permission`
+        let mut registered = answer();
+        registered.as_object_mut().unwrap().remove("dm_permission");
+        assert_eq!(
+            canonical_set(&sent(), Scope::Guild(GUILD_ID)),
+            canonical_set(&[registered], Scope::Guild(GUILD_ID))
+        );
+    }
+
+    #[test]
+    fn notices_changes() {
+        let wanted = canonical_set(&sent(), Scope::Global);
+        let changes = [
+            ("/options/0/max_value", json!(11)),
+            ("/options/1/required", json!(true)),
+            ("/options/1/autocomplete", json!(true)),
+            ("/description", json!("Set the volume")),
+            ("/default_member_permissions", json!("8")),
+            ("/dm_permission", json!(true)),
+        ];
+        for (path, value) in changes {
+            let mut registered = answer();
+            let (parent, key) = path.rsplit_once('/').unwrap();
+            registered.pointer_mut(parent).unwrap()[key] = value;
+            assert_ne!(
+                wanted,
+                canonical_set(&[registered], Scope::Global),
+                "{path} changed"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn only_registers_what_changed() {
+        let discord = FakeDiscord::start().await;
+        let http = discord_http(&discord.config());
+        http.set_application_id(BOT_ID.0);
+        let scope = Scope::Guild(GUILD_ID);
+
+        assert!(sync(&http, &[volume()], scope).await.unwrap());
+        assert!(!sync(&http, &[volume()], scope).await.unwrap());
+        let puts = discord
+            .requests()
+            .iter()
+            .filter(|x| x.method == Method::PUT)
+            .count();
+        assert_eq!(puts, 1);
+    }
+}
@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+use serenity::{ChannelId, GuildId, Mentionable, UserId};
+use sqlx::PgPool;
+use tracing::debug;
+
+use crate::jobs::{self, Job, JobContext};
+use crate::{when, Error};
+
+/// How many reminders a user can have at once
+pub const MAX_REMINDERS: i64 = 25;
+
+/// A row of the `reminders` table
+#[derive(sqlx::FromRow, Debug)]
+pub struct Reminder {
+    pub id: i64,
+    pub user_id: i64,
+    pub channel_id: Option<i64>,
+    pub message: String,
+    pub remind_at: DateTime<Utc>,
+    pub interval_seconds: Option<i64>,
+    pub timezone: String,
+    pub created_at: DateTime<Utc>,
+    /// Local time of day repeating reminders are meant for
+    pub time_of_day: Option<NaiveTime>,
+}
+
+impl Reminder {
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval_seconds
+            .map(|secs| Duration::from_secs(secs as u64))
+    }
+
+    fn time_of_day(&self, tz: Tz) -> NaiveTime {
+        self.time_of_day
+            .unwrap_or_else(|| self.remind_at.with_timezone(&tz).time())
+    }
+}
+
+/// A reminder that has not been saved yet
+pub struct NewReminder<'a> {
+    pub user_id: UserId,
+    pub guild_id: Option<GuildId>,
+    /// Where the reminder is posted, by DM if this is `None`
+    pub channel_id: Option<ChannelId>,
+    pub message: &'a str,
+    pub remind_at: DateTime<Utc>,
+    pub interval: Option<Duration>,
+    /// Used to keep repeating reminders at the same time of day
+    pub timezone: Tz,
+}
+
+impl NewReminder<'_> {
+    /// Saves the reminder and queues its delivery, returns the ID of the reminder
+    pub async fn create(&self, db: &PgPool) -> Result<i64, Error> {
+        let mut transaction = db.begin().await?;
+
+        let id = sqlx::query_scalar(
+            "INSERT INTO reminders (user_id, guild_id, channel_id, message, remind_at, interval_seconds, timezone, time_of_day)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id",
+        )
+        .bind(self.user_id.0 as i64)
+        .bind(self.guild_id.map(|x| x.0 as i64))
+        .bind(self.channel_id.map(|x| x.0 as i64))
+        .bind(self.message)
+        .bind(self.remind_at)
+        .bind(self.interval.map(|x| x.as_secs() as i64))
+        .bind(self.timezone.name())
+        .bind(
+            self.interval
+                .map(|_| self.remind_at.with_timezone(&self.timezone).time()),
+        )
+        .fetch_one(&mut transaction)
+        .await?;
+
+        let job = DeliverReminder { reminder_id: id };
+        jobs::enqueue_in(&mut transaction, &job, self.remind_at).await?;
+
+        transaction.commit().await?;
+        Ok(id)
+    }
+}
+
+/// The reminders of a user, the next one first
+pub async fn for_user(db: &PgPool, user_id: UserId) -> Result<Vec<Reminder>, Error> {
+    let reminders = sqlx::query_as("SELECT * FROM reminders WHERE user_id = $1 ORDER BY remind_at")
+        .bind(user_id.0 as i64)
+        .fetch_all(db)
+        .await?;
+    Ok(reminders)
+}
+
+pub async fn count_for_user(db: &PgPool, user_id: UserId) -> Result<i64, Error> {
+    let count = sqlx::query_scalar("SELECT count(*) FROM reminders WHERE user_id = $1")
+        .bind(user_id.0 as i64)
+        .fetch_one(db)
+        .await?;
+    Ok(count)
+}
+
+/// Deletes a reminder of the user, returns false if they have no such reminder
+pub async fn cancel(db: &PgPool, user_id: UserId, id: i64) -> Result<bool, Error> {
+    let deleted = sqlx::query("DELETE FROM reminders WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user_id.0 as i64)
+        .execute(db)
+        .await?
+        .rows_affected()
+        > 0;
+
+    if deleted {
+        jobs::cancel::<DeliverReminder>(db, &id.to_string()).await?;
+    }
+    Ok(deleted)
+}
+
+/// Posts a reminder and queues the next one if it repeats
+#[derive(Serialize, Deserialize)]
+pub struct DeliverReminder {
+    reminder_id: i64,
+}
+
+impl Job for DeliverReminder {
+    const KIND: &'static str = "deliver_reminder";
+
+    fn key(&self) -> Option<String> {
+        Some(self.reminder_id.to_string())
+    }
+
+    async fn run(self, ctx: &JobContext) -> Result<(), Error> {
+        let reminder: Option<Reminder> = sqlx::query_as("SELECT * FROM reminders WHERE id = $1")
+            .bind(self.reminder_id)
+            .fetch_optional(&ctx.db)
+            .await?;
+        // Cancelled in the meantime
+        let Some(reminder) = reminder else {
+            return Ok(());
+        };
+
+        deliver(ctx, &reminder).await?;
+
+        let tz: Tz = reminder.timezone.parse().unwrap_or(Tz::UTC);
+        let next = reminder.interval().and_then(|every| {
+            let time_of_day = reminder.time_of_day(tz);
+            when::next_occurrence(reminder.remind_at, every, time_of_day, Utc::now(), tz)
+        });
+
+        let mut transaction = ctx.db.begin().await?;
+        match next {
+            Some(next) => {
+                sqlx::query("UPDATE reminders SET remind_at = $2 WHERE id = $1")
+                    .bind(reminder.id)
+                    .bind(next)
+                    .execute(&mut transaction)
+                    .await?;
+                jobs::enqueue_in(&mut transaction, &self, next).await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM reminders WHERE id = $1")
+                    .bind(reminder.id)
+                    .execute(&mut transaction)
+                    .await?;
+            }
+        }
+        transaction.commit().await?;
+
+        Ok(())
+    }
+}
+
+async fn deliver(ctx: &JobContext, reminder: &Reminder) -> Result<(), Error> {
+    let user_id = UserId(reminder.user_id as u64);
+    let content = format!(
+        "⏰ Reminder from <t:{}:R>: {}",
+        reminder.created_at.timestamp(),
+        reminder.message
+    );
+
+    if let Some(channel_id) = reminder.channel_id {
+        let sent = ChannelId(channel_id as u64)
+            .send_message(&ctx.http, |m| {
+                m.content(format!("{} {content}", user_id.mention()))
+                    .allowed_mentions(|a| a.empty_parse().users([user_id]))
+            })
+            .await;
+
+        match sent {
+            Ok(_) => return Ok(()),
+            // The channel is gone or the bot can't talk there anymore, a DM is better than nothing
+            Err(err) => debug!("Could not post reminder {} in channel: {err}", reminder.id),
+        }
+    }
+
+    user_id
+        .create_dm_channel(&ctx.http)
+        .await?
+        .say(&ctx.http, content)
+        .await?;
+    Ok(())
+}
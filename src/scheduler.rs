@@ -0,0 +1,134 @@
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+use serenity::{GuildId, RoleId, UserId};
+use sqlx::PgPool;
+
+use crate::cases::{CaseAction, NewCase};
+use crate::jobs::{self, Job, JobContext};
+use crate::Error;
+
+/// Something that has to be undone once a temporary punishment runs out
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduledAction {
+    Unban,
+    RemoveRole(RoleId),
+}
+
+impl ScheduledAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            ScheduledAction::Unban => "unban",
+            ScheduledAction::RemoveRole(_) => "remove_role",
+        }
+    }
+}
+
+/// Undoes a temporary punishment, queued when the punishment is handed out
+#[derive(Serialize, Deserialize)]
+pub struct ExpirePunishment {
+    guild_id: GuildId,
+    user_id: UserId,
+    action: ScheduledAction,
+    case_number: i32,
+}
+
+impl Job for ExpirePunishment {
+    const KIND: &'static str = "expire_punishment";
+
+    fn key(&self) -> Option<String> {
+        Some(key(self.guild_id, self.user_id, self.action))
+    }
+
+    async fn run(self, ctx: &JobContext) -> Result<(), Error> {
+        let case = format!(" (case #{})", self.case_number);
+        let (guild_id, user_id) = (self.guild_id, self.user_id);
+
+        let (result, case_action, reason) = match self.action {
+            ScheduledAction::Unban => {
+                let reason = format!("Temporary ban expired{case}");
+                let result = ctx
+                    .http
+                    .remove_ban(guild_id.0, user_id.0, Some(&reason))
+                    .await;
+                (result, CaseAction::Unban, reason)
+            }
+            ScheduledAction::RemoveRole(role_id) => {
+                let reason = format!("Temporary mute expired{case}");
+                let result = ctx
+                    .http
+                    .remove_member_role(guild_id.0, user_id.0, role_id.0, Some(&reason))
+                    .await;
+                (result, CaseAction::Unmute, reason)
+            }
+        };
+
+        match result {
+            Ok(()) => {}
+            // Someone already undid it by hand, the user left or the guild is gone, so there is
+            // nothing left to do
+            Err(err) if is_not_found(&err) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+
+        let bot_id = ctx.http.get_current_user().await?.id;
+        NewCase {
+            guild_id,
+            action: case_action,
+            moderator_id: bot_id,
+            target_id: Some(user_id),
+            reason: Some(&reason),
+            duration: None,
+        }
+        .create(&ctx.db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// One pending action of each kind per user, a new punishment replaces the end of an older one
+fn key(guild_id: GuildId, user_id: UserId, action: ScheduledAction) -> String {
+    format!("{}:{}:{}", guild_id.0, user_id.0, action.as_str())
+}
+
+/// Runs `action` on the user at `execute_at`, even if the bot restarts in between
+pub async fn schedule(
+    db: &PgPool,
+    guild_id: GuildId,
+    user_id: UserId,
+    action: ScheduledAction,
+    execute_at: DateTime<Utc>,
+    case_number: i32,
+) -> Result<(), Error> {
+    let job = ExpirePunishment {
+        guild_id,
+        user_id,
+        action,
+        case_number,
+    };
+    jobs::enqueue_at(db, &job, execute_at).await?;
+    Ok(())
+}
+
+/// Cancels pending actions, for example when a moderator unbans someone by hand
+pub async fn cancel(
+    db: &PgPool,
+    guild_id: GuildId,
+    user_id: UserId,
+    action: ScheduledAction,
+) -> Result<(), Error> {
+    jobs::cancel::<ExpirePunishment>(db, &key(guild_id, user_id, action)).await?;
+    Ok(())
+}
+
+/// Whether discord answered with 404, like `Unknown Ban` or `Unknown Member`
+pub fn is_not_found(err: &serenity::SerenityError) -> bool {
+    match err {
+        serenity::SerenityError::Http(http_error) => http_error
+            .status_code()
+            .is_some_and(|status| status.as_u16() == 404),
+        _ => false,
+    }
+}
@@ -0,0 +1,253 @@
+use std::io;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use poise::serenity_prelude as serenity;
+use serenity::ShardManager;
+use sqlx::PgPool;
+use tokio::sync::{watch, Mutex, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::jobs::Workers;
+
+/// How long running commands and jobs get to finish after a shutdown was requested
+const TIMEOUT: Duration = Duration::from_secs(25);
+
+/// Exit code after a clean shutdown
+pub const EXIT_OK: i32 = 0;
+/// The bot failed to start or lost its connection to discord on its own
+pub const EXIT_FAILURE: i32 = 1;
+/// The bot shut down, but had to cut off commands or jobs that took too long
+pub const EXIT_TIMED_OUT: i32 = 2;
+
+/// Keeps track of running commands so a shutdown can wait for them
+pub struct Shutdown {
+    stopping: watch::Sender<bool>,
+    in_flight: watch::Sender<usize>,
+    /// Notified when an owner asks for a shutdown instead of a signal
+    requested: Notify,
+    restart: AtomicBool,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            stopping: watch::channel(false).0,
+            in_flight: watch::channel(0).0,
+            requested: Notify::new(),
+            restart: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Shutdown {
+    /// True once a shutdown started, no new commands should run anymore
+    pub fn is_stopping(&self) -> bool {
+        *self.stopping.borrow()
+    }
+
+    /// Waits until a shutdown started, for background tasks that should stop with the bot
+    pub async fn stopped(&self) {
+        let mut stopping = self.stopping.subscribe();
+        while !*stopping.borrow_and_update() {
+            if stopping.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Used in `pre_command`, the command counts as running until the returned guard is dropped.
+    /// That also happens when the command panics, which skips `post_command` and `on_error`.
+    pub fn command_started(self: &Arc<Self>) -> RunningCommand {
+        self.in_flight.send_modify(|count| *count += 1);
+        RunningCommand(self.clone())
+    }
+
+    /// Shuts the bot down the same way a signal does, used by `/owner shutdown` and
+    /// `/owner restart`
+    pub fn request(&self, restart: bool) {
+        self.restart.store(restart, Ordering::SeqCst);
+        self.requested.notify_one();
+    }
+
+    /// Whether the process should start again once it shut down, see [`restart`]
+    pub fn restart_requested(&self) -> bool {
+        self.restart.load(Ordering::SeqCst)
+    }
+
+    /// Waits for the running commands to finish, returns false if they didn't before `deadline`
+    async fn drain(&self, deadline: Instant) -> bool {
+        let mut in_flight = self.in_flight.subscribe();
+        let drained = async {
+            while *in_flight.borrow_and_update() > 0 {
+                if in_flight.changed().await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        tokio::time::timeout_at(deadline, drained).await.is_ok()
+    }
+}
+
+/// A command that a shutdown waits for, see [`Shutdown::command_started`]
+pub struct RunningCommand(Arc<Shutdown>);
+
+impl Drop for RunningCommand {
+    fn drop(&mut self) {
+        self.0.in_flight.send_modify(|count| *count -= 1);
+    }
+}
+
+/// Waits for a reason to shut down and then stops the bot in order: no new commands, waiting for
+/// running commands, jobs and background tasks, closing the shards and finally the database
+/// connections, when there is a pool the bot opened itself. Signals are only a reason with
+/// `handle_signals`, they are up to whoever runs the bot otherwise.
+///
+/// Returns the exit code for the process.
+pub async fn coordinate(
+    shutdown: Arc<Shutdown>,
+    shard_manager: Arc<Mutex<ShardManager>>,
+    workers: Workers,
+    tasks: Vec<JoinHandle<()>>,
+    db: Option<PgPool>,
+    handle_signals: bool,
+) -> i32 {
+    tokio::select! {
+        signal = signal(handle_signals) => info!("Received {signal}, shutting down the bot!"),
+        _ = shutdown.requested.notified() => info!("Asked to shut down the bot!"),
+    }
+    shutdown.stopping.send_replace(true);
+
+    let deadline = Instant::now() + TIMEOUT;
+    let finished = tokio::select! {
+        finished = async {
+            let commands = shutdown.drain(deadline).await;
+            let jobs = workers.shutdown(deadline).await;
+            let tasks = finish_tasks(tasks, deadline).await;
+            commands && jobs && tasks
+        } => finished,
+        // Someone really wants the bot gone
+        signal = self::signal(handle_signals) => {
+            warn!("Received {signal} again, not waiting for commands and jobs anymore");
+            false
+        }
+    };
+    if !finished {
+        warn!("Some commands, jobs or tasks did not finish in time and were cut off");
+    }
+
+    shard_manager.lock().await.shutdown_all().await;
+    if let Some(db) = db {
+        db.close().await;
+    }
+
+    if finished {
+        EXIT_OK
+    } else {
+        EXIT_TIMED_OUT
+    }
+}
+
+/// Waits for background tasks to return once they saw the shutdown, the ones still running at
+/// `deadline` are cut off. Returns false if there were any.
+async fn finish_tasks(tasks: Vec<JoinHandle<()>>, deadline: Instant) -> bool {
+    let mut finished = true;
+    for mut task in tasks {
+        if tokio::time::timeout_at(deadline, &mut task).await.is_err() {
+            task.abort();
+            finished = false;
+        }
+    }
+    finished
+}
+
+/// Replaces the process with a fresh copy of the bot started with the same arguments, only
+/// returns if that failed
+pub fn restart() -> io::Error {
+    let executable = match std::env::current_exe() {
+        Ok(executable) => executable,
+        Err(err) => return err,
+    };
+    let mut command = Command::new(executable);
+    command.args(std::env::args_os().skip(1));
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+
+        // Keeps the process id, so service managers and containers don't notice
+        command.exec()
+    }
+
+    #[cfg(not(unix))]
+    {
+        match command.spawn() {
+            Ok(_) => std::process::exit(EXIT_OK),
+            Err(err) => err,
+        }
+    }
+}
+
+/// Waits for ctrl+c, or on unix for SIGTERM (sent by container orchestrators) and SIGHUP, and
+/// returns its name. Waits forever when not `enabled`.
+async fn signal(enabled: bool) -> &'static str {
+    if !enabled {
+        return std::future::pending().await;
+    }
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate =
+            signal(SignalKind::terminate()).expect("Cannot register a SIGTERM handler!");
+        let mut hangup = signal(SignalKind::hangup()).expect("Cannot register a SIGHUP handler!");
+
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                result.expect("Cannot register a ctrl+c handler!");
+                "ctrl+c"
+            }
+            _ = terminate.recv() => "SIGTERM",
+            _ = hangup.recv() => "SIGHUP",
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Cannot register a ctrl+c handler!");
+        "ctrl+c"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn counts_commands_until_they_are_over() {
+        let shutdown = Arc::new(Shutdown::default());
+        let first = shutdown.command_started();
+        let second = shutdown.command_started();
+        assert_eq!(*shutdown.in_flight.borrow(), 2);
+
+        drop(first);
+        assert_eq!(*shutdown.in_flight.borrow(), 1);
+        assert!(!shutdown.drain(Instant::now()).await);
+
+        // A command that panics is over too
+        let panicking = tokio::spawn(async move {
+            let _running = second;
+            panic!("the command broke");
+        });
+        assert!(panicking.await.is_err());
+        assert!(shutdown.drain(Instant::now() + TIMEOUT).await);
+    }
+}
@@ -0,0 +1,321 @@
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry::sdk::trace::{self as sdktrace, EvictedHashMap, Tracer, TracerProvider};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::{SpanKind, TraceError, Tracer as _, TracerProvider as _};
+use opentelemetry::{global, Key, KeyValue};
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+use poise::BoxFuture;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_opentelemetry::{OtelData, PreSampledTracer};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::{self, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// sqlx logs every query it ran under this target
+const QUERY_TARGET: &str = "sqlx::query";
+/// serenity runs every request to discord in a span with this target
+const DISCORD_HTTP_TARGET: &str = "serenity::http::client";
+
+/// Sends traces to the OTLP collector at `endpoint` (like `http://localhost:4317`), set with
+/// `OTLP_ENDPOINT`.
+///
+/// Spans are sent in batches in the background, [`shutdown`] sends the ones that are left.
+pub fn layer<S>(endpoint: &str) -> Result<impl Layer<S>, TraceError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint);
+    let exporter = SpanExporterBuilder::from(exporter).build_span_exporter()?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(Scrub(exporter), opentelemetry::runtime::Tokio)
+        .with_config(config())
+        .build();
+
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    // Kept globally so it can be flushed on shutdown
+    global::set_tracer_provider(provider);
+    global::set_error_handler(|err| tracing::warn!("Could not export traces: {err}"))
+        .expect("the error handler is only set once");
+
+    Ok(layers(tracer))
+}
+
+/// Sends the spans that were not exported yet, call this right before exiting
+pub async fn shutdown() {
+    // Blocks until the batch exporter (which runs on the runtime) is done
+    let _ = tokio::task::spawn_blocking(global::shutdown_tracer_provider).await;
+}
+
+fn config() -> sdktrace::Config {
+    sdktrace::config().with_resource(Resource::new([
+        KeyValue::new("service.name", env!("CARGO_PKG_NAME")),
+        KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+    ]))
+}
+
+/// Commands are traced on their own, each of them starts a new trace (see
+/// `logging::instrument`). Queries and requests to discord show up as their children.
+fn layers<S>(tracer: Tracer) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    // Only what belongs to a trace, no matter what `RUST_LOG` says
+    let targets = Targets::new()
+        .with_target(env!("CARGO_CRATE_NAME"), Level::INFO)
+        .with_target(QUERY_TARGET, Level::INFO)
+        .with_target(DISCORD_HTTP_TARGET, Level::INFO);
+
+    tracing_opentelemetry::layer()
+        .with_tracer(tracer.clone())
+        .and_then(Queries { tracer })
+        .with_filter(targets)
+}
+
+/// Turns the log line sqlx writes after every query into a span of the span it ran in.
+///
+/// sqlx has no spans of its own, but the line has everything needed: the statement, the number
+/// of rows and how long it took.
+struct Queries {
+    tracer: Tracer,
+}
+
+impl<S> Layer<S> for Queries
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: layer::Context<'_, S>) {
+        // sqlx logs with the `log` crate, the real target is hidden in the fields
+        let metadata = event.normalized_metadata();
+        let metadata = metadata.as_ref().unwrap_or_else(|| event.metadata());
+        if metadata.target() != QUERY_TARGET {
+            return;
+        }
+        // Queries outside of commands (like those of jobs and health checks) are not traced
+        let Some(parent) = ctx.lookup_current() else {
+            return;
+        };
+
+        let mut message = Message::default();
+        event.record(&mut message);
+        let Some(query) = Query::parse(&message.0) else {
+            return;
+        };
+
+        let mut extensions = parent.extensions_mut();
+        let Some(data) = extensions.get_mut::<OtelData>() else {
+            return;
+        };
+        let parent_cx = self.tracer.sampled_context(data);
+
+        let end = SystemTime::now();
+        self.tracer
+            .span_builder(query.summary.to_string())
+            .with_kind(SpanKind::Client)
+            .with_start_time(end - query.elapsed)
+            .with_end_time(end)
+            .with_attributes(vec![
+                KeyValue::new("db.system", "postgresql"),
+                KeyValue::new("db.statement", query.statement.to_string()),
+                KeyValue::new("db.rows_affected", query.rows_affected),
+                KeyValue::new("db.rows_returned", query.rows_returned),
+            ])
+            .start_with_context(&self.tracer, &parent_cx);
+    }
+}
+
+#[derive(Default)]
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{value:?}");
+        }
+    }
+}
+
+/// A query as logged by sqlx:
+/// `<summary>; rows affected: 1, rows returned: 0, elapsed: 1.234ms` followed by the formatted
+/// statement if the summary is shortened
+#[derive(Debug, PartialEq)]
+struct Query<'a> {
+    summary: &'a str,
+    statement: &'a str,
+    rows_affected: i64,
+    rows_returned: i64,
+    elapsed: Duration,
+}
+
+impl<'a> Query<'a> {
+    fn parse(message: &'a str) -> Option<Self> {
+        let (summary, rest) = message.split_once("; rows affected: ")?;
+        let (rows_affected, rest) = rest.split_once(", rows returned: ")?;
+        let (rows_returned, rest) = rest.split_once(", elapsed: ")?;
+        let (elapsed, statement) = rest.split_once('\n').unwrap_or((rest, ""));
+
+        let statement = statement.trim();
+        Some(Self {
+            summary: summary.trim_end_matches(" …"),
+            statement: if statement.is_empty() {
+                summary
+            } else {
+                statement
+            },
+            rows_affected: rows_affected.parse().ok()?,
+            rows_returned: rows_returned.parse().ok()?,
+            elapsed: parse_elapsed(elapsed)?,
+        })
+    }
+}
+
+/// Parses the debug format of a [`Duration`], like `1.234ms`
+fn parse_elapsed(s: &str) -> Option<Duration> {
+    let unit = s.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let (number, unit) = s.split_at(unit);
+    let number: f64 = number.parse().ok()?;
+
+    let seconds = match unit {
+        "ns" => number / 1e9,
+        "µs" => number / 1e6,
+        "ms" => number / 1e3,
+        "s" => number,
+        _ => return None,
+    };
+    Some(Duration::from_secs_f64(seconds))
+}
+
+/// Drops the `self` attribute serenity gives its request spans, which is a dump of the whole
+/// HTTP client including every ratelimit it knows about
+#[derive(Debug)]
+struct Scrub<E>(E);
+
+impl<E: SpanExporter> SpanExporter for Scrub<E> {
+    fn export(&mut self, mut batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let key = Key::from_static_str("self");
+        for span in &mut batch {
+            if span.attributes.get(&key).is_none() {
+                continue;
+            }
+            let mut attributes = EvictedHashMap::new(u32::MAX, span.attributes.len());
+            for (k, v) in span.attributes.iter() {
+                if *k != key {
+                    attributes.insert(KeyValue::new(k.clone(), v.clone()));
+                }
+            }
+            span.attributes = attributes;
+        }
+        self.0.export(batch)
+    }
+
+    fn shutdown(&mut self) {
+        self.0.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use opentelemetry::trace::SpanId;
+    use tracing::info_span;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    /// Keeps every exported span in memory
+    #[derive(Clone, Debug, Default)]
+    struct TestExporter(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for TestExporter {
+        fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(std::future::ready(Ok(())))
+        }
+    }
+
+    #[test]
+    fn parses_query_logs() {
+        let short = "SELECT 1; rows affected: 0, rows returned: 1, elapsed: 512.000µs";
+        assert_eq!(
+            Query::parse(short),
+            Some(Query {
+                summary: "SELECT 1",
+                statement: "SELECT 1",
+                rows_affected: 0,
+                rows_returned: 1,
+                elapsed: Duration::from_micros(512),
+            })
+        );
+
+        let long = "UPDATE jobs SET status …; rows affected: 2, rows returned: 0, elapsed: 1.500s\n\nUPDATE\n  jobs\nSET\n  status = $1\n";
+        let query = Query::parse(long).unwrap();
+        assert_eq!(query.summary, "UPDATE jobs SET status");
+        assert_eq!(query.statement, "UPDATE\n  jobs\nSET\n  status = $1");
+        assert_eq!(query.rows_affected, 2);
+        assert_eq!(query.elapsed, Duration::from_millis(1500));
+
+        assert_eq!(Query::parse("Connected to the database"), None);
+    }
+
+    #[test]
+    fn traces_commands_with_queries_and_requests() {
+        let exporter = TestExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(Scrub(exporter.clone()))
+            .with_config(config())
+            .build();
+        let subscriber = tracing_subscriber::registry().with(layers(provider.tracer("test")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let command = info_span!(parent: None, "command", command = "ping", shard = 0);
+            let _entered = command.enter();
+
+            tracing::info!(
+                target: "sqlx::query",
+                "SELECT 1; rows affected: 0, rows returned: 1, elapsed: 1.000ms"
+            );
+            info_span!(target: "serenity::http::client", "request", "self" = "Http { .. }")
+                .in_scope(|| {});
+            // Not part of the trace
+            info_span!(target: "serenity::gateway", "heartbeat").in_scope(|| {});
+        });
+        // Waits for the exporter thread to be done
+        drop(provider);
+
+        let spans = exporter.0.lock().unwrap();
+        let names: Vec<_> = spans.iter().map(|x| x.name.as_ref()).collect();
+        assert_eq!(names, ["SELECT 1", "request", "command"]);
+
+        let command = &spans[2];
+        assert_eq!(command.parent_span_id, SpanId::INVALID);
+        assert_eq!(
+            command.resource.get(Key::new("service.name")),
+            Some(env!("CARGO_PKG_NAME").into())
+        );
+        for child in &spans[..2] {
+            assert_eq!(child.parent_span_id, command.span_context.span_id());
+            assert_eq!(
+                child.span_context.trace_id(),
+                command.span_context.trace_id()
+            );
+        }
+
+        let query = &spans[0];
+        assert_eq!(
+            query.attributes.get(&Key::new("db.statement")),
+            Some(&"SELECT 1".into())
+        );
+        assert_eq!(
+            query.end_time.duration_since(query.start_time).unwrap(),
+            Duration::from_millis(1)
+        );
+        assert_eq!(spans[1].attributes.get(&Key::new("self")), None);
+    }
+}
@@ -0,0 +1,367 @@
+//! Runs commands without Discord, for tests.
+//!
+//! A [`Harness`] has the same framework options and [`Data`] as the bot, with a database from
+//! `#[sqlx::test]`. Requests to Discord go to a [`FakeDiscord`] that records them, so the replies
+//! of a command can be looked at afterwards:
+//!
+//! ```ignore
+//! #[sqlx::test]
+//! async fn replies(db: PgPool) {
+//!     let harness = Harness::new(db).await;
+//!     let replies = harness.slash("pong", &[]).await;
+//!     assert_eq!(replies[0].content.as_deref(), Some("pong!"));
+//! }
+//! ```
+//!
+//! Component collectors (like the one of `/help`) end right away, as if nobody clicked anything
+//! before the timeout.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use axum::http::Method;
+use poise::serenity_prelude as serenity;
+use serde_json::{json, Value};
+use serenity::{
+    ApplicationCommandInteraction, AutocompleteInteraction, ChannelId, GatewayIntents, GuildId,
+    HttpBuilder, Message, Permissions, ShardManager, ShardMessenger, UserId,
+};
+use sqlx::PgPool;
+
+use crate::blocklist::Blocklist;
+use crate::config::DEFAULT_MAINTENANCE_MESSAGE;
+use crate::cooldowns::Cooldowns;
+use crate::maintenance::Maintenance;
+use crate::overrides::OverrideCache;
+use crate::prefixes::PrefixCache;
+use crate::{commands, Data, Error};
+
+pub use discord::{FakeDiscord, Request};
+
+pub mod discord;
+
+/// The only prefix of prefix commands
+pub const PREFIX: &str = "!";
+pub const BOT_ID: UserId = UserId(1000);
+/// The user that runs the commands
+pub const AUTHOR_ID: UserId = UserId(2000);
+pub const CHANNEL_ID: ChannelId = ChannelId(3000);
+/// A thread in [`CHANNEL_ID`], commands run in it after [`Harness::in_thread`]
+pub const THREAD_ID: ChannelId = ChannelId(3001);
+/// The guild commands run in after [`Harness::in_guild`]
+pub const GUILD_ID: GuildId = GuildId(4000);
+/// The owner of the application, which makes them an owner of the bot
+pub const OWNER_ID: UserId = UserId(5000);
+
+/// A message the command sent or edited, as a reply or on its own
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Reply {
+    pub content: Option<String>,
+    pub embeds: Vec<Value>,
+    pub components: Vec<Value>,
+    pub ephemeral: bool,
+    /// Whether this changed a message that was sent before
+    pub edit: bool,
+}
+
+impl Reply {
+    /// Turns a request into the message it sends, `None` if it doesn't send one
+    fn from_request(request: &Request) -> Option<Self> {
+        let segments = request.segments();
+        let (body, edit) = match (&request.method, segments.as_slice()) {
+            // The first response to an interaction, only some types of it come with a message
+            (&Method::POST, ["interactions", _, _, "callback"]) => {
+                let kind = request.body["type"].as_u64();
+                // 4 is a new message, 7 changes the message of a component
+                if kind != Some(4) && kind != Some(7) {
+                    return None;
+                }
+                (&request.body["data"], kind == Some(7))
+            }
+            (&Method::POST, ["channels", _, "messages"] | ["webhooks", _, _]) => {
+                (&request.body, false)
+            }
+            (
+                &Method::PATCH,
+                ["channels", _, "messages", _] | ["webhooks", _, _, "messages", _],
+            ) => (&request.body, true),
+            _ => return None,
+        };
+
+        let list = |key: &str| body[key].as_array().cloned().unwrap_or_default();
+        Some(Self {
+            content: body["content"].as_str().map(String::from),
+            embeds: list("embeds"),
+            components: list("components"),
+            ephemeral: body["flags"].as_u64().is_some_and(|x| x & 64 != 0),
+            edit,
+        })
+    }
+
+    /// The text of every button and select menu option, in order
+    pub fn labels(&self) -> Vec<String> {
+        fn collect(value: &Value, labels: &mut Vec<String>) {
+            if let Some(label) = value["label"].as_str() {
+                labels.push(label.to_string());
+            }
+            for key in ["components", "options"] {
+                for child in value[key].as_array().into_iter().flatten() {
+                    collect(child, labels);
+                }
+            }
+        }
+
+        let mut labels = Vec::new();
+        for row in &self.components {
+            collect(row, &mut labels);
+        }
+        labels
+    }
+}
+
+/// Runs commands like the bot would and keeps what they send
+pub struct Harness {
+    pub data: Data,
+    options: poise::FrameworkOptions<Data, Error>,
+    ctx: serenity::Context,
+    shard_manager: std::sync::Arc<tokio::sync::Mutex<ShardManager>>,
+    discord: FakeDiscord,
+    guild_id: Option<GuildId>,
+    channel_id: ChannelId,
+    /// What the author may do in the guild, slash commands are told
+    permissions: Permissions,
+    next_id: AtomicU64,
+}
+
+impl Harness {
+    /// Commands run in the DMs of [`AUTHOR_ID`], who is not an owner
+    pub async fn new(db: PgPool) -> Self {
+        let data = Data {
+            prefixes: PrefixCache::new(vec![PREFIX.to_string()]),
+            shutdown: Default::default(),
+            metrics: Default::default(),
+            blocklist: Blocklist::load(&db).await.unwrap(),
+            maintenance: Maintenance::load(&db, DEFAULT_MAINTENANCE_MESSAGE.to_string(), false)
+                .await
+                .unwrap(),
+            overrides: OverrideCache::default(),
+            cooldowns: Cooldowns::load(&db).await.unwrap(),
+            extensions: Default::default(),
+            event_handlers: Vec::new(),
+            db,
+        };
+
+        let mut commands = commands::all().unwrap();
+        commands::set_qualified_names(&mut commands);
+        let options = crate::framework_options(commands, HashSet::new());
+
+        let discord = FakeDiscord::start().await;
+        let http = HttpBuilder::new("token")
+            .application_id(BOT_ID.0)
+            .proxy(discord.url())
+            .unwrap()
+            .ratelimiter_disabled(true)
+            .build();
+        // Never started, only built for its cache and the shard manager commands look at
+        let client = serenity::ClientBuilder::new_with_http(http, GatewayIntents::empty())
+            .await
+            .unwrap();
+
+        // Without a receiver collectors stop waiting for interactions right away
+        let (shard_tx, _) = serenity::futures::channel::mpsc::unbounded();
+        let ctx = serenity::Context {
+            data: client.data.clone(),
+            shard: ShardMessenger::new(shard_tx),
+            shard_id: 0,
+            http: client.cache_and_http.http.clone(),
+            cache: client.cache_and_http.cache.clone(),
+        };
+        let shard_manager = client.shard_manager.clone();
+
+        Self {
+            data,
+            options,
+            ctx,
+            shard_manager,
+            discord,
+            guild_id: None,
+            channel_id: CHANNEL_ID,
+            permissions: Permissions::empty(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Commands run in [`GUILD_ID`] instead of DMs, the bot doesn't know anything about the guild
+    pub fn in_guild(mut self) -> Self {
+        self.guild_id = Some(GUILD_ID);
+        self
+    }
+
+    /// Commands run in [`THREAD_ID`] of [`GUILD_ID`]
+    pub fn in_thread(mut self) -> Self {
+        self.guild_id = Some(GUILD_ID);
+        self.channel_id = THREAD_ID;
+        self
+    }
+
+    /// The author of slash commands has `permissions` in the guild, they have none otherwise
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Commands are run by an owner of the bot
+    pub fn by_owner(mut self) -> Self {
+        self.options.owners.insert(AUTHOR_ID);
+        self
+    }
+
+    /// Runs the slash command with the qualified name `command`, like `prefix add`. Arguments are
+    /// strings, integers, numbers or booleans.
+    pub async fn slash(&self, command: &str, arguments: &[(&str, Value)]) -> Vec<Reply> {
+        let interaction: ApplicationCommandInteraction =
+            serde_json::from_value(self.interaction(command, arguments)).unwrap();
+
+        let has_sent_initial_response = AtomicBool::new(false);
+        let invocation_data = tokio::sync::Mutex::new(Box::new(()) as _);
+        let mut parent_commands = Vec::new();
+        self.capture(async {
+            let result = poise::dispatch_interaction(
+                self.framework(),
+                &self.ctx,
+                &interaction,
+                &has_sent_initial_response,
+                &invocation_data,
+                &mut parent_commands,
+            )
+            .await;
+            if let Err(error) = result {
+                error.handle(&self.options).await;
+            }
+        })
+        .await
+    }
+
+    /// The suggestions of the slash command `command` for the argument called `focused`, whose
+    /// value in `arguments` is what was typed so far
+    pub async fn autocomplete(
+        &self,
+        command: &str,
+        arguments: &[(&str, Value)],
+        focused: &str,
+    ) -> Vec<String> {
+        fn focus(options: &mut Value, focused: &str) {
+            for option in options.as_array_mut().into_iter().flatten() {
+                if option["name"] == focused && option["type"] != 1 {
+                    option["focused"] = json!(true);
+                }
+                if let Some(options) = option.get_mut("options") {
+                    focus(options, focused);
+                }
+            }
+        }
+
+        let mut interaction = self.interaction(command, arguments);
+        interaction["type"] = json!(4);
+        focus(&mut interaction["data"]["options"], focused);
+        let interaction: AutocompleteInteraction = serde_json::from_value(interaction).unwrap();
+
+        let before = self.discord.requests().len();
+        let has_sent_initial_response = AtomicBool::new(false);
+        let invocation_data = tokio::sync::Mutex::new(Box::new(()) as _);
+        let mut parent_commands = Vec::new();
+        let result = poise::dispatch_autocomplete(
+            self.framework(),
+            &self.ctx,
+            &interaction,
+            &has_sent_initial_response,
+            &invocation_data,
+            &mut parent_commands,
+        )
+        .await;
+        if let Err(error) = result {
+            error.handle(&self.options).await;
+        }
+
+        // 8 is the response with the suggestions
+        let requests = self.discord.requests();
+        let response = requests[before..]
+            .iter()
+            .find(|x| x.path.ends_with("/callback") && x.body["type"] == 8);
+        response
+            .and_then(|x| x.body["data"]["choices"].as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|x| x["name"].as_str().map(String::from))
+            .collect()
+    }
+
+    /// Sends a message with [`PREFIX`] in front of `text`, like `pong` or `prefix add ?`
+    pub async fn prefix(&self, text: &str) -> Vec<Reply> {
+        let content = format!("{PREFIX}{text}");
+        let msg = discord::message_with_author(
+            self.next_id(),
+            self.channel_id,
+            AUTHOR_ID,
+            self.guild_id,
+            &content,
+        );
+        let msg: Message = serde_json::from_value(msg).unwrap();
+
+        let invocation_data = tokio::sync::Mutex::new(Box::new(()) as _);
+        let mut parent_commands = Vec::new();
+        self.capture(async {
+            let result = poise::dispatch_message(
+                self.framework(),
+                &self.ctx,
+                &msg,
+                poise::MessageDispatchTrigger::MessageCreate,
+                &invocation_data,
+                &mut parent_commands,
+            )
+            .await;
+            if let Err(error) = result {
+                error.handle(&self.options).await;
+            }
+        })
+        .await
+    }
+
+    /// Every request made to discord so far
+    pub fn requests(&self) -> Vec<Request> {
+        self.discord.requests()
+    }
+
+    /// Runs `run` and returns the messages it sent
+    async fn capture(&self, run: impl std::future::Future<Output = ()>) -> Vec<Reply> {
+        let before = self.discord.requests().len();
+        run.await;
+        self.discord.requests()[before..]
+            .iter()
+            .filter_map(Reply::from_request)
+            .collect()
+    }
+
+    fn interaction(&self, command: &str, arguments: &[(&str, Value)]) -> Value {
+        let mut interaction =
+            discord::interaction(self.next_id(), AUTHOR_ID, self.guild_id, command, arguments);
+        interaction["channel_id"] = json!(self.channel_id.to_string());
+        if self.guild_id.is_some() {
+            interaction["member"]["permissions"] = json!(self.permissions.bits().to_string());
+        }
+        interaction
+    }
+
+    fn framework(&self) -> poise::FrameworkContext<'_, Data, Error> {
+        poise::FrameworkContext {
+            bot_id: BOT_ID,
+            options: &self.options,
+            user_data: &self.data,
+            shard_manager: &self.shard_manager,
+        }
+    }
+
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,516 @@
+//! A fake discord that the bot can talk to like the real one, see `DISCORD_API_URL`.
+//!
+//! It answers the requests the bot makes on startup and while running commands (the application
+//! info, slash command registration, messages and interaction responses) and runs a gateway the
+//! shards connect to. Tests send events through the gateway and look at the requests afterwards.
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_tungstenite::tungstenite::protocol::CloseFrame;
+use async_tungstenite::tungstenite::Message as WsMessage;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use poise::serenity_prelude as serenity;
+use serde_json::{json, Value};
+use serenity::futures::{SinkExt, StreamExt};
+use serenity::{ChannelId, GuildId, UserId};
+use tokio::sync::{mpsc, watch};
+
+use super::{BOT_ID, CHANNEL_ID, GUILD_ID, OWNER_ID, PREFIX, THREAD_ID};
+use crate::config::{self, Config};
+
+/// How long [`FakeDiscord::wait_for`] waits before failing the test
+const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Long enough that no heartbeat is due during a test
+const HEARTBEAT_INTERVAL: u64 = 45_000;
+
+/// A request the bot made to discord
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: Method,
+    /// Without the `/api/v10` in front, like `/channels/3000/messages`
+    pub path: String,
+    /// `Null` for requests without a JSON body
+    pub body: Value,
+}
+
+impl Request {
+    /// The parts of the path, `["channels", "3000", "messages"]` for the example above
+    pub fn segments(&self) -> Vec<&str> {
+        self.path.trim_matches('/').split('/').collect()
+    }
+}
+
+/// What a test sends to a shard
+enum Outgoing {
+    Event(String, Value),
+    Close(u16),
+}
+
+struct Shared {
+    requests: Mutex<Vec<Request>>,
+    /// The number of requests, so waiting for one doesn't miss it
+    received: watch::Sender<usize>,
+    /// Registered slash commands by the guild they are in, `None` for global ones
+    commands: Mutex<HashMap<Option<u64>, Value>>,
+    /// Every payload the shards sent over the gateway
+    gateway: Mutex<Vec<Value>>,
+    /// Shards that identified themselves, events are sent to all of them
+    sessions: Mutex<Vec<mpsc::UnboundedSender<Outgoing>>>,
+    gateway_url: String,
+    next_id: AtomicU64,
+}
+
+impl Shared {
+    fn next_id(&self) -> u64 {
+        1 + self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn record(&self, request: Request) {
+        self.requests.lock().unwrap().push(request);
+        self.received.send_modify(|count| *count += 1);
+    }
+}
+
+/// Runs on random ports on localhost until it is dropped
+pub struct FakeDiscord {
+    shared: Arc<Shared>,
+    url: String,
+    next_id: AtomicU64,
+}
+
+impl FakeDiscord {
+    pub async fn start() -> Self {
+        let gateway = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let shared = Arc::new(Shared {
+            requests: Mutex::default(),
+            received: watch::channel(0).0,
+            commands: Mutex::default(),
+            gateway: Mutex::default(),
+            sessions: Mutex::default(),
+            gateway_url: format!("ws://{}", gateway.local_addr().unwrap()),
+            next_id: AtomicU64::new(0),
+        });
+        tokio::spawn(accept(gateway, shared.clone()));
+
+        let app = axum::Router::new()
+            .fallback(handle)
+            .with_state(shared.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        Self {
+            shared,
+            url: format!("http://{address}"),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// What to set `DISCORD_API_URL` to
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// A configuration that runs the whole bot against this fake, with slash commands in
+    /// [`GUILD_ID`]
+    pub fn config(&self) -> Config {
+        Config {
+            discord_token: "token".to_string(),
+            discord_api_url: Some(self.url.clone()),
+            database_url: String::new(),
+            prefixes: vec![PREFIX.to_string()],
+            disable_no_dotenv_warning: true,
+            dev_guild_id: Some(GUILD_ID),
+            owners: Default::default(),
+            http_address: None,
+            log_format: Default::default(),
+            log_file: None,
+            otlp_endpoint: None,
+            record_events: None,
+            maintenance: false,
+            maintenance_message: config::DEFAULT_MAINTENANCE_MESSAGE.to_string(),
+        }
+    }
+
+    /// Every request the bot made so far
+    pub fn requests(&self) -> Vec<Request> {
+        self.shared.requests.lock().unwrap().clone()
+    }
+
+    /// Every payload the bot sent over the gateway so far, like its identify and presence updates
+    pub fn gateway_payloads(&self) -> Vec<Value> {
+        self.shared.gateway.lock().unwrap().clone()
+    }
+
+    /// Waits until the bot made a request that matches, panics after a while if it doesn't.
+    /// `what` describes the request in that panic.
+    pub async fn wait_for(&self, what: &str, matches: impl Fn(&Request) -> bool) -> Request {
+        let mut received = self.shared.received.subscribe();
+        let wait = async {
+            loop {
+                received.borrow_and_update();
+                if let Some(request) = self.requests().into_iter().find(|x| matches(x)) {
+                    return request;
+                }
+                received.changed().await.unwrap();
+            }
+        };
+        match tokio::time::timeout(WAIT_TIMEOUT, wait).await {
+            Ok(request) => request,
+            Err(_) => panic!("The bot never sent {what}, got {:#?}", self.requests()),
+        }
+    }
+
+    /// Sends an event to every shard that is connected
+    pub fn dispatch(&self, event: &str, data: Value) {
+        for session in self.shared.sessions.lock().unwrap().iter() {
+            let _ = session.send(Outgoing::Event(event.to_string(), data.clone()));
+        }
+    }
+
+    /// Closes the connection of every shard with the close `code`, `4004` tells them the token is
+    /// wrong so they give up instead of connecting again
+    pub fn close_gateway(&self, code: u16) {
+        for session in self.shared.sessions.lock().unwrap().drain(..) {
+            let _ = session.send(Outgoing::Close(code));
+        }
+    }
+
+    /// `author` sends `content` in [`CHANNEL_ID`], in DMs unless there is a guild
+    pub fn message_create(&self, author: UserId, guild_id: Option<GuildId>, content: &str) {
+        let message = message_with_author(self.next_id(), CHANNEL_ID, author, guild_id, content);
+        self.dispatch("MESSAGE_CREATE", message);
+    }
+
+    /// `user` uses a slash command, see [`interaction`]
+    pub fn interaction_create(
+        &self,
+        user: UserId,
+        guild_id: Option<GuildId>,
+        command: &str,
+        arguments: &[(&str, Value)],
+    ) {
+        let interaction = interaction(self.next_id(), user, guild_id, command, arguments);
+        self.dispatch("INTERACTION_CREATE", interaction);
+    }
+
+    fn next_id(&self) -> u64 {
+        // Far away from the ids of the messages the bot sends
+        1_000_000 + self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+async fn handle(
+    State(shared): State<Arc<Shared>>,
+    method: Method,
+    uri: Uri,
+    body: Bytes,
+) -> Response {
+    let path = uri.path().trim_start_matches("/api/v10").to_string();
+    let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    let request = Request { method, path, body };
+    let response = respond(&shared, &request);
+    shared.record(request);
+    response
+}
+
+fn respond(shared: &Shared, request: &Request) -> Response {
+    let segments = request.segments();
+    match (&request.method, segments.as_slice()) {
+        (&Method::GET, ["gateway"]) => Json(json!({ "url": shared.gateway_url })).into_response(),
+        (&Method::GET, ["gateway", "bot"]) => Json(json!({
+            "url": shared.gateway_url,
+            "shards": 1,
+            "session_start_limit": {
+                "total": 1000,
+                "remaining": 1000,
+                "reset_after": 0,
+                "max_concurrency": 1,
+            },
+        }))
+        .into_response(),
+        (&Method::GET, ["oauth2", "applications", "@me"]) => Json(json!({
+            "id": BOT_ID.to_string(),
+            "name": "bot",
+            "icon": null,
+            "description": "",
+            "bot_public": true,
+            "bot_require_code_grant": false,
+            "owner": user(OWNER_ID),
+            "team": null,
+        }))
+        .into_response(),
+        (&Method::GET, ["users", "@me"]) => Json(current_user()).into_response(),
+
+        (method, ["applications", _, "commands"]) => commands(shared, method, None, &request.body),
+        (method, ["applications", _, "guilds", guild_id, "commands"]) => {
+            let guild_id = guild_id.parse().ok();
+            commands(shared, method, guild_id, &request.body)
+        }
+
+        (&Method::POST, ["interactions", _, _, "callback"]) | (&Method::DELETE, _) => {
+            StatusCode::NO_CONTENT.into_response()
+        }
+        (&Method::GET, ["channels", channel_id]) => {
+            let channel_id = ChannelId(channel_id.parse().unwrap_or(CHANNEL_ID.0));
+            Json(channel(channel_id)).into_response()
+        }
+        (_, ["channels", channel_id, "messages", ..]) => {
+            let channel_id = ChannelId(channel_id.parse().unwrap_or(CHANNEL_ID.0));
+            Json(message(shared.next_id(), channel_id, &request.body)).into_response()
+        }
+        (_, ["webhooks", ..]) => {
+            Json(message(shared.next_id(), CHANNEL_ID, &request.body)).into_response()
+        }
+
+        _ => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "message": "Unknown", "code": 0 })),
+        )
+            .into_response(),
+    }
+}
+
+/// Lists or overwrites the slash commands of a scope
+fn commands(shared: &Shared, method: &Method, guild_id: Option<u64>, body: &Value) -> Response {
+    let mut registered = shared.commands.lock().unwrap();
+    if method == Method::PUT {
+        let commands = body
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|command| {
+                let mut command = command.clone();
+                command["id"] = json!(shared.next_id().to_string());
+                command["application_id"] = json!(BOT_ID.to_string());
+                command["version"] = json!("1");
+                // Left out for slash commands, which are the default
+                if command["type"].is_null() {
+                    command["type"] = json!(1);
+                }
+                if let Some(guild_id) = guild_id {
+                    command["guild_id"] = json!(guild_id.to_string());
+                }
+                command
+            })
+            .collect();
+        registered.insert(guild_id, Value::Array(commands));
+    }
+    let commands = registered.get(&guild_id).cloned().unwrap_or(json!([]));
+    Json(commands).into_response()
+}
+
+async fn accept(listener: tokio::net::TcpListener, shared: Arc<Shared>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(session(stream, shared.clone()));
+    }
+}
+
+/// One connection to the gateway: says hello, answers identifies and heartbeats and sends the
+/// events of the test
+async fn session(stream: tokio::net::TcpStream, shared: Arc<Shared>) {
+    let Ok(mut ws) = async_tungstenite::tokio::accept_async(stream).await else {
+        return;
+    };
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let mut sequence = 0;
+
+    let hello = json!({ "op": 10, "d": { "heartbeat_interval": HEARTBEAT_INTERVAL } });
+    if ws.send(WsMessage::Text(hello.to_string())).await.is_err() {
+        return;
+    }
+
+    loop {
+        let payload = tokio::select! {
+            message = ws.next() => match message {
+                Some(Ok(WsMessage::Text(text))) => match serde_json::from_str::<Value>(&text) {
+                    Ok(payload) => payload,
+                    Err(_) => continue,
+                },
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => continue,
+            },
+            Some(outgoing) = events.recv() => match outgoing {
+                Outgoing::Event(event, data) => {
+                    sequence += 1;
+                    let dispatch = json!({ "op": 0, "t": event, "s": sequence, "d": data });
+                    if ws.send(WsMessage::Text(dispatch.to_string())).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                Outgoing::Close(code) => {
+                    let frame = CloseFrame { code: code.into(), reason: "".into() };
+                    let _ = ws.send(WsMessage::Close(Some(frame))).await;
+                    return;
+                }
+            },
+        };
+        shared.gateway.lock().unwrap().push(payload.clone());
+
+        let reply = match payload["op"].as_u64() {
+            // Heartbeat
+            Some(1) => json!({ "op": 11 }),
+            // Identify, from now on the shard gets the events of the test
+            Some(2) => {
+                shared.sessions.lock().unwrap().push(events_tx.clone());
+                sequence += 1;
+                json!({ "op": 0, "t": "READY", "s": sequence, "d": ready(&payload) })
+            }
+            _ => continue,
+        };
+        if ws.send(WsMessage::Text(reply.to_string())).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn ready(identify: &Value) -> Value {
+    json!({
+        "v": 10,
+        "user": current_user(),
+        "guilds": [],
+        "session_id": "fake",
+        "shard": identify["d"]["shard"],
+        "application": { "id": BOT_ID.to_string(), "flags": 0 },
+    })
+}
+
+fn current_user() -> Value {
+    let mut user = user(BOT_ID);
+    user["mfa_enabled"] = json!(false);
+    user
+}
+
+pub fn user(id: UserId) -> Value {
+    json!({
+        "id": id.to_string(),
+        "username": if id == BOT_ID { "bot" } else { "user" },
+        "discriminator": "0001",
+        "avatar": null,
+        "bot": id == BOT_ID,
+    })
+}
+
+/// A text channel of [`GUILD_ID`], or a thread in [`CHANNEL_ID`] for [`THREAD_ID`]
+pub fn channel(id: ChannelId) -> Value {
+    let mut channel = json!({
+        "id": id.to_string(),
+        "guild_id": GUILD_ID.to_string(),
+        "type": 0,
+        "name": "general",
+    });
+    if id == THREAD_ID {
+        channel["type"] = json!(11);
+        channel["name"] = json!("thread");
+        channel["parent_id"] = json!(CHANNEL_ID.to_string());
+        channel["thread_metadata"] = json!({
+            "archived": false,
+            "auto_archive_duration": 60,
+            "archive_timestamp": "2023-01-01T00:00:00+00:00",
+            "locked": false,
+        });
+    }
+    channel
+}
+
+/// A message of the bot as discord sends it back, with the content of `body`
+pub fn message(id: u64, channel_id: ChannelId, body: &Value) -> Value {
+    let content = body["content"].as_str().unwrap_or_default();
+    message_with_author(id, channel_id, BOT_ID, None, content)
+}
+
+pub fn message_with_author(
+    id: u64,
+    channel_id: ChannelId,
+    author: UserId,
+    guild_id: Option<GuildId>,
+    content: &str,
+) -> Value {
+    let mut message = json!({
+        "id": id.to_string(),
+        "channel_id": channel_id.to_string(),
+        "author": user(author),
+        "content": content,
+        "timestamp": "2023-01-01T00:00:00+00:00",
+        "edited_timestamp": null,
+        "tts": false,
+        "mention_everyone": false,
+        "mentions": [],
+        "mention_roles": [],
+        "attachments": [],
+        "embeds": [],
+        "pinned": false,
+        "type": 0,
+    });
+    if let Some(guild_id) = guild_id {
+        message["guild_id"] = json!(guild_id.to_string());
+    }
+    message
+}
+
+/// A use of the slash command with the qualified name `command` (like `prefix add`) in
+/// [`CHANNEL_ID`]. Arguments are strings, integers, numbers or booleans.
+pub fn interaction(
+    id: u64,
+    user_id: UserId,
+    guild_id: Option<GuildId>,
+    command: &str,
+    arguments: &[(&str, Value)],
+) -> Value {
+    let mut options: Vec<Value> = arguments
+        .iter()
+        .map(|(name, value)| {
+            let kind = match value {
+                Value::Bool(_) => 5,
+                Value::Number(x) if x.is_f64() => 10,
+                Value::Number(_) => 4,
+                _ => 3,
+            };
+            json!({ "name": name, "type": kind, "value": value })
+        })
+        .collect();
+
+    // Subcommands are options of their parent
+    let mut names: Vec<_> = command.split_whitespace().collect();
+    let name = names.remove(0);
+    for subcommand in names.into_iter().rev() {
+        options = vec![json!({ "name": subcommand, "type": 1, "options": options })];
+    }
+
+    let mut interaction = json!({
+        "id": id.to_string(),
+        "application_id": BOT_ID.to_string(),
+        "type": 2,
+        "data": { "id": "1", "name": name, "type": 1, "options": options },
+        "channel_id": CHANNEL_ID.to_string(),
+        "user": user(user_id),
+        "token": format!("token-{id}"),
+        "version": 1,
+        "locale": "en-US",
+    });
+    if let Some(guild_id) = guild_id {
+        interaction["guild_id"] = json!(guild_id.to_string());
+        interaction["member"] = json!({
+            "user": user(user_id),
+            "roles": [],
+            "joined_at": "2023-01-01T00:00:00+00:00",
+            "deaf": false,
+            "mute": false,
+            "permissions": "0",
+        });
+    }
+    interaction
+}
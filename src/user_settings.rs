@@ -0,0 +1,31 @@
+use chrono_tz::Tz;
+use poise::serenity_prelude as serenity;
+use serenity::UserId;
+use sqlx::PgPool;
+
+use crate::Error;
+
+/// The time zone the user picked, UTC if they didn't
+pub async fn timezone(db: &PgPool, user_id: UserId) -> Result<Tz, Error> {
+    let timezone: Option<Option<String>> =
+        sqlx::query_scalar("SELECT timezone FROM user_settings WHERE user_id = $1")
+            .bind(user_id.0 as i64)
+            .fetch_optional(db)
+            .await?;
+    Ok(timezone
+        .flatten()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(Tz::UTC))
+}
+
+pub async fn set_timezone(db: &PgPool, user_id: UserId, timezone: Tz) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO user_settings (user_id, timezone) VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE SET timezone = excluded.timezone",
+    )
+    .bind(user_id.0 as i64)
+    .bind(timezone.name())
+    .execute(db)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,390 @@
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{
+    DateTime, Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+
+use crate::duration::HumanDuration;
+
+/// Times given without a time of day, like `tomorrow`, happen at this hour
+const DEFAULT_HOUR: u32 = 9;
+
+#[derive(Debug)]
+pub enum InvalidTime {
+    Unknown,
+    InPast,
+}
+
+impl fmt::Display for InvalidTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidTime::Unknown => f.write_str(
+                "expected a time like `in 2h30m`, `tomorrow 9am`, `next friday`, `21:30` or `2023-02-14 18:00`",
+            ),
+            InvalidTime::InPast => f.write_str("that time is in the past"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidTime {}
+
+/// Parses a point in time written like people write them, relative to `now` and in the time zone
+/// of the user.
+///
+/// Understands durations (`in 2h30m`), days (`today`, `tomorrow`, `friday`, `next friday` which is
+/// a week after `friday`, `2023-02-14`) and times of day (`9am`, `9:30 pm`, `21:30`, `noon`), on
+/// their own or combined, as well as RFC 3339 timestamps and Discord timestamps
+/// (`<t:1676397600:f>`).
+pub fn parse(input: &str, now: DateTime<Utc>, tz: Tz) -> Result<DateTime<Utc>, InvalidTime> {
+    let input = input.trim().to_lowercase();
+
+    let time = match absolute(&input) {
+        Some(time) => time,
+        None => relative(&input, now, tz).ok_or(InvalidTime::Unknown)?,
+    };
+
+    if time <= now {
+        return Err(InvalidTime::InPast);
+    }
+    Ok(time)
+}
+
+/// Times that don't depend on when or where they are read
+fn absolute(input: &str) -> Option<DateTime<Utc>> {
+    if let Some(timestamp) = input.strip_prefix("<t:").and_then(|x| x.strip_suffix('>')) {
+        let seconds = timestamp.split(':').next()?.parse().ok()?;
+        return DateTime::from_timestamp(seconds, 0);
+    }
+
+    DateTime::parse_from_rfc3339(input)
+        .ok()
+        .map(|x| x.with_timezone(&Utc))
+}
+
+fn relative(input: &str, now: DateTime<Utc>, tz: Tz) -> Option<DateTime<Utc>> {
+    let input = input.strip_prefix("in ").unwrap_or(input);
+    if let Ok(duration) = input.parse::<HumanDuration>() {
+        return now.checked_add_signed(chrono::Duration::from_std(duration.0).ok()?);
+    }
+
+    let today = now.with_timezone(&tz).date_naive();
+    let words: Vec<&str> = input
+        .split_whitespace()
+        // `2023-02-14t18:00` is a date and a time
+        .flat_map(|word| match word.split_once('t') {
+            Some((date, time)) if date.contains('-') => vec![date, time],
+            _ => vec![word],
+        })
+        .filter(|x| !matches!(*x, "at" | "on"))
+        .collect();
+
+    let mut date = None;
+    let mut time = None;
+    let mut next = false;
+    let mut words = words.iter().peekable();
+
+    while let Some(word) = words.next() {
+        if *word == "next" {
+            next = true;
+            continue;
+        }
+
+        // `9 am` is written as two words
+        let word = match words.peek() {
+            Some(suffix) if matches!(**suffix, "am" | "pm") => {
+                let joined = format!("{word}{}", words.next().unwrap());
+                time = Some(time_of_day(&joined)?);
+                continue;
+            }
+            _ => *word,
+        };
+
+        if let Some(day) = day(word, today, next) {
+            if date.replace(day).is_some() {
+                return None;
+            }
+        } else if let Some(parsed) = time_of_day(word) {
+            if time.replace(parsed).is_some() {
+                return None;
+            }
+        } else {
+            return None;
+        }
+        next = false;
+    }
+
+    // A lone `next` doesn't mean anything
+    if next {
+        return None;
+    }
+
+    let resolve = |date: NaiveDate, time: NaiveTime| local_to_utc(tz, date.and_time(time));
+    match (date, time) {
+        (Some(date), time) => resolve(date, time.unwrap_or(default_time())),
+        // A time of day that already passed today means tomorrow
+        (None, Some(time)) => {
+            let at = resolve(today, time)?;
+            if at > now {
+                Some(at)
+            } else {
+                resolve(today.checked_add_days(Days::new(1))?, time)
+            }
+        }
+        (None, None) => None,
+    }
+}
+
+fn default_time() -> NaiveTime {
+    NaiveTime::from_hms_opt(DEFAULT_HOUR, 0, 0).unwrap()
+}
+
+fn day(word: &str, today: NaiveDate, next: bool) -> Option<NaiveDate> {
+    match word {
+        "today" if !next => return Some(today),
+        "tomorrow" if !next => return today.checked_add_days(Days::new(1)),
+        _ => {}
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(word, "%Y-%m-%d") {
+        return (!next).then_some(date);
+    }
+
+    // `friday` is the first friday after today, `next friday` the one a week after that
+    let weekday: Weekday = word.parse().ok()?;
+    let ahead = (weekday.num_days_from_monday() + 7 - today.weekday().num_days_from_monday()) % 7;
+    let ahead = if ahead == 0 { 7 } else { ahead };
+    let ahead = if next { ahead + 7 } else { ahead };
+    today.checked_add_days(Days::new(ahead.into()))
+}
+
+fn time_of_day(word: &str) -> Option<NaiveTime> {
+    match word {
+        "noon" => return NaiveTime::from_hms_opt(12, 0, 0),
+        "midnight" => return NaiveTime::from_hms_opt(0, 0, 0),
+        _ => {}
+    }
+
+    let (clock, offset) = if let Some(clock) = word.strip_suffix("am") {
+        (clock, Some(0))
+    } else if let Some(clock) = word.strip_suffix("pm") {
+        (clock, Some(12))
+    } else {
+        (word, None)
+    };
+
+    let (hour, minute) = match clock.split_once(':') {
+        Some((hour, minute)) if minute.len() == 2 => (hour.parse().ok()?, minute.parse().ok()?),
+        Some(_) => return None,
+        // A plain number is only a time with am or pm after it
+        None if offset.is_some() => (clock.parse().ok()?, 0),
+        None => return None,
+    };
+
+    let hour: u32 = match offset {
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some(offset) => hour % 12 + offset,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+/// Converts a local time to UTC, moving times that don't exist because of daylight saving time
+/// forward by an hour
+fn local_to_utc(tz: Tz, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+    tz.from_local_datetime(&local)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(local + chrono::Duration::hours(1)))
+                .earliest()
+        })
+        .map(|x| x.with_timezone(&Utc))
+}
+
+/// The first time after `now` that a reminder repeating `every` so often comes up again.
+///
+/// Whole days are counted in the time zone of the user and always land on `time_of_day`, so a
+/// daily reminder at 9am stays at 9am when daylight saving time starts or ends, and one that was
+/// moved out of the hour that doesn't exist goes back to its time the day after.
+pub fn next_occurrence(
+    previous: DateTime<Utc>,
+    every: Duration,
+    time_of_day: NaiveTime,
+    now: DateTime<Utc>,
+    tz: Tz,
+) -> Option<DateTime<Utc>> {
+    const DAY: u64 = 60 * 60 * 24;
+
+    let mut next = previous;
+    let mut date = previous.with_timezone(&tz).date_naive();
+    // Skips the ones that were missed while the bot was offline
+    while next <= now {
+        next = if every.as_secs().is_multiple_of(DAY) {
+            date = date.checked_add_days(Days::new(every.as_secs() / DAY))?;
+            local_to_utc(tz, date.and_time(time_of_day))?
+        } else {
+            next.checked_add_signed(chrono::Duration::from_std(every).ok()?)?
+        };
+    }
+    Some(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Europe::Berlin;
+
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn parses_times() {
+        // A friday, 13:00 in Berlin which is UTC+1 until daylight saving time starts on the 26th
+        let now = utc("2023-03-10T12:00:00Z");
+        let cases = [
+            ("in 2h30m", "2023-03-10T14:30:00Z"),
+            ("2h", "2023-03-10T14:00:00Z"),
+            ("tomorrow", "2023-03-11T08:00:00Z"),
+            ("tomorrow 9pm", "2023-03-11T20:00:00Z"),
+            ("tomorrow at 9 pm", "2023-03-11T20:00:00Z"),
+            ("21:30", "2023-03-10T20:30:00Z"),
+            // Already passed today
+            ("9am", "2023-03-11T08:00:00Z"),
+            ("noon", "2023-03-11T11:00:00Z"),
+            ("12am", "2023-03-10T23:00:00Z"),
+            ("monday", "2023-03-13T08:00:00Z"),
+            ("next monday", "2023-03-20T08:00:00Z"),
+            // Today is a friday
+            ("friday", "2023-03-17T08:00:00Z"),
+            ("next friday 18:00", "2023-03-24T17:00:00Z"),
+            ("2023-03-14 18:00", "2023-03-14T17:00:00Z"),
+            ("on 2023-03-14 at 6:00 pm", "2023-03-14T17:00:00Z"),
+            ("2023-03-14t18:00", "2023-03-14T17:00:00Z"),
+            ("2023-03-14T18:00:00Z", "2023-03-14T18:00:00Z"),
+            ("<t:1678708800:f>", "2023-03-13T12:00:00Z"),
+            // After daylight saving time started
+            ("2023-03-26 09:00", "2023-03-26T07:00:00Z"),
+            // 02:30 doesn't exist that night, so it is an hour later
+            ("2023-03-26 02:30", "2023-03-26T01:30:00Z"),
+            // 02:30 happens twice when it ends, the first one is used
+            ("2023-10-29 02:30", "2023-10-29T00:30:00Z"),
+        ];
+        for (input, expected) in cases {
+            let parsed = parse(input, now, Berlin).unwrap_or_else(|err| panic!("{input}: {err}"));
+            assert_eq!(parsed, utc(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        let now = utc("2023-03-10T12:00:00Z");
+        let unknown = [
+            "",
+            "yesterday",
+            "next",
+            "next tomorrow",
+            "friday monday",
+            "9am 10am",
+            "13pm",
+            "0am",
+            "25:00",
+            "9:5",
+            "10",
+            "in",
+            "next 2023-03-14",
+        ];
+        for input in unknown {
+            assert!(
+                matches!(parse(input, now, Berlin), Err(InvalidTime::Unknown)),
+                "{input}"
+            );
+        }
+        for input in ["2023-01-01", "2023-03-10T11:00:00Z", "<t:1000:f>"] {
+            assert!(
+                matches!(parse(input, now, Berlin), Err(InvalidTime::InPast)),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn repeats_across_daylight_saving_time() {
+        let day = Duration::from_secs(60 * 60 * 24);
+        let hour = Duration::from_secs(60 * 60);
+        let cases = [
+            // 9am stays 9am when daylight saving time starts and ends
+            (
+                "2023-03-25T08:00:00Z",
+                day,
+                "2023-03-25T12:00:00Z",
+                "2023-03-26T07:00:00Z",
+            ),
+            (
+                "2023-10-28T07:00:00Z",
+                day,
+                "2023-10-28T12:00:00Z",
+                "2023-10-29T08:00:00Z",
+            ),
+            (
+                "2023-03-25T08:00:00Z",
+                day * 7,
+                "2023-03-25T12:00:00Z",
+                "2023-04-01T07:00:00Z",
+            ),
+            // Shorter intervals are exact
+            (
+                "2023-03-26T00:30:00Z",
+                hour,
+                "2023-03-26T00:45:00Z",
+                "2023-03-26T01:30:00Z",
+            ),
+            // Skips the ones that were missed
+            (
+                "2023-03-20T08:00:00Z",
+                day,
+                "2023-03-25T12:00:00Z",
+                "2023-03-26T07:00:00Z",
+            ),
+            (
+                "2023-03-10T10:00:00Z",
+                hour * 3,
+                "2023-03-10T16:30:00Z",
+                "2023-03-10T19:00:00Z",
+            ),
+        ];
+        for (previous, every, now, expected) in cases {
+            let time_of_day = utc(previous).with_timezone(&Berlin).time();
+            let next = next_occurrence(utc(previous), every, time_of_day, utc(now), Berlin);
+            assert_eq!(next, Some(utc(expected)), "{previous} every {every:?}");
+        }
+    }
+
+    #[test]
+    fn keeps_the_time_of_day_after_the_gap() {
+        let day = Duration::from_secs(60 * 60 * 24);
+        let time_of_day = NaiveTime::from_hms_opt(2, 30, 0).unwrap();
+
+        // 02:30 on the 25th, then 03:30 because 02:30 doesn't exist on the 26th
+        let gap = next_occurrence(
+            utc("2023-03-25T01:30:00Z"),
+            day,
+            time_of_day,
+            utc("2023-03-25T12:00:00Z"),
+            Berlin,
+        );
+        assert_eq!(gap, Some(utc("2023-03-26T01:30:00Z")));
+
+        // And back to 02:30, which is 00:30 UTC now
+        let after = next_occurrence(
+            gap.unwrap(),
+            day,
+            time_of_day,
+            utc("2023-03-26T12:00:00Z"),
+            Berlin,
+        );
+        assert_eq!(after, Some(utc("2023-03-27T00:30:00Z")));
+    }
+}